@@ -1,19 +1,43 @@
 use chrono::{DateTime, Local};
-use eframe::egui::{self, vec2, Color32, FontId, Hyperlink, RichText};
+use eframe::egui::{
+    self,
+    text::{CCursor, CCursorRange, LayoutJob, TextFormat},
+    text_edit::TextEditOutput,
+    vec2, Color32, FontId, Galley, Hyperlink, RichText,
+};
 use egui_dock::{DockArea, DockState, NodeIndex, Style};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    assembler::assemble,
+    assembler::{assemble_for, Program},
+    cfg::{ControlFlowGraph, Edge, EdgeKind},
     colors::MfColors,
+    disassembler::disassemble,
+    encoding::{form_syntax, Encoding, OperandKind, INSTRUCTION_FORMS},
+    format::format_source,
+    highlight::{highlight_line, Highlight},
+    history::History,
     icons::material_design_icons::{
-        MDI_ALERT, MDI_CLOCK_FAST, MDI_CLOSE_OCTAGON, MDI_CONTENT_COPY, MDI_EXPORT,
-        MDI_HELP_CIRCLE_OUTLINE, MDI_IMPORT, MDI_OCTAGON, MDI_PACKAGE_VARIANT_CLOSED_REMOVE,
-        MDI_PLAY, MDI_RESTORE, MDI_STEP_FORWARD, MDI_STOP,
+        MDI_ALERT, MDI_CHECK, MDI_CHECK_CIRCLE, MDI_CHIP, MDI_CLOCK_FAST, MDI_CLOSE,
+        MDI_CLOSE_CIRCLE, MDI_CLOSE_OCTAGON, MDI_CODE_BRACES, MDI_CONTENT_COPY, MDI_DELETE,
+        MDI_DELETE_SWEEP, MDI_EXPORT, MDI_FILE_CODE, MDI_HELP_CIRCLE_OUTLINE, MDI_IMPORT,
+        MDI_MINUS, MDI_OCTAGON, MDI_PACKAGE_VARIANT_CLOSED_REMOVE, MDI_PAUSE, MDI_PLAY, MDI_PLUS,
+        MDI_RESTORE, MDI_SKIP_NEXT, MDI_SKIP_PREVIOUS, MDI_STEP_BACKWARD, MDI_STEP_FORWARD,
+        MDI_STOP,
     },
-    init, AssemblerError, ExecutionInfo, ExecutionState, MemoryData, Opcode, Operand, Register,
+    init,
+    instruction_set::{example, form_rtl, opcode_of, InstructionSet, MNEMONICS},
+    lint::lint,
+    number_format::NumberFormat,
+    test_case::{TestCase, TestResult},
+    trace::{format_instruction, Trace, TraceColumn},
+    worksheet::{Worksheet, WorksheetMarks},
+    Diagnostic, ExecutionInfo, ExecutionState, Machine, MemoryData, Opcode, Span, StepOutcome,
 };
 
+/// Width of the Source Editor's left margin, where memory addresses are shown.
+const SOURCE_GUTTER_WIDTH: f32 = 36.0;
+
 const DEFAULT_PROGRAM: &str = "loop:
     LDX string
     OUT
@@ -27,45 +51,105 @@ const DEFAULT_PROGRAM: &str = "loop:
 
 count:  #12
 
-string:
-        &48
-        &65
-        &6c
-        &6c
-        &6f
-        &2c
-        &20
-        &77
-        &6f
-        &72
-        &6c
-        &64
+string: \"Hello, world\"
 ";
 
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum BreakpointLocation {
+    /// A 1-based source line, resolved to an address through the loaded program.
+    Line(usize),
+    Address(u16),
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Breakpoint {
+    location: BreakpointLocation,
+    enabled: bool,
+}
+
+/// Words offered to complete the one being typed in the Source Editor.
+struct Completion {
+    /// Char index of the start of the word.
+    word_start: usize,
+    /// Char index of the cursor, at the end of the word.
+    cursor: usize,
+    /// Each word, with a hint on what it is.
+    candidates: Vec<(String, String)>,
+    selected: usize,
+}
+
 #[derive(Serialize, Deserialize)]
 struct AppContext {
     source_code: String,
-    memory: [[MemoryData; 16]; 16],
+    #[serde(flatten)]
+    machine: Machine,
     program_load_location: u16,
-    pc: u16,
-    cir: (Opcode, Operand),
-    ix: u16,
-    mdr: MemoryData,
-    mar: u16,
-    acc: u16,
-    carry: bool,
-    zero: bool,
-    overflow: bool,
-    sign: bool,
-    output: String,
     input: String,
     execution_state: ExecutionState,
     highlight_pc_location: bool,
     pc_highlight_color: [u8; 3],
-    assembler_error: Option<AssemblerError>,
+    #[serde(default)]
+    assembler_diagnostics: Vec<Diagnostic>,
+    /// The source code the diagnostics were reported for, so they are only shown in the editor
+    /// until it is edited.
+    #[serde(skip)]
+    diagnosed_source: String,
     show_assembler_error_window: bool,
+    #[serde(skip)]
+    jump_to_span: Option<Span>,
+    #[serde(default)]
+    program: Option<Program>,
+    #[serde(default)]
+    assembled_source: String,
+    #[serde(skip)]
+    last_scrolled_pc: Option<u16>,
+    #[serde(default)]
+    breakpoints: Vec<Breakpoint>,
+    /// Address of a breakpoint that execution has been resumed from, so it does not pause again.
+    #[serde(skip)]
+    breakpoint_skipped: Option<u16>,
+    #[serde(skip)]
+    history: History,
+    #[serde(skip)]
+    trace: Trace,
+    #[serde(default = "TraceColumn::defaults")]
+    trace_columns: Vec<TraceColumn>,
+    /// Address typed into the Trace Table panel for a new memory column.
+    #[serde(skip)]
+    trace_new_address: u16,
+    #[serde(default)]
+    worksheet: Worksheet,
+    #[serde(skip)]
+    worksheet_marks: Option<WorksheetMarks>,
+    /// The trace the worksheet is checked against, with the input it was run with. Cleared when
+    /// the program changes.
+    #[serde(skip)]
+    worksheet_trace: Option<(String, Trace)>,
+    #[serde(default)]
+    test_cases: Vec<TestCase>,
+    /// The machine code layout programs are encoded with, or `None` to keep instructions symbolic.
+    /// Renamed so that it does not clash with the flattened `Machine::encoding`.
+    #[serde(default, rename = "selected_encoding")]
+    encoding: Option<Encoding>,
+    /// Renamed so that it does not clash with the flattened `Machine::instruction_set`.
+    #[serde(default, rename = "selected_instruction_set")]
+    instruction_set: InstructionSet,
+    #[serde(skip)]
+    completion: Option<Completion>,
+    /// Results of the last test run, indexed like `test_cases`.
+    #[serde(skip)]
+    test_results: Vec<TestResult>,
+    /// Why the last test run could not be done.
+    #[serde(skip)]
+    test_error: Option<String>,
     show_assembler_info_window: bool,
-    value_as_hex: bool,
+    #[serde(default)]
+    number_format: NumberFormat,
+    #[serde(default)]
+    show_ascii_column: bool,
+    /// Memory cell picked in the Symbols panel.
+    #[serde(skip)]
+    highlighted_address: Option<u16>,
 
     ins_executed: u64,
 
@@ -87,6 +171,13 @@ impl egui_dock::TabViewer for AppContext {
             "Console" => self.console(ui),
             "Registers" => self.registers(ui),
             "Memory" => self.memory(ui),
+            "Breakpoints" => self.breakpoints(ui),
+            "Symbols" => self.symbols(ui),
+            "Control Flow" => self.control_flow(ui),
+            "Timeline" => self.timeline(ui),
+            "Trace Table" => self.trace_table(ui),
+            "Worksheet" => self.worksheet(ui),
+            "Tests" => self.tests(ui),
             _ => {
                 ui.label("There is nothing here...\nYou see this because of a bug. Please report this to Micfong.");
             }
@@ -99,63 +190,414 @@ impl AppContext {
         ui.horizontal(|ui| {
                     ui.label("Load program to");
                     ui.add(
-                        if self.value_as_hex {
-                            egui::DragValue::new(&mut self.program_load_location)
-                                .speed(1.0)
-                                .range(0..=255)
-                                .hexadecimal(2, false, true)
-                        } else {
-                            egui::DragValue::new(&mut self.program_load_location).speed(1.0).range(0..=255)
-                        }
+                        address_drag(&mut self.program_load_location, self.number_format)
                     );
                     ui.colored_label(MfColors::GRAY_700, MDI_HELP_CIRCLE_OUTLINE)
-                        .on_hover_text("Program needs to be loaded in the memory before execution. This is the memory address where the first line of your compiled program will be loaded. Usually, this is kept consistent with where the program is executed from.");
+                        .on_hover_text("Program needs to be loaded in the memory before execution. This is the memory address where the first line of your compiled program will be loaded. Usually, this is kept consistent with where the program is executed from, or, if the program starts with ORG, with the address ORG continues at. Loading does not change the PC.");
                     ui.separator();
                     if ui.button("Assemble and load").clicked() {
-                        match assemble(&self.source_code) {
+                        match self.assemble_source() {
                             Ok(program) => {
-                                if program.len() > 256 - self.program_load_location as usize {
-                                    self.assembler_error = Some(AssemblerError::ProgramTooLong {
-                                        program_size: program.len(),
-                                        memory_available: 256 - self.program_load_location as usize,
-                                    });
-                                    self.show_assembler_error_window = true;
-                                } else {
-                                    for (i, &instruction) in program.iter().enumerate() {
-                                        self.memory[(self.program_load_location as usize + i) / 16][(self.program_load_location as usize + i) % 16] =
-                                            match instruction.0 {
-                                                Opcode::Data(v) => {
-                                                    MemoryData::Value(v)
-                                                }
-                                                _ => {
-                                                    if let Operand::Address(v) = instruction.1 {
-                                                        MemoryData::Instruction(instruction.0, Operand::Address(v + self.program_load_location))
-                                                    } else {
-                                                        MemoryData::Instruction(instruction.0, instruction.1)
-                                                    }
-                                                }
-                                            }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                self.assembler_error = Some(e);
-                                self.show_assembler_error_window = true;
+                                self.machine.load_program(&program);
+                                self.history.clear();
+                                self.trace = Trace::default();
+                                self.assembler_diagnostics = program.warnings.clone();
+                                self.program = Some(program);
+                                self.worksheet_trace = None;
+                                self.assembled_source = self.source_code.clone();
                             }
+                            Err(diagnostics) => self.assembler_diagnostics = diagnostics,
                         }
+                        self.diagnosed_source = self.source_code.clone();
+                        self.show_assembler_error_window = !self.assembler_diagnostics.is_empty();
                     };
+                    if ui
+                        .button("Format source")
+                        .on_hover_text("Align labels, mnemonics, operands and comments into columns. The program assembles to the same memory.")
+                        .clicked()
+                    {
+                        self.source_code = format_source(&self.source_code);
+                    }
+                    ui.menu_button(MDI_FILE_CODE.to_owned() + " Load memory into editor", |ui| {
+                        ui.label("Replace the source code with a disassembly of the memory, starting from the load address?");
+                        if ui.button("Replace source code").clicked() {
+                            self.source_code = disassemble(
+                                self.machine.memory.as_flattened(),
+                                self.program_load_location,
+                                self.machine.encoding.as_ref(),
+                                self.number_format,
+                            );
+                            // The disassembly assembles to what is already in memory
+                            if let Ok(program) = self.assemble_source() {
+                                self.program = Some(program);
+                                self.worksheet_trace = None;
+                                self.assembled_source = self.source_code.clone();
+                            }
+                            ui.close_menu();
+                        }
+                    });
                 });
-        ui.add(
-            egui::TextEdit::multiline(&mut self.source_code)
-                .code_editor()
-                .desired_rows(10)
-                .desired_width(f32::INFINITY),
-        );
+        let (diagnosed_source, assembler_diagnostics) =
+            (&self.diagnosed_source, &self.assembler_diagnostics);
+        let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+            let diagnostics: &[Diagnostic] = if text == diagnosed_source {
+                assembler_diagnostics
+            } else {
+                &[]
+            };
+            let mut job = source_layout_job(ui, text, diagnostics);
+            job.wrap.max_width = wrap_width;
+            ui.fonts(|f| f.layout_job(job))
+        };
+        let editor_id = ui.make_persistent_id("source_editor");
+        let mut accepted = None;
+        if let Some(completion) = self
+            .completion
+            .as_mut()
+            .filter(|_| ui.memory(|m| m.has_focus(editor_id)))
+        {
+            let len = completion.candidates.len();
+            ui.input_mut(|i| {
+                if i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown) {
+                    completion.selected = (completion.selected + 1) % len;
+                }
+                if i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp) {
+                    completion.selected = (completion.selected + len - 1) % len;
+                }
+                if i.consume_key(egui::Modifiers::NONE, egui::Key::Tab)
+                    || i.consume_key(egui::Modifiers::NONE, egui::Key::Enter)
+                {
+                    accepted = Some(completion.selected);
+                }
+            });
+            if ui.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Escape)) {
+                self.completion = None;
+            }
+        }
+        let mut output = egui::TextEdit::multiline(&mut self.source_code)
+            .id(editor_id)
+            .code_editor()
+            .layouter(&mut layouter)
+            .desired_rows(10)
+            .desired_width(f32::INFINITY)
+            .margin(egui::Margin {
+                left: SOURCE_GUTTER_WIDTH,
+                right: 4.0,
+                top: 2.0,
+                bottom: 2.0,
+            })
+            .show(ui);
+        let line_rects = line_rects(&output.galley, output.galley_pos);
+        // The gutter and PC highlight only make sense while the source matches what is loaded
+        let program = self
+            .program
+            .as_ref()
+            .filter(|_| self.assembled_source == self.source_code);
+        let diagnostics: &[Diagnostic] = if self.diagnosed_source == self.source_code {
+            &self.assembler_diagnostics
+        } else {
+            &[]
+        };
+        if let Some(pointer) = output.response.hover_pos() {
+            let cursor = output.galley.cursor_from_pos(pointer - output.galley_pos);
+            let (line, column) = (cursor.pcursor.paragraph + 1, cursor.pcursor.offset);
+            let messages: Vec<String> = diagnostics
+                .iter()
+                .filter(|d| {
+                    let span = d.span();
+                    span.line == line
+                        && span.start < span.end
+                        && (span.start..=span.end).contains(&column)
+                })
+                .map(ToString::to_string)
+                .collect();
+            let opcode = self.source_code.split('\n').nth(line - 1).and_then(|text| {
+                let index = byte_index(text, column);
+                highlight_line(text)
+                    .into_iter()
+                    .find(|(range, highlight)| {
+                        *highlight == Highlight::Mnemonic
+                            && (range.start..=range.end).contains(&index)
+                    })
+                    .and_then(|(range, _)| opcode_of(&text[range]))
+            });
+            if !messages.is_empty() || opcode.is_some() {
+                output.response.clone().on_hover_ui_at_pointer(|ui| {
+                    for message in &messages {
+                        ui.label(message);
+                    }
+                    if let Some(opcode) = opcode {
+                        if !messages.is_empty() {
+                            ui.separator();
+                        }
+                        self.opcode_documentation(ui, opcode);
+                    }
+                });
+            }
+        }
+        let painter = ui.painter_at(output.response.rect);
+        let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+        for (line_index, rect) in line_rects.iter().enumerate() {
+            let line = line_index + 1;
+            let mut line_diagnostics = diagnostics.iter().filter(|d| d.span().line == line);
+            if let Some(diagnostic) = line_diagnostics.next() {
+                let (icon, color) = if diagnostic.is_error() {
+                    (MDI_CLOSE_CIRCLE, MfColors::RED_400)
+                } else {
+                    (MDI_ALERT, MfColors::YELLOW_400)
+                };
+                let mut message = format!("{} {}", icon, diagnostic);
+                let more = line_diagnostics.count();
+                if more > 0 {
+                    message += &format!(" (+{} more)", more);
+                }
+                painter.text(
+                    egui::pos2(rect.right() + 24.0, rect.top()),
+                    egui::Align2::LEFT_TOP,
+                    message,
+                    font_id.clone(),
+                    color,
+                );
+            }
+            let address = program.and_then(|program| program.address_of_line(line));
+            if let Some(breakpoint) = self.breakpoints.iter().find(|b| {
+                b.location == BreakpointLocation::Line(line)
+                    || address.is_some_and(|a| b.location == BreakpointLocation::Address(a))
+            }) {
+                let center = egui::pos2(output.response.rect.left() + 8.0, rect.center().y);
+                if breakpoint.enabled {
+                    painter.circle_filled(center, 4.0, MfColors::RED_500);
+                } else {
+                    painter.circle_stroke(center, 3.5, (1.0, MfColors::RED_500));
+                }
+            }
+            if let Some(address) = address {
+                painter.text(
+                    egui::pos2(output.response.rect.left() + 14.0, rect.top()),
+                    egui::Align2::LEFT_TOP,
+                    format!("{:02X}", address),
+                    font_id.clone(),
+                    MfColors::GRAY_600,
+                );
+            }
+        }
+        let gutter = ui
+            .interact(
+                egui::Rect::from_min_size(
+                    output.response.rect.left_top(),
+                    vec2(SOURCE_GUTTER_WIDTH, output.response.rect.height()),
+                ),
+                output.response.id.with("gutter"),
+                egui::Sense::click(),
+            )
+            .on_hover_cursor(egui::CursorIcon::PointingHand)
+            .on_hover_text("Click to toggle a breakpoint on this line.");
+        // Once assembled, only lines that produced a word can be stopped at
+        let clicked_line = gutter
+            .interact_pointer_pos()
+            .filter(|_| gutter.clicked())
+            .and_then(|pointer| {
+                line_rects
+                    .iter()
+                    .position(|rect| rect.y_range().contains(pointer.y))
+            })
+            .map(|line_index| line_index + 1)
+            .filter(|&line| {
+                program.map_or(true, |program| program.address_of_line(line).is_some())
+            });
+        if let Some(rect) = program
+            .filter(|_| self.highlight_pc_location)
+            .and_then(|program| program.line_of(self.machine.pc))
+            .and_then(|line| line_rects.get(line - 1))
+        {
+            let rect = egui::Rect::from_x_y_ranges(output.response.rect.x_range(), rect.y_range());
+            let [r, g, b] = self.pc_highlight_color;
+            painter.rect_filled(rect, 0.0, Color32::from_rgba_unmultiplied(r, g, b, 40));
+            if self.last_scrolled_pc != Some(self.machine.pc) {
+                self.last_scrolled_pc = Some(self.machine.pc);
+                ui.scroll_to_rect(rect, None);
+            }
+        }
+        if let Some(line) = clicked_line {
+            self.toggle_breakpoint(BreakpointLocation::Line(line));
+        }
+        if let Some(span) = self.jump_to_span.take() {
+            let line_start = line_start_char_index(&self.source_code, span.line);
+            let start = CCursor::new(line_start + span.start);
+            output.state.cursor.set_char_range(Some(CCursorRange::two(
+                start,
+                CCursor::new(line_start + span.end),
+            )));
+            output.state.clone().store(ui.ctx(), output.response.id);
+            output.response.request_focus();
+            let rect = output.galley.pos_from_ccursor(start);
+            ui.scroll_to_rect(
+                rect.translate(output.galley_pos.to_vec2()),
+                Some(egui::Align::Center),
+            );
+        }
+        self.source_completion(ui, &mut output, accepted);
+    }
+
+    /// Offers to complete the word before the cursor with a mnemonic or a label, showing the
+    /// candidates under the cursor. `accepted` is the candidate chosen with the keyboard.
+    fn source_completion(
+        &mut self,
+        ui: &mut egui::Ui,
+        output: &mut TextEditOutput,
+        mut accepted: Option<usize>,
+    ) {
+        let cursor = output.cursor_range.map(|range| range.primary.ccursor.index);
+        if accepted.is_none() {
+            if output.response.changed() {
+                self.completion = cursor.and_then(|cursor| self.completion_at(cursor));
+            } else if self
+                .completion
+                .as_ref()
+                .is_some_and(|completion| Some(completion.cursor) != cursor)
+            {
+                self.completion = None;
+            }
+        }
+        let Some(completion) = &self.completion else {
+            return;
+        };
+
+        let position = output.galley_pos
+            + output
+                .galley
+                .pos_from_ccursor(CCursor::new(completion.cursor))
+                .left_bottom()
+                .to_vec2();
+        egui::Area::new(output.response.id.with("completion"))
+            .order(egui::Order::Foreground)
+            .fixed_pos(position)
+            .show(ui.ctx(), |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    egui::Grid::new("completion").show(ui, |ui| {
+                        for (i, (word, hint)) in completion.candidates.iter().enumerate() {
+                            if ui
+                                .selectable_label(
+                                    i == completion.selected,
+                                    RichText::new(word).monospace(),
+                                )
+                                .clicked()
+                            {
+                                accepted = Some(i);
+                            }
+                            ui.colored_label(MfColors::GRAY_500, hint);
+                            ui.end_row();
+                        }
+                    });
+                    ui.colored_label(MfColors::GRAY_500, "Tab or Enter to complete");
+                });
+            });
+
+        if let Some(index) = accepted {
+            let Some(completion) = self.completion.take() else {
+                return;
+            };
+            let word = &completion.candidates[index].0;
+            let start = byte_index(&self.source_code, completion.word_start);
+            let end = byte_index(&self.source_code, completion.cursor);
+            self.source_code.replace_range(start..end, word);
+            let cursor = CCursor::new(completion.word_start + word.chars().count());
+            output
+                .state
+                .cursor
+                .set_char_range(Some(CCursorRange::one(cursor)));
+            output.state.clone().store(ui.ctx(), output.response.id);
+            output.response.request_focus();
+        } else if !output.response.has_focus() {
+            self.completion = None;
+        }
+    }
+
+    /// Finds the mnemonics and labels that the word ending at char index `cursor` could be.
+    fn completion_at(&self, cursor: usize) -> Option<Completion> {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+        let end = byte_index(&self.source_code, cursor);
+        let line_start = self.source_code[..end].rfind('\n').map_or(0, |i| i + 1);
+        let before = &self.source_code[line_start..end];
+        let word_start = before
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_word(c))
+            .last()
+            .map(|(i, _)| i)?;
+        let word = &before[word_start..];
+        let inside_word = self.source_code[end..].starts_with(is_word);
+        let in_literal = before[..word_start].ends_with(['&', '\'', '"']) || before.contains("//");
+        if inside_word || in_literal || word.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let upper = word.to_uppercase();
+        let mut candidates: Vec<(String, String)> = MNEMONICS
+            .iter()
+            .filter(|&&(mnemonic, opcode)| {
+                mnemonic.starts_with(&upper)
+                    && mnemonic != word
+                    && self.instruction_set.has_opcode(opcode)
+            })
+            .map(|&(mnemonic, opcode)| {
+                let operand = self.instruction_set.expected_operand(opcode);
+                let hint = if operand.is_empty() {
+                    "no operand".to_owned()
+                } else {
+                    operand
+                };
+                (mnemonic.to_owned(), hint)
+            })
+            .collect();
+        if let Some(program) = &self.program {
+            let labels = program.symbols.keys().map(|label| (label, "label"));
+            let constants = program.constants.keys().map(|name| (name, "constant"));
+            candidates.extend(
+                labels
+                    .chain(constants)
+                    .filter(|(name, _)| name.starts_with(word) && *name != word)
+                    .map(|(name, hint)| (name.clone(), hint.to_owned())),
+            );
+        }
+        candidates.truncate(8);
+        (!candidates.is_empty()).then(|| Completion {
+            word_start: cursor - word.chars().count(),
+            cursor,
+            candidates,
+            selected: 0,
+        })
+    }
+
+    /// Describes the forms of an opcode in the current instruction set, for hovering over it.
+    fn opcode_documentation(&self, ui: &mut egui::Ui, opcode: Opcode) {
+        let kinds: Vec<OperandKind> = self.instruction_set.operand_kinds(opcode).collect();
+        if kinds.is_empty() {
+            ui.colored_label(
+                MfColors::YELLOW_500,
+                format!(
+                    "{} {} is not in the {} instruction set.",
+                    MDI_ALERT,
+                    opcode,
+                    self.instruction_set.name()
+                ),
+            );
+            return;
+        }
+        egui::Grid::new("opcode_forms").show(ui, |ui| {
+            for kind in kinds {
+                ui.monospace(form_syntax(opcode, kind));
+                ui.label(form_rtl(opcode, kind));
+                ui.end_row();
+            }
+        });
+        ui.label("Example:");
+        ui.monospace(example(opcode));
     }
 
     fn console(&mut self, ui: &mut egui::Ui) {
         ui.add(
-            egui::TextEdit::multiline(&mut self.output)
+            egui::TextEdit::multiline(&mut self.machine.output)
                 .code_editor()
                 .desired_width(f32::INFINITY)
                 .interactive(false),
@@ -181,7 +623,9 @@ impl AppContext {
                             }
                             _ => unreachable!(),
                         }
-                        self.acc = self.input.chars().next().unwrap() as u16;
+                        self.history
+                            .input(&mut self.machine, self.input.chars().next().unwrap());
+                        self.trace.input(&self.machine);
                         self.input.clear();
                     }
                 });
@@ -203,102 +647,67 @@ impl AppContext {
             .spacing(vec2(0.0, 2.0))
             .show(ui, |ui| {
                 ui.label("PC");
-                ui.add(if self.value_as_hex {
-                    egui::DragValue::new(&mut self.pc)
-                        .speed(1.0)
-                        .range(0..=255)
-                        .hexadecimal(2, false, true)
-                } else {
-                    egui::DragValue::new(&mut self.pc).speed(1.0).range(0..=255)
-                });
+                ui.add(address_drag(&mut self.machine.pc, self.number_format));
                 ui.end_row();
 
                 ui.label("CIR");
-                ui.label(self.cir.0.to_string())
-                    .on_hover_text(format!("{} {}", self.cir.0, self.cir.1));
+                ui.label(self.machine.cir.0.to_string())
+                    .on_hover_text(format!("{} {}", self.machine.cir.0, self.machine.cir.1));
                 ui.end_row();
 
                 ui.label("IX");
-                ui.add(if self.value_as_hex {
-                    egui::DragValue::new(&mut self.ix)
-                        .speed(1.0)
-                        .range(0..=255)
-                        .hexadecimal(2, false, true)
-                } else {
-                    egui::DragValue::new(&mut self.ix).speed(1.0).range(0..=255)
-                });
+                ui.add(address_drag(&mut self.machine.ix, self.number_format));
                 ui.end_row();
 
                 ui.label("MDR");
-                match &mut self.mdr {
+                match &mut self.machine.mdr {
                     MemoryData::Instruction(opcode, operand) => {
                         ui.label(opcode.to_string())
                             .on_hover_text(format!("{} {}", opcode, operand));
                     }
                     MemoryData::Value(v) => {
-                        ui.add(if self.value_as_hex {
-                            egui::DragValue::new(v)
-                                .speed(1.0)
-                                .range(0..=0xffff)
-                                .hexadecimal(4, false, true)
-                        } else {
-                            egui::DragValue::new(v).speed(1.0).range(0..=0xffff)
-                        });
+                        ui.add(value_drag(v, self.number_format));
                     }
                 }
                 ui.end_row();
 
                 ui.label("MAR");
-                ui.add(if self.value_as_hex {
-                    egui::DragValue::new(&mut self.mar)
-                        .speed(1.0)
-                        .range(0..=255)
-                        .hexadecimal(2, false, true)
-                } else {
-                    egui::DragValue::new(&mut self.mar)
-                        .speed(1.0)
-                        .range(0..=255)
-                });
+                ui.add(address_drag(&mut self.machine.mar, self.number_format));
                 ui.end_row();
 
                 ui.label("ACC");
-                ui.add(if self.value_as_hex {
-                    egui::DragValue::new(&mut self.acc)
-                        .speed(1.0)
-                        .range(0..=0xffff)
-                        .hexadecimal(4, false, true)
-                } else {
-                    egui::DragValue::new(&mut self.acc)
-                        .speed(1.0)
-                        .range(0..=0xffff)
-                });
+                ui.add(value_drag(&mut self.machine.acc, self.number_format));
                 ui.end_row();
             });
         ui.separator();
         ui.vertical(|ui| {
-            ui.toggle_value(&mut self.carry, "Carry flag");
-            ui.toggle_value(&mut self.zero, "Zero flag");
-            ui.toggle_value(&mut self.overflow, "Overflow flag");
-            ui.toggle_value(&mut self.sign, "Sign flag");
+            ui.toggle_value(&mut self.machine.carry, "Carry flag");
+            ui.toggle_value(&mut self.machine.zero, "Zero flag");
+            ui.toggle_value(&mut self.machine.overflow, "Overflow flag");
+            ui.toggle_value(&mut self.machine.sign, "Sign flag");
             ui.horizontal(|ui| {
                 ui.checkbox(
                     &mut self.highlight_pc_location,
-                    "Highlight PC index in memory",
+                    "Highlight PC location in memory and source",
                 );
                 ui.color_edit_button_srgb(&mut self.pc_highlight_color);
             });
             ui.horizontal(|ui| {
                 ui.label("Show values in");
-                ui.radio_value(&mut self.value_as_hex, true, "hex");
-                ui.radio_value(&mut self.value_as_hex, false, "dec");
+                for format in NumberFormat::ALL {
+                    ui.radio_value(&mut self.number_format, format, format.name());
+                }
             });
         })
     }
 
     fn memory(&mut self, ui: &mut egui::Ui) {
-        ui.colored_label(MfColors::GRAY_700, "Hover on any cell to see details.");
+        ui.horizontal(|ui| {
+            ui.colored_label(MfColors::GRAY_700, "Hover on any cell to see details.");
+            ui.checkbox(&mut self.show_ascii_column, "Show ASCII");
+        });
         egui::Grid::new("memroy_grid")
-            .num_columns(17)
+            .num_columns(if self.show_ascii_column { 18 } else { 17 })
             .spacing(vec2(2.0, 2.0))
             .show(ui, |ui| {
                 ui.style_mut().spacing.interact_size = vec2(30.0, 18.0);
@@ -306,21 +715,27 @@ impl AppContext {
                 for i in 0..16 {
                     ui.label(format!("{:02X}", i));
                 }
+                if self.show_ascii_column {
+                    ui.label("ASCII");
+                }
                 ui.end_row();
                 for i in 0..16 {
                     ui.label(format!("{:02X}", i));
                     for j in 0..16 {
-                        match &mut self.memory[i][j] {
+                        let address = (i * 16 + j) as u16;
+                        let response = match &mut self.machine.memory[i][j] {
                             MemoryData::Instruction(opcode, operand) => {
-                                if self.highlight_pc_location && i * 16 + j == self.pc as usize {
-                                    ui.colored_label(
-                                        Color32::from_rgb(
-                                            self.pc_highlight_color[0],
-                                            self.pc_highlight_color[1],
-                                            self.pc_highlight_color[2],
-                                        ),
-                                        opcode.to_string(),
-                                    )
+                                let mut text = RichText::new(opcode.to_string());
+                                if self.highlight_pc_location
+                                    && i * 16 + j == self.machine.pc as usize
+                                {
+                                    text = text.color(Color32::from_rgb(
+                                        self.pc_highlight_color[0],
+                                        self.pc_highlight_color[1],
+                                        self.pc_highlight_color[2],
+                                    ));
+                                }
+                                ui.add(egui::Label::new(text).sense(egui::Sense::click()))
                                     .on_hover_ui(|ui| {
                                         ui.label(format!("{} {}", opcode, operand));
                                         ui.separator();
@@ -330,24 +745,18 @@ impl AppContext {
                                             j,
                                             i * 16 + j
                                         ));
-                                    });
-                                } else {
-                                    ui.label(opcode.to_string()).on_hover_ui(|ui| {
-                                        ui.label(format!("{} {}", opcode, operand));
-                                        ui.separator();
-                                        ui.label(format!(
-                                            "Address: {:X}{:X}₁₆ = {}₁₀",
-                                            i,
-                                            j,
-                                            i * 16 + j
-                                        ));
-                                    });
-                                }
+                                        ui.colored_label(
+                                            MfColors::GRAY_700,
+                                            "Click to toggle a breakpoint.",
+                                        );
+                                    })
                             }
                             MemoryData::Value(v) => {
                                 let original_color =
                                     ui.style().visuals.widgets.inactive.fg_stroke.color;
-                                if self.highlight_pc_location && i * 16 + j == self.pc as usize {
+                                if self.highlight_pc_location
+                                    && i * 16 + j == self.machine.pc as usize
+                                {
                                     ui.style_mut().visuals.widgets.inactive.fg_stroke.color =
                                         Color32::from_rgb(
                                             self.pc_highlight_color[0],
@@ -355,678 +764,1185 @@ impl AppContext {
                                             self.pc_highlight_color[2],
                                         );
                                 }
-                                ui.add(if self.value_as_hex {
-                                    egui::DragValue::new(v)
-                                        .speed(1.0)
-                                        .range(0..=0xffff)
-                                        .hexadecimal(4, false, true)
-                                } else {
-                                    egui::DragValue::new(v).speed(1.0).range(0..=0xffff)
-                                })
-                                .on_hover_ui(|ui| {
-                                    ui.label(format!("Value: {:04X}₁₆ = {}₁₀", v, v));
-                                    ui.separator();
-                                    ui.label(format!(
-                                        "Address: {:X}{:X}₁₆ = {}₁₀",
-                                        i,
-                                        j,
-                                        i * 16 + j
-                                    ));
-                                });
+                                let response =
+                                    ui.add(value_drag(v, self.number_format)).on_hover_ui(|ui| {
+                                        ui.label(format!("Value: {:04X}₁₆ = {}₁₀", v, v));
+                                        ui.label(format!(
+                                            "Signed: {}, binary: {}",
+                                            *v as i16,
+                                            NumberFormat::Binary.format(*v)
+                                        ));
+                                        if let Some(c) = ascii_char(*v) {
+                                            ui.label(format!("ASCII: '{}'", c));
+                                        }
+                                        if let Some(encoding) = &self.machine.encoding {
+                                            let bits = format!("{:016b}", v);
+                                            let (opcode_field, operand_field) = bits.split_at(
+                                                usize::from(encoding.opcode_bits).min(16),
+                                            );
+                                            ui.monospace(format!(
+                                                "{} {}",
+                                                opcode_field, operand_field
+                                            ));
+                                            match encoding.decode(*v) {
+                                                Some((opcode, operand)) => ui.label(format!(
+                                                    "Decodes to {}",
+                                                    format_instruction(
+                                                        opcode,
+                                                        operand,
+                                                        self.number_format
+                                                    )
+                                                )),
+                                                None => ui.label("Not a valid instruction"),
+                                            };
+                                        }
+                                        ui.separator();
+                                        ui.label(format!(
+                                            "Address: {:X}{:X}₁₆ = {}₁₀",
+                                            i,
+                                            j,
+                                            i * 16 + j
+                                        ));
+                                        ui.colored_label(
+                                            MfColors::GRAY_700,
+                                            "Right-click to toggle a breakpoint.",
+                                        );
+                                    });
                                 ui.style_mut().visuals.widgets.inactive.fg_stroke.color =
                                     original_color;
+                                response
+                            }
+                        };
+                        let is_instruction =
+                            matches!(self.machine.memory[i][j], MemoryData::Instruction(_, _));
+                        if is_instruction && response.clicked() {
+                            self.toggle_breakpoint(BreakpointLocation::Address(address));
+                        }
+                        response.context_menu(|ui| {
+                            if ui.button("Toggle breakpoint").clicked() {
+                                self.toggle_breakpoint(BreakpointLocation::Address(address));
+                                ui.close_menu();
+                            }
+                        });
+                        if self.highlighted_address == Some(address) {
+                            ui.painter().rect_stroke(
+                                response.rect.expand(1.0),
+                                2.0,
+                                (1.5, MfColors::BLUE_300),
+                            );
+                        }
+                        if let Some(enabled) = self.breakpoint_enabled_at(address) {
+                            let center = response.rect.left_top() + vec2(3.0, 3.0);
+                            if enabled {
+                                ui.painter().circle_filled(center, 2.5, MfColors::RED_500);
+                            } else {
+                                ui.painter()
+                                    .circle_stroke(center, 2.0, (1.0, MfColors::RED_500));
                             }
                         }
                     }
+                    if self.show_ascii_column {
+                        let text: String = self.machine.memory[i]
+                            .iter()
+                            .map(|data| match data {
+                                MemoryData::Value(v) => ascii_char(*v).unwrap_or('.'),
+                                MemoryData::Instruction(_, _) => '.',
+                            })
+                            .collect();
+                        ui.monospace(text);
+                    }
                     ui.end_row();
                 }
             });
     }
 
-    fn step(&mut self) {
-        if self.ins_executed >= 1000 {
-            self.execution_info = Some(ExecutionInfo::TooManySteps {
-                steps: self.ins_executed,
+    fn breakpoints(&mut self, ui: &mut egui::Ui) {
+        ui.colored_label(
+            MfColors::GRAY_700,
+            "Click the Source Editor gutter or a memory cell to add a breakpoint.",
+        );
+        if self.execution_state == ExecutionState::Paused {
+            ui.colored_label(
+                MfColors::YELLOW_500,
+                format!(
+                    "{} Paused at address {:02X}. Click Continue to resume.",
+                    MDI_PAUSE, self.machine.pc
+                ),
+            );
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Enable all").clicked() {
+                self.breakpoints.iter_mut().for_each(|b| b.enabled = true);
+            }
+            if ui.button("Disable all").clicked() {
+                self.breakpoints.iter_mut().for_each(|b| b.enabled = false);
+            }
+            if ui
+                .button(MDI_DELETE_SWEEP.to_owned() + " Clear all")
+                .clicked()
+            {
+                self.breakpoints.clear();
+            }
+        });
+        ui.separator();
+        if self.breakpoints.is_empty() {
+            ui.label("No breakpoints.");
+        }
+        let mut removed = None;
+        for (index, breakpoint) in self.breakpoints.iter_mut().enumerate() {
+            let address = match breakpoint.location {
+                BreakpointLocation::Address(a) => Some(a),
+                BreakpointLocation::Line(line) => self
+                    .program
+                    .as_ref()
+                    .and_then(|program| program.address_of_line(line)),
+            };
+            let description = match (breakpoint.location, address) {
+                (BreakpointLocation::Line(line), Some(a)) => {
+                    format!("Line {} (address {:02X})", line, a)
+                }
+                (BreakpointLocation::Line(line), None) => {
+                    format!("Line {} (not assembled)", line)
+                }
+                (BreakpointLocation::Address(a), _) => format!("Address {:02X}", a),
+            };
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut breakpoint.enabled, description);
+                if ui.small_button(MDI_CLOSE).on_hover_text("Remove").clicked() {
+                    removed = Some(index);
+                }
             });
-            self.show_assembler_info_window = true;
         }
-        self.mar = self.pc;
-        self.mdr = self.memory.as_flattened()[self.pc as usize];
-        let current_instruction = match &self.memory.as_flattened()[self.pc as usize] {
-            MemoryData::Instruction(opcode, operand) => (*opcode, *operand),
-            MemoryData::Value(v) => {
-                if *v == 0 {
-                    self.execution_info = Some(ExecutionInfo::ExecutionTerminated {
-                        ins_address: self.pc,
-                    });
-                    self.execution_state = ExecutionState::Stopped;
-                } else {
-                    self.execution_info = Some(ExecutionInfo::ExecutionAbortedValueMet {
-                        ins_address: self.pc,
-                        value: *v,
+        if let Some(index) = removed {
+            self.breakpoints.remove(index);
+        }
+    }
+
+    fn symbols(&mut self, ui: &mut egui::Ui) {
+        let Some(program) = &self.program else {
+            ui.label("Assemble and load the program to see its labels.");
+            return;
+        };
+        ui.colored_label(
+            MfColors::GRAY_700,
+            "Click a label to find its definition and its memory cell.",
+        );
+        if self.assembled_source != self.source_code {
+            ui.colored_label(
+                MfColors::YELLOW_500,
+                format!(
+                    "{} The source code has changed since it was assembled.",
+                    MDI_ALERT
+                ),
+            );
+        }
+        if program.symbols.is_empty() {
+            ui.label("The program has no labels.");
+            return;
+        }
+        let mut clicked = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("symbols").striped(true).show(ui, |ui| {
+                ui.strong("Label");
+                ui.strong("Address");
+                ui.strong("Kind");
+                ui.strong("Value");
+                ui.end_row();
+                for (label, &address) in &program.symbols {
+                    let definition = program.definitions.get(label);
+                    if ui
+                        .selectable_label(self.highlighted_address == Some(address), label)
+                        .clicked()
+                    {
+                        clicked = Some((address, definition.map(|d| d.span)));
+                    }
+                    ui.monospace(self.number_format.format_address(address));
+                    ui.label(match definition {
+                        Some(d) if d.is_code => "Code",
+                        _ => "Data",
                     });
-                    self.execution_state = ExecutionState::Stopped;
+                    let value = match self.machine.memory.as_flattened().get(address as usize) {
+                        Some(&MemoryData::Value(v)) => match ascii_char(v) {
+                            Some(c) => format!("{} '{}'", self.number_format.format(v), c),
+                            None => self.number_format.format(v),
+                        },
+                        Some(&MemoryData::Instruction(opcode, operand)) => {
+                            format_instruction(opcode, operand, self.number_format)
+                        }
+                        None => "Not in memory".to_owned(),
+                    };
+                    ui.monospace(value);
+                    ui.end_row();
                 }
-                self.show_assembler_info_window = true;
-                return;
+            });
+        });
+        if let Some((address, span)) = clicked {
+            self.highlighted_address = Some(address);
+            // Spans only point at the right text in the source the program was assembled from
+            if self.assembled_source == self.source_code {
+                self.jump_to_span = span;
             }
+        }
+    }
+
+    fn control_flow(&mut self, ui: &mut egui::Ui) {
+        let Some(program) = &self.program else {
+            ui.label("Assemble and load the program to see its control flow.");
+            return;
         };
-        self.cir = current_instruction;
-        let cur_ins_add = self.pc;
-        self.pc += 1;
-        match current_instruction.0 {
-            Opcode::Ldm => {
-                self.acc = match current_instruction.1 {
-                    Operand::Immediate(v) => v,
-                    _ => unreachable!(),
-                };
-            }
-            Opcode::Ldd => {
-                self.acc = match current_instruction.1 {
-                    Operand::Address(a) => {
-                        self.mar = a;
-                        match self.memory.as_flattened()[a as usize] {
-                            MemoryData::Value(v) => {
-                                self.mdr = MemoryData::Value(v);
-                                v
-                            }
-                            MemoryData::Instruction(_, _) => {
-                                self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                    ins_address: cur_ins_add,
-                                    requested_address: a,
-                                });
-                                self.execution_state = ExecutionState::Stopped;
-                                self.show_assembler_info_window = true;
-                                return;
-                            }
+        ui.colored_label(
+            MfColors::GRAY_700,
+            "Each box is a run of instructions that are always executed in order. Click a box to find it in the source code.",
+        );
+        if self.assembled_source != self.source_code {
+            ui.colored_label(
+                MfColors::YELLOW_500,
+                format!(
+                    "{} The source code has changed since it was assembled.",
+                    MDI_ALERT
+                ),
+            );
+        }
+        let graph = ControlFlowGraph::new(program);
+        if graph.blocks.is_empty() {
+            ui.label("The program has no code.");
+            return;
+        }
+        let pc_block = graph
+            .block_of(self.machine.pc)
+            .filter(|_| self.highlight_pc_location);
+        // Edges to the next block are drawn straight down, the others in lanes to the right
+        let is_straight = |block: usize, edge: &Edge| {
+            edge.target == Some(block + 1) && edge.kind != EdgeKind::Taken
+        };
+        let lanes = graph
+            .blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(i, block)| block.edges.iter().filter(move |e| !is_straight(i, e)))
+            .filter(|edge| edge.target.is_some())
+            .count();
+        let mut clicked = None;
+        egui::ScrollArea::both().show(ui, |ui| {
+            let mut rects = Vec::new();
+            ui.horizontal_top(|ui| {
+                ui.vertical(|ui| {
+                    for (i, block) in graph.blocks.iter().enumerate() {
+                        let fill = if pc_block == Some(i) {
+                            let [r, g, b] = self.pc_highlight_color;
+                            Color32::from_rgba_unmultiplied(r, g, b, 40)
+                        } else {
+                            Color32::TRANSPARENT
+                        };
+                        let response = egui::Frame::group(ui.style())
+                            .fill(fill)
+                            .show(ui, |ui| {
+                                ui.set_min_width(160.0);
+                                if let Some(label) = &block.label {
+                                    ui.strong(format!("{}:", label));
+                                }
+                                for &(address, opcode, operand) in &block.instructions {
+                                    ui.monospace(format!(
+                                        "{}  {}",
+                                        self.number_format.format_address(address),
+                                        format_instruction(opcode, operand, self.number_format)
+                                    ));
+                                }
+                                for edge in block.edges.iter().filter(|e| e.target.is_none()) {
+                                    ui.colored_label(
+                                        MfColors::YELLOW_500,
+                                        format!(
+                                            "{} Continues at {}, which is not code",
+                                            MDI_ALERT,
+                                            self.number_format.format_address(edge.address)
+                                        ),
+                                    );
+                                }
+                            })
+                            .response
+                            .interact(egui::Sense::click())
+                            .on_hover_cursor(egui::CursorIcon::PointingHand);
+                        if response.clicked() {
+                            clicked = Some(block.start());
                         }
+                        rects.push(response.rect);
+                        ui.add_space(24.0);
+                    }
+                });
+                ui.add_space(16.0 + lanes as f32 * 12.0);
+            });
+
+            let painter = ui.painter();
+            let font_id = egui::TextStyle::Small.resolve(ui.style());
+            let right = rects.iter().map(|r| r.right()).fold(f32::MIN, f32::max);
+            let mut lane = 0;
+            for (i, block) in graph.blocks.iter().enumerate() {
+                for edge in &block.edges {
+                    let Some(target) = edge.target else {
+                        continue;
+                    };
+                    let (color, text) = match edge.kind {
+                        EdgeKind::Next | EdgeKind::Jump => (MfColors::GRAY_500, ""),
+                        EdgeKind::Taken => (MfColors::GREEN_500, "taken"),
+                        EdgeKind::NotTaken => (MfColors::RED_400, "not taken"),
+                    };
+                    let stroke = egui::Stroke::from((1.5, color));
+                    let (from, to) = (rects[i], rects[target]);
+                    if is_straight(i, edge) {
+                        let start = from.center_bottom();
+                        painter.arrow(start, to.center_top() - start, stroke);
+                        painter.text(
+                            start + vec2(6.0, 2.0),
+                            egui::Align2::LEFT_TOP,
+                            text,
+                            font_id.clone(),
+                            color,
+                        );
+                    } else {
+                        lane += 1;
+                        let x = right + 4.0 + lane as f32 * 12.0;
+                        let start = egui::pos2(from.right(), from.bottom() - 10.0);
+                        let end = egui::pos2(to.right(), to.top() + 10.0);
+                        painter.line_segment([start, egui::pos2(x, start.y)], stroke);
+                        painter
+                            .line_segment([egui::pos2(x, start.y), egui::pos2(x, end.y)], stroke);
+                        painter.arrow(egui::pos2(x, end.y), end - egui::pos2(x, end.y), stroke);
+                        painter.text(
+                            start + vec2(2.0, 0.0),
+                            egui::Align2::LEFT_BOTTOM,
+                            text,
+                            font_id.clone(),
+                            color,
+                        );
                     }
-                    _ => unreachable!(),
                 }
             }
-            Opcode::Ldi => {
-                self.acc = match current_instruction.1 {
-                    Operand::Address(a) => {
-                        self.mar = a;
-                        match self.memory.as_flattened()[a as usize] {
-                            MemoryData::Value(v) => {
-                                self.mdr = MemoryData::Value(v);
-                                self.mar = v;
-                                if v > 255 {
-                                    self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                                        ins_address: cur_ins_add,
-                                        requested_address: v,
-                                    });
-                                    self.execution_state = ExecutionState::Stopped;
-                                    self.show_assembler_info_window = true;
-                                    return;
+        });
+        if let Some(address) = clicked {
+            // Spans only point at the right text in the source the program was assembled from
+            if self.assembled_source == self.source_code {
+                self.jump_to_span = address
+                    .checked_sub(program.load_address)
+                    .and_then(|offset| program.spans.get(usize::from(offset)))
+                    .copied();
+            }
+        }
+    }
+
+    fn timeline(&mut self, ui: &mut egui::Ui) {
+        ui.colored_label(
+            MfColors::GRAY_700,
+            "Drag the slider to go back to any earlier step. Stepping from an earlier point discards the steps after it.",
+        );
+        ui.add_enabled_ui(self.can_travel(), |ui| {
+            let first = self.history.first();
+            let len = self.history.len();
+            let mut position = self.history.position();
+            ui.horizontal(|ui| {
+                if ui
+                    .button(MDI_SKIP_PREVIOUS)
+                    .on_hover_text("First step")
+                    .clicked()
+                {
+                    position = first;
+                }
+                if ui
+                    .button(MDI_STEP_BACKWARD)
+                    .on_hover_text("Step back")
+                    .clicked()
+                {
+                    position = position.saturating_sub(1).max(first);
+                }
+                if ui
+                    .button(MDI_STEP_FORWARD)
+                    .on_hover_text("Step forward")
+                    .clicked()
+                {
+                    position = (position + 1).min(len);
+                }
+                if ui
+                    .button(MDI_SKIP_NEXT)
+                    .on_hover_text("Latest step")
+                    .clicked()
+                {
+                    position = len;
+                }
+            });
+            ui.spacing_mut().slider_width = ui.available_width() - 80.0;
+            ui.add(egui::Slider::new(&mut position, first..=len).text("steps"));
+            if position != self.history.position() {
+                self.travel_to(position);
+            }
+        });
+        ui.separator();
+        match self
+            .history
+            .position()
+            .checked_sub(1)
+            .and_then(|i| self.history.address_of(i))
+        {
+            Some(address) => ui.label(format!(
+                "Step {} of {}: last executed the instruction at address {:02X}.",
+                self.history.position(),
+                self.history.len(),
+                address
+            )),
+            None if self.history.position() > 0 => ui.label(format!(
+                "Step {} of {}: the steps before it are forgotten.",
+                self.history.position(),
+                self.history.len()
+            )),
+            None => ui.label(format!(
+                "Before the first step ({} recorded).",
+                self.history.len()
+            )),
+        };
+    }
+
+    /// Assembles and lints the source code to the load location, encoding it if machine code is
+    /// enabled.
+    fn assemble_source(&self) -> Result<Program, Vec<Diagnostic>> {
+        let mut program = assemble_for(
+            &self.source_code,
+            self.program_load_location,
+            self.instruction_set,
+        )?;
+        program.warnings.extend(lint(&program));
+        program
+            .warnings
+            .sort_by_key(|d| (d.span().line, d.span().start));
+        if let Some(encoding) = &self.encoding {
+            program.encode(encoding)?;
+        }
+        Ok(program)
+    }
+
+    fn instruction_set_settings(&mut self, ui: &mut egui::Ui) {
+        for set in InstructionSet::ALL {
+            if ui
+                .radio_value(&mut self.instruction_set, set, set.name())
+                .on_hover_text(set.description())
+                .changed()
+            {
+                self.machine.instruction_set = set;
+            }
+        }
+        ui.colored_label(
+            MfColors::GRAY_700,
+            "Programs are checked against the instruction set when they are assembled, and instructions outside it stop execution.",
+        );
+    }
+
+    fn machine_code_settings(&mut self, ui: &mut egui::Ui) {
+        let mut enabled = self.encoding.is_some();
+        if ui
+            .checkbox(&mut enabled, "Store instructions as machine code")
+            .changed()
+        {
+            self.encoding = enabled.then(Encoding::default);
+        }
+        ui.colored_label(
+            MfColors::GRAY_700,
+            "Instructions are encoded into 16-bit words the next time the program is assembled and loaded, and decoded when they are fetched. STO can then overwrite the program.",
+        );
+        let Some(encoding) = &mut self.encoding else {
+            return;
+        };
+        ui.horizontal(|ui| {
+            ui.label("Opcode bits");
+            ui.add(
+                egui::DragValue::new(&mut encoding.opcode_bits)
+                    .range(Encoding::MIN_OPCODE_BITS..=Encoding::MAX_OPCODE_BITS),
+            );
+            ui.label(format!("Operand bits: {}", encoding.operand_bits()));
+            if ui.button("Reset to default").clicked() {
+                *encoding = Encoding::default();
+            }
+        });
+        if let Err(e) = encoding.validate() {
+            ui.colored_label(
+                MfColors::RED_400,
+                format!("{} Invalid layout: {}", MDI_ALERT, e),
+            );
+        }
+        let opcode_bits = encoding.opcode_bits as usize;
+        let max_opcode = u16::MAX
+            .checked_shr(encoding.operand_bits().into())
+            .unwrap_or(0);
+        egui::ScrollArea::vertical()
+            .max_height(320.0)
+            .show(ui, |ui| {
+                egui::Grid::new("encoding").striped(true).show(ui, |ui| {
+                    ui.strong("Instruction");
+                    ui.strong("Opcode");
+                    ui.strong("Binary");
+                    ui.end_row();
+                    for (&(opcode, kind), code) in
+                        INSTRUCTION_FORMS.iter().zip(&mut encoding.opcodes)
+                    {
+                        ui.monospace(form_syntax(opcode, kind));
+                        ui.add(egui::DragValue::new(code).range(0..=max_opcode));
+                        ui.monospace(format!("{:0width$b}", code, width = opcode_bits));
+                        ui.end_row();
+                    }
+                });
+            });
+    }
+
+    fn tests(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal_wrapped(|ui| {
+            if ui.button(MDI_PLAY.to_owned() + " Run all tests").clicked() {
+                self.test_results.clear();
+                self.test_error = None;
+                match self.assemble_source() {
+                    Ok(program) => {
+                        self.test_results =
+                            self.test_cases.iter().map(|c| c.run(&program)).collect()
+                    }
+                    Err(diagnostics) => {
+                        self.test_error = Some(format!(
+                            "The program could not be assembled: {}",
+                            diagnostics
+                                .iter()
+                                .find(|d| d.is_error())
+                                .map(|d| d.to_string())
+                                .unwrap_or_default()
+                        ))
+                    }
+                }
+            }
+            if ui.button(MDI_PLUS.to_owned() + " Add test case").clicked() {
+                self.test_cases.push(TestCase {
+                    name: format!("Test {}", self.test_cases.len() + 1),
+                    ..Default::default()
+                });
+            }
+            if let Some(e) = &self.test_error {
+                ui.colored_label(MfColors::RED_400, e);
+            } else if !self.test_results.is_empty() {
+                let results = &self.test_results;
+                let passed = results.iter().filter(|r| r.passed()).count();
+                let color = if passed == results.len() {
+                    MfColors::GREEN_400
+                } else {
+                    MfColors::RED_400
+                };
+                ui.colored_label(color, format!("{} of {} passed", passed, results.len()));
+            }
+        });
+        ui.separator();
+        if self.test_cases.is_empty() {
+            ui.label("No test cases. Each test case runs the program with some input and checks the output, registers and memory it ends with.");
+        }
+        let format = self.number_format;
+        let mut removed = None;
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                for (index, case) in self.test_cases.iter_mut().enumerate() {
+                    let result = self.test_results.get(index);
+                    let title = match result {
+                        Some(result) if result.passed() => {
+                            RichText::new(format!("{} {}", MDI_CHECK_CIRCLE, case.name))
+                                .color(MfColors::GREEN_400)
+                        }
+                        Some(_) => RichText::new(format!("{} {}", MDI_CLOSE_CIRCLE, case.name))
+                            .color(MfColors::RED_400),
+                        None => RichText::new(&case.name),
+                    };
+                    egui::CollapsingHeader::new(title)
+                        .id_salt(("test_case", index))
+                        .show(ui, |ui| {
+                            egui::Grid::new(("test_case_grid", index))
+                                .num_columns(2)
+                                .show(ui, |ui| {
+                                    ui.label("Name");
+                                    ui.text_edit_singleline(&mut case.name);
+                                    ui.end_row();
+                                    ui.label("Input");
+                                    ui.add(
+                                        egui::TextEdit::singleline(&mut case.input)
+                                            .hint_text("Characters for IN"),
+                                    );
+                                    ui.end_row();
+                                    let mut checked = case.expected_output.is_some();
+                                    if ui.checkbox(&mut checked, "Output").changed() {
+                                        case.expected_output = checked.then(String::new);
+                                    }
+                                    if let Some(output) = &mut case.expected_output {
+                                        ui.add(
+                                            egui::TextEdit::multiline(output)
+                                                .code_editor()
+                                                .desired_rows(1),
+                                        );
+                                    }
+                                    ui.end_row();
+                                    for (name, expected) in [
+                                        ("ACC", &mut case.expected_acc),
+                                        ("IX", &mut case.expected_ix),
+                                    ] {
+                                        let mut checked = expected.is_some();
+                                        if ui.checkbox(&mut checked, name).changed() {
+                                            *expected = checked.then_some(0);
+                                        }
+                                        if let Some(value) = expected {
+                                            ui.add(value_drag(value, format));
+                                        }
+                                        ui.end_row();
+                                    }
+                                    ui.label("Max steps");
+                                    ui.add(
+                                        egui::DragValue::new(&mut case.max_steps)
+                                            .range(1..=100_000),
+                                    );
+                                    ui.end_row();
+                                });
+                            ui.label("Memory when the program ends:");
+                            let mut removed_check = None;
+                            for (check, (address, value)) in
+                                case.expected_memory.iter_mut().enumerate()
+                            {
+                                ui.horizontal(|ui| {
+                                    ui.label("Address");
+                                    ui.add(address_drag(address, self.number_format));
+                                    ui.label("contains");
+                                    ui.add(value_drag(value, format));
+                                    if ui.small_button(MDI_CLOSE).clicked() {
+                                        removed_check = Some(check);
+                                    }
+                                });
+                            }
+                            if let Some(check) = removed_check {
+                                case.expected_memory.remove(check);
+                            }
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .button(MDI_PLUS.to_owned() + " Add memory check")
+                                    .clicked()
+                                {
+                                    case.expected_memory.push((0, 0));
+                                }
+                                if ui
+                                    .button(MDI_DELETE.to_owned() + " Remove test case")
+                                    .clicked()
+                                {
+                                    removed = Some(index);
+                                }
+                            });
+                            match result {
+                                Some(result) if result.passed() => {
+                                    ui.colored_label(
+                                        MfColors::GREEN_400,
+                                        format!("Passed after {} instructions.", result.steps),
+                                    );
                                 }
-                                match self.memory.as_flattened()[v as usize] {
-                                    MemoryData::Value(v) => v,
-                                    MemoryData::Instruction(_, _) => {
-                                        self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                            ins_address: cur_ins_add,
-                                            requested_address: v,
+                                Some(result) => {
+                                    egui::Grid::new(("test_case_result", index))
+                                        .striped(true)
+                                        .show(ui, |ui| {
+                                            ui.strong("");
+                                            ui.strong("Expected");
+                                            ui.strong("Actual");
+                                            ui.end_row();
+                                            for mismatch in &result.mismatches {
+                                                ui.label(&mismatch.what);
+                                                ui.colored_label(
+                                                    MfColors::GREEN_400,
+                                                    &mismatch.expected,
+                                                );
+                                                ui.colored_label(
+                                                    MfColors::RED_400,
+                                                    &mismatch.actual,
+                                                );
+                                                ui.end_row();
+                                            }
                                         });
-                                        self.execution_state = ExecutionState::Stopped;
-                                        self.show_assembler_info_window = true;
-                                        return;
-                                    }
                                 }
+                                None => (),
                             }
-                            MemoryData::Instruction(_, _) => {
-                                self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                    ins_address: cur_ins_add,
-                                    requested_address: a,
-                                });
-                                self.execution_state = ExecutionState::Stopped;
-                                self.show_assembler_info_window = true;
-                                return;
-                            }
-                        }
-                    }
-                    _ => unreachable!(),
+                        });
                 }
+            });
+        if let Some(index) = removed {
+            self.test_cases.remove(index);
+            self.test_results.clear();
+        }
+    }
+
+    fn worksheet(&mut self, ui: &mut egui::Ui) {
+        let Some(program) = &self.program else {
+            ui.label("Assemble and load a program to practise its trace table.");
+            return;
+        };
+        ui.colored_label(
+            MfColors::GRAY_700,
+            "Fill in the trace table without running the program, then check your answers. The columns are the ones selected in the Trace Table panel when the worksheet was started.",
+        );
+        ui.horizontal(|ui| {
+            ui.label("Input:");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.worksheet.input)
+                    .desired_width(120.0)
+                    .hint_text("Characters for IN"),
+            );
+            if ui.button(MDI_PLUS.to_owned() + " New worksheet").clicked() {
+                self.worksheet = Worksheet::new(
+                    self.trace_columns.clone(),
+                    std::mem::take(&mut self.worksheet.input),
+                );
+                self.worksheet.add_row();
+                self.worksheet_marks = None;
+            }
+        });
+        if self.worksheet.columns.is_empty() {
+            return;
+        }
+
+        let input = &self.worksheet.input;
+        let (_, expected) = match &mut self.worksheet_trace {
+            Some(cached) if cached.0 == *input => cached,
+            cache => cache.insert((input.clone(), Trace::run(program, input, 1000))),
+        };
+        let expected = &*expected;
+        let format = self.number_format;
+        ui.horizontal(|ui| {
+            if ui.button(MDI_PLUS.to_owned() + " Add row").clicked() {
+                self.worksheet.add_row();
+                self.worksheet_marks = None;
             }
-            Opcode::Ldx => {
-                self.acc = match current_instruction.1 {
-                    Operand::Address(a) => {
-                        self.mar = a + self.ix;
-                        if a + self.ix > 255 {
-                            self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                                ins_address: cur_ins_add,
-                                requested_address: a + self.ix,
-                            });
-                            self.execution_state = ExecutionState::Stopped;
-                            self.show_assembler_info_window = true;
-                            return;
-                        }
-                        match self.memory.as_flattened()[(a + self.ix) as usize] {
-                            MemoryData::Value(v) => {
-                                self.mdr = MemoryData::Value(v);
-                                v
-                            }
-                            MemoryData::Instruction(_, _) => {
-                                self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                    ins_address: cur_ins_add,
-                                    requested_address: a + self.ix,
-                                });
-                                self.execution_state = ExecutionState::Stopped;
-                                self.show_assembler_info_window = true;
-                                return;
-                            }
-                        }
-                    }
-                    _ => unreachable!(),
-                }
+            if ui
+                .add_enabled(
+                    !self.worksheet.answers.is_empty(),
+                    egui::Button::new(MDI_MINUS.to_owned() + " Remove last row"),
+                )
+                .clicked()
+            {
+                self.worksheet.answers.pop();
+                self.worksheet_marks = None;
             }
-            Opcode::Ldr => {
-                self.ix = match current_instruction.1 {
-                    Operand::Immediate(v) => v,
-                    _ => unreachable!(),
-                };
+            if ui.button(MDI_CHECK.to_owned() + " Check answers").clicked() {
+                self.worksheet_marks = Some(self.worksheet.check(expected, format));
             }
-            Opcode::Mov => match current_instruction.1 {
-                Operand::Register(r) => match r {
-                    Register::Ix => {
-                        self.ix = self.acc;
-                    }
-                    Register::Acc => (),
-                },
-                _ => unreachable!(),
-            },
-            Opcode::Sto => match current_instruction.1 {
-                Operand::Address(a) => {
-                    self.mar = a;
-                    self.mdr = MemoryData::Value(self.acc);
-                    if a > 255 {
-                        self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                            ins_address: cur_ins_add,
-                            requested_address: a,
-                        });
-                        self.execution_state = ExecutionState::Stopped;
-                        self.show_assembler_info_window = true;
-                        return;
+        });
+        if let Some(marks) = &self.worksheet_marks {
+            match &marks.first_mistake {
+                Some(mistake) => ui.colored_label(
+                    MfColors::RED_400,
+                    format!("{} {}", MDI_CLOSE_CIRCLE, mistake),
+                ),
+                None => ui.colored_label(
+                    MfColors::GREEN_400,
+                    format!(
+                        "{} All {} rows are correct!",
+                        MDI_CHECK_CIRCLE, marks.expected_rows
+                    ),
+                ),
+            };
+        }
+        ui.separator();
+        let initial = expected
+            .cells(&self.worksheet.columns, format)
+            .swap_remove(0);
+        let mut changed = false;
+        egui::ScrollArea::both()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                egui::Grid::new("worksheet").striped(true).show(ui, |ui| {
+                    ui.strong("Row");
+                    for column in &self.worksheet.columns {
+                        ui.strong(column.header(format));
                     }
-                    self.memory.as_flattened_mut()[a as usize] = MemoryData::Value(self.acc);
-                }
-                _ => unreachable!(),
-            },
-            Opcode::Add => match current_instruction.1 {
-                Operand::Immediate(v) => {
-                    let (result, overflow) = self.acc.overflowing_add(v);
-                    self.acc = result;
-                    self.carry = overflow;
-                    self.zero = result == 0;
-                    self.overflow = overflow;
-                    self.sign = result & 0x8000 != 0;
-                }
-                Operand::Address(a) => {
-                    self.mar = a;
-                    if a > 255 {
-                        self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                            ins_address: cur_ins_add,
-                            requested_address: a,
-                        });
-                        self.execution_state = ExecutionState::Stopped;
-                        self.show_assembler_info_window = true;
-                        return;
+                    ui.end_row();
+                    ui.label("");
+                    for cell in initial {
+                        ui.monospace(cell);
                     }
-                    self.mdr = self.memory.as_flattened()[a as usize];
-                    match self.mdr {
-                        MemoryData::Value(v) => {
-                            let (result, overflow) = self.acc.overflowing_add(v);
-                            self.acc = result;
-                            self.carry = overflow;
-                            self.zero = result == 0;
-                            self.overflow = overflow;
-                            self.sign = result & 0x8000 != 0;
-                        }
-                        MemoryData::Instruction(_, _) => {
-                            self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                ins_address: cur_ins_add,
-                                requested_address: a,
-                            });
-                            self.execution_state = ExecutionState::Stopped;
-                            self.show_assembler_info_window = true;
+                    ui.end_row();
+                    for (row, answers) in self.worksheet.answers.iter_mut().enumerate() {
+                        ui.label((row + 1).to_string());
+                        for (column, answer) in answers.iter_mut().enumerate() {
+                            let mark = self
+                                .worksheet_marks
+                                .as_ref()
+                                .and_then(|marks| marks.cells.get(row)?.get(column).copied())
+                                .flatten();
+                            let mut edit = egui::TextEdit::singleline(answer)
+                                .font(egui::TextStyle::Monospace)
+                                .desired_width(56.0);
+                            match mark {
+                                Some(true) => edit = edit.text_color(MfColors::GREEN_400),
+                                Some(false) => edit = edit.text_color(MfColors::RED_400),
+                                None => (),
+                            }
+                            changed |= ui.add(edit).changed();
                         }
+                        ui.end_row();
                     }
-                }
-                _ => unreachable!(),
-            },
-            Opcode::Sub => match current_instruction.1 {
-                Operand::Immediate(v) => {
-                    let (result, overflow) = self.acc.overflowing_sub(v);
-                    self.acc = result;
-                    self.carry = overflow;
-                    self.zero = result == 0;
-                    self.overflow = overflow;
-                    self.sign = result & 0x8000 != 0;
-                }
-                Operand::Address(a) => {
-                    self.mar = a;
-                    if a > 255 {
-                        self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                            ins_address: cur_ins_add,
-                            requested_address: a,
-                        });
-                        self.execution_state = ExecutionState::Stopped;
-                        self.show_assembler_info_window = true;
-                        return;
-                    }
-                    self.mdr = self.memory.as_flattened()[a as usize];
-                    match self.mdr {
-                        MemoryData::Value(v) => {
-                            let (result, overflow) = self.acc.overflowing_sub(v);
-                            self.acc = result;
-                            self.carry = overflow;
-                            self.zero = result == 0;
-                            self.overflow = overflow;
-                            self.sign = result & 0x8000 != 0;
-                        }
-                        MemoryData::Instruction(_, _) => {
-                            self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                ins_address: cur_ins_add,
-                                requested_address: a,
-                            });
-                            self.execution_state = ExecutionState::Stopped;
-                            self.show_assembler_info_window = true;
-                        }
+                });
+            });
+        if changed {
+            self.worksheet_marks = None;
+        }
+    }
+
+    fn trace_table(&mut self, ui: &mut egui::Ui) {
+        /// Only the latest rows are drawn, as a long table makes every frame slow
+        const SHOWN_ROWS: usize = 500;
+
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Columns:");
+            for (column, name) in [
+                (TraceColumn::Instruction, "Instruction"),
+                (TraceColumn::Acc, "ACC"),
+                (TraceColumn::Ix, "IX"),
+                (TraceColumn::Output, "OUTPUT"),
+            ] {
+                let mut shown = self.trace_columns.contains(&column);
+                if ui.checkbox(&mut shown, name).changed() {
+                    if shown {
+                        self.trace_columns.push(column);
+                    } else {
+                        self.trace_columns.retain(|&c| c != column);
                     }
                 }
-                _ => unreachable!(),
-            },
-            Opcode::Inc => match current_instruction.1 {
-                Operand::Register(r) => match r {
-                    // Add 1 to the destination register, while preserving CF
-                    Register::Ix => {
-                        let (result, overflow) = self.ix.overflowing_add(1);
-                        self.ix = result;
-                        self.zero = result == 0;
-                        self.overflow = overflow;
-                        self.sign = result & 0x8000 != 0;
-                    }
-                    Register::Acc => {
-                        let (result, overflow) = self.acc.overflowing_add(1);
-                        self.acc = result;
-                        self.zero = result == 0;
-                        self.overflow = overflow;
-                        self.sign = result & 0x8000 != 0;
-                    }
-                },
-                _ => unreachable!(),
-            },
-            Opcode::Dec => match current_instruction.1 {
-                Operand::Register(r) => match r {
-                    // Subtract 1 from the destination register, while preserving CF
-                    Register::Ix => {
-                        let (result, overflow) = self.ix.overflowing_sub(1);
-                        self.ix = result;
-                        self.zero = result == 0;
-                        self.overflow = overflow;
-                        self.sign = result & 0x8000 != 0;
-                    }
-                    Register::Acc => {
-                        let (result, overflow) = self.acc.overflowing_sub(1);
-                        self.acc = result;
-                        self.zero = result == 0;
-                        self.overflow = overflow;
-                        self.sign = result & 0x8000 != 0;
-                    }
-                },
-                _ => unreachable!(),
-            },
-            Opcode::Jmp => match current_instruction.1 {
-                Operand::Address(a) => {
-                    if a > 255 {
-                        self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                            ins_address: cur_ins_add,
-                            requested_address: a,
-                        });
-                        self.execution_state = ExecutionState::Stopped;
-                        self.show_assembler_info_window = true;
-                        return;
+            }
+            ui.separator();
+            ui.label("Memory address:");
+            ui.add(address_drag(
+                &mut self.trace_new_address,
+                self.number_format,
+            ));
+            let column = TraceColumn::Memory(self.trace_new_address);
+            if ui
+                .add_enabled(
+                    !self.trace_columns.contains(&column),
+                    egui::Button::new("Add column"),
+                )
+                .clicked()
+            {
+                self.trace_columns.push(column);
+            }
+            let mut removed = None;
+            for column in &self.trace_columns {
+                if let TraceColumn::Memory(_) = column {
+                    if ui
+                        .small_button(format!(
+                            "{} {}",
+                            column.header(self.number_format),
+                            MDI_CLOSE
+                        ))
+                        .on_hover_text("Remove this column")
+                        .clicked()
+                    {
+                        removed = Some(*column);
                     }
-                    self.pc = a;
-                }
-                _ => unreachable!(),
-            },
-            Opcode::Cmp => match current_instruction.1 {
-                Operand::Immediate(v) => {
-                    let (result, overflow) = self.acc.overflowing_sub(v);
-                    self.carry = overflow;
-                    self.zero = result == 0;
-                    self.overflow = overflow;
-                    self.sign = result & 0x8000 != 0;
                 }
-                Operand::Address(a) => {
-                    self.mar = a;
-                    if a > 255 {
-                        self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                            ins_address: cur_ins_add,
-                            requested_address: a,
-                        });
-                        self.execution_state = ExecutionState::Stopped;
-                        self.show_assembler_info_window = true;
-                        return;
-                    }
-                    self.mdr = self.memory.as_flattened()[a as usize];
-                    match self.mdr {
-                        MemoryData::Value(v) => {
-                            let (result, overflow) = self.acc.overflowing_sub(v);
-                            self.carry = overflow;
-                            self.zero = result == 0;
-                            self.overflow = overflow;
-                            self.sign = result & 0x8000 != 0;
-                        }
-                        MemoryData::Instruction(_, _) => {
-                            self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                ins_address: cur_ins_add,
-                                requested_address: a,
-                            });
-                            self.execution_state = ExecutionState::Stopped;
-                            self.show_assembler_info_window = true;
+            }
+            self.trace_columns.retain(|&c| Some(c) != removed);
+            self.trace_columns.sort();
+        });
+        ui.horizontal(|ui| {
+            if ui
+                .button(MDI_CONTENT_COPY.to_owned() + " Copy as CSV")
+                .clicked()
+            {
+                let csv = self.trace.to_csv(&self.trace_columns, self.number_format);
+                ui.output_mut(|o| o.copied_text = csv);
+            }
+            if ui
+                .button(MDI_CONTENT_COPY.to_owned() + " Copy as Markdown")
+                .clicked()
+            {
+                let markdown = self
+                    .trace
+                    .to_markdown(&self.trace_columns, self.number_format);
+                ui.output_mut(|o| o.copied_text = markdown);
+            }
+            if ui.button(MDI_DELETE_SWEEP.to_owned() + " Clear").clicked() {
+                self.trace = Trace::default();
+            }
+        });
+        ui.separator();
+        let cells = self.trace.cells(&self.trace_columns, self.number_format);
+        if cells.len() > SHOWN_ROWS + 1 {
+            ui.colored_label(
+                MfColors::GRAY_700,
+                format!(
+                    "Showing the last {} of {} rows. Copy the table to see all of them.",
+                    SHOWN_ROWS,
+                    cells.len() - 1
+                ),
+            );
+        }
+        egui::ScrollArea::both()
+            .auto_shrink([false, false])
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                egui::Grid::new("trace_table")
+                    .striped(true)
+                    .min_col_width(40.0)
+                    .show(ui, |ui| {
+                        for column in &self.trace_columns {
+                            ui.strong(column.header(self.number_format));
                         }
-                    }
-                }
-                _ => unreachable!(),
-            },
-            Opcode::Cmi => match current_instruction.1 {
-                Operand::Address(a) => {
-                    self.mar = a;
-                    if a > 255 {
-                        self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                            ins_address: cur_ins_add,
-                            requested_address: a,
-                        });
-                        self.execution_state = ExecutionState::Stopped;
-                        self.show_assembler_info_window = true;
-                        return;
-                    }
-                    self.mdr = self.memory.as_flattened()[a as usize];
-                    match self.mdr {
-                        MemoryData::Value(v) => {
-                            self.mar = v;
-                            if v > 255 {
-                                self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                                    ins_address: cur_ins_add,
-                                    requested_address: v,
-                                });
-                                self.execution_state = ExecutionState::Stopped;
-                                self.show_assembler_info_window = true;
-                                return;
+                        ui.end_row();
+                        let skipped = cells.len().saturating_sub(SHOWN_ROWS + 1);
+                        for (index, row) in cells.iter().enumerate() {
+                            if index > 0 && index <= skipped {
+                                continue;
                             }
-                            self.mdr = self.memory.as_flattened()[v as usize];
-                            match self.mdr {
-                                MemoryData::Value(v) => {
-                                    let (result, overflow) = self.acc.overflowing_sub(v);
-                                    self.carry = overflow;
-                                    self.zero = result == 0;
-                                    self.overflow = overflow;
-                                    self.sign = result & 0x8000 != 0;
-                                }
-                                MemoryData::Instruction(_, _) => {
-                                    self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                        ins_address: cur_ins_add,
-                                        requested_address: v,
-                                    });
-                                    self.execution_state = ExecutionState::Stopped;
-                                    self.show_assembler_info_window = true;
-                                }
+                            for cell in row {
+                                ui.monospace(cell.replace('\n', "↵"));
                             }
+                            ui.end_row();
                         }
-                        MemoryData::Instruction(_, _) => {
-                            self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                ins_address: cur_ins_add,
-                                requested_address: a,
-                            });
-                            self.execution_state = ExecutionState::Stopped;
-                            self.show_assembler_info_window = true;
-                        }
-                    }
-                }
-                _ => unreachable!(),
-            },
-            Opcode::Jpe => match current_instruction.1 {
-                Operand::Address(a) => {
-                    if self.zero {
-                        if a > 255 {
-                            self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                                ins_address: cur_ins_add,
-                                requested_address: a,
-                            });
-                            self.execution_state = ExecutionState::Stopped;
-                            self.show_assembler_info_window = true;
-                            return;
-                        }
-                        self.pc = a;
-                    }
-                }
-                _ => unreachable!(),
-            },
-            Opcode::Jpn => match current_instruction.1 {
-                Operand::Address(a) => {
-                    if !self.zero {
-                        if a > 255 {
-                            self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                                ins_address: cur_ins_add,
-                                requested_address: a,
-                            });
-                            self.execution_state = ExecutionState::Stopped;
-                            self.show_assembler_info_window = true;
-                            return;
-                        }
-                        self.pc = a;
-                    }
-                }
-                _ => unreachable!(),
-            },
-            Opcode::In => {
+                    });
+            });
+    }
+
+    /// Whether the timeline can be moved, i.e. nothing is currently executing.
+    fn can_travel(&self) -> bool {
+        !matches!(
+            self.execution_state,
+            ExecutionState::Executing | ExecutionState::ExecutingAwaitingInput
+        )
+    }
+
+    /// Undoes or replays recorded steps until `position` steps are applied.
+    fn travel_to(&mut self, position: usize) {
+        self.history.seek(&mut self.machine, position);
+        self.trace.truncate(self.history.position(), &self.machine);
+        // Moving in time cancels a pending IN, whose step is undone or completed by the replay
+        if self.execution_state == ExecutionState::SteppingAwaitingInput {
+            self.execution_state = ExecutionState::Stopped;
+        }
+    }
+
+    /// Returns the address a breakpoint stops at, if it can be resolved.
+    fn breakpoint_address(&self, location: BreakpointLocation) -> Option<u16> {
+        match location {
+            BreakpointLocation::Address(a) => Some(a),
+            BreakpointLocation::Line(line) => self.program.as_ref()?.address_of_line(line),
+        }
+    }
+
+    /// Returns whether there is an enabled (`Some(true)`) or disabled breakpoint at `address`.
+    fn breakpoint_enabled_at(&self, address: u16) -> Option<bool> {
+        self.breakpoints
+            .iter()
+            .filter(|b| self.breakpoint_address(b.location) == Some(address))
+            .map(|b| b.enabled)
+            .reduce(|a, b| a || b)
+    }
+
+    /// Removes every breakpoint at the same place as `location`, or adds one if there was none.
+    fn toggle_breakpoint(&mut self, location: BreakpointLocation) {
+        let address = self.breakpoint_address(location);
+        let at_same_place: Vec<bool> = self
+            .breakpoints
+            .iter()
+            .map(|b| {
+                b.location == location
+                    || address.is_some() && self.breakpoint_address(b.location) == address
+            })
+            .collect();
+        if at_same_place.contains(&true) {
+            let mut at_same_place = at_same_place.into_iter();
+            self.breakpoints
+                .retain(|_| !at_same_place.next().unwrap_or(false));
+        } else {
+            self.breakpoints.push(Breakpoint {
+                location,
+                enabled: true,
+            });
+        }
+    }
+
+    /// Executes the next instruction while running, pausing instead if it has a breakpoint.
+    fn run_step(&mut self) {
+        let pc = self.machine.pc;
+        if self.breakpoint_skipped != Some(pc) && self.breakpoint_enabled_at(pc) == Some(true) {
+            self.execution_state = ExecutionState::Paused;
+            return;
+        }
+        self.breakpoint_skipped = None;
+        self.step();
+    }
+
+    fn step(&mut self) {
+        if self.ins_executed >= 1000 {
+            self.execution_info = Some(ExecutionInfo::TooManySteps {
+                steps: self.ins_executed,
+            });
+            self.show_assembler_info_window = true;
+        }
+        let address = self.machine.pc;
+        let fetched = self.machine.instruction_at(address).is_some();
+        if self.trace.is_empty() {
+            self.trace = Trace::new(&self.machine);
+        }
+        let step = self.history.position();
+        let outcome = self.history.step(&mut self.machine);
+        if fetched {
+            self.trace.record(step, address, &self.machine);
+        }
+        match outcome {
+            StepOutcome::Continue => (),
+            StepOutcome::AwaitingInput => {
                 if self.execution_state == ExecutionState::Executing {
                     self.execution_state = ExecutionState::ExecutingAwaitingInput;
                 } else {
                     self.execution_state = ExecutionState::SteppingAwaitingInput;
                 }
             }
-            Opcode::Out => {
-                if let Some(c) = std::char::from_u32(self.acc as u32) {
-                    self.output.push(c);
-                } else {
-                    self.output.push('�');
-                }
-            }
-            Opcode::End => {
-                self.execution_info = Some(ExecutionInfo::ExecutionTerminated {
-                    ins_address: cur_ins_add,
-                });
+            StepOutcome::Stopped(info) => {
+                self.execution_info = Some(info);
                 self.execution_state = ExecutionState::Stopped;
                 self.show_assembler_info_window = true;
             }
-            Opcode::And => match current_instruction.1 {
-                Operand::Immediate(v) => {
-                    self.acc &= v;
-                    self.zero = self.acc == 0;
-                    self.sign = self.acc & 0x8000 != 0;
-                }
-                Operand::Address(a) => {
-                    self.mar = a;
-                    if a > 255 {
-                        self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                            ins_address: cur_ins_add,
-                            requested_address: a,
-                        });
-                        self.execution_state = ExecutionState::Stopped;
-                        self.show_assembler_info_window = true;
-                        return;
-                    }
-                    self.mdr = self.memory.as_flattened()[a as usize];
-                    match self.mdr {
-                        MemoryData::Value(v) => {
-                            self.acc &= v;
-                            self.zero = self.acc == 0;
-                            self.sign = self.acc & 0x8000 != 0;
-                        }
-                        MemoryData::Instruction(_, _) => {
-                            self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                ins_address: cur_ins_add,
-                                requested_address: a,
-                            });
-                            self.execution_state = ExecutionState::Stopped;
-                            self.show_assembler_info_window = true;
-                        }
-                    }
-                }
-                _ => unimplemented!(),
-            },
-            Opcode::Xor => match current_instruction.1 {
-                Operand::Immediate(v) => {
-                    self.acc ^= v;
-                    self.zero = self.acc == 0;
-                    self.sign = self.acc & 0x8000 != 0;
-                }
-                Operand::Address(a) => {
-                    self.mar = a;
-                    if a > 255 {
-                        self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                            ins_address: cur_ins_add,
-                            requested_address: a,
-                        });
-                        self.execution_state = ExecutionState::Stopped;
-                        self.show_assembler_info_window = true;
-                        return;
-                    }
-                    self.mdr = self.memory.as_flattened()[a as usize];
-                    match self.mdr {
-                        MemoryData::Value(v) => {
-                            self.acc ^= v;
-                            self.zero = self.acc == 0;
-                            self.sign = self.acc & 0x8000 != 0;
-                        }
-                        MemoryData::Instruction(_, _) => {
-                            self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                ins_address: cur_ins_add,
-                                requested_address: a,
-                            });
-                            self.execution_state = ExecutionState::Stopped;
-                            self.show_assembler_info_window = true;
-                        }
-                    }
-                }
-                _ => unimplemented!(),
-            },
-            Opcode::Or => match current_instruction.1 {
-                Operand::Immediate(v) => {
-                    self.acc |= v;
-                    self.zero = self.acc == 0;
-                    self.sign = self.acc & 0x8000 != 0;
-                }
-                Operand::Address(a) => {
-                    self.mar = a;
-                    if a > 255 {
-                        self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                            ins_address: cur_ins_add,
-                            requested_address: a,
-                        });
-                        self.execution_state = ExecutionState::Stopped;
-                        self.show_assembler_info_window = true;
-                        return;
-                    }
-                    self.mdr = self.memory.as_flattened()[a as usize];
-                    match self.mdr {
-                        MemoryData::Value(v) => {
-                            self.acc |= v;
-                            self.zero = self.acc == 0;
-                            self.sign = self.acc & 0x8000 != 0;
-                        }
-                        MemoryData::Instruction(_, _) => {
-                            self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                ins_address: cur_ins_add,
-                                requested_address: a,
-                            });
-                            self.execution_state = ExecutionState::Stopped;
-                            self.show_assembler_info_window = true;
-                        }
-                    }
-                }
-                _ => unimplemented!(),
-            },
-            Opcode::Lsl => match current_instruction.1 {
-                Operand::Immediate(v) => {
-                    self.acc <<= v;
-                    self.zero = self.acc == 0;
-                    self.sign = self.acc & 0x8000 != 0;
-                }
-                Operand::Address(a) => {
-                    self.mar = a;
-                    if a > 255 {
-                        self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                            ins_address: cur_ins_add,
-                            requested_address: a,
-                        });
-                        self.execution_state = ExecutionState::Stopped;
-                        self.show_assembler_info_window = true;
-                        return;
-                    }
-                    self.mdr = self.memory.as_flattened()[a as usize];
-                    match self.mdr {
-                        MemoryData::Value(v) => {
-                            self.acc <<= v;
-                            self.zero = self.acc == 0;
-                            self.sign = self.acc & 0x8000 != 0;
-                        }
-                        MemoryData::Instruction(_, _) => {
-                            self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                ins_address: cur_ins_add,
-                                requested_address: a,
-                            });
-                            self.execution_state = ExecutionState::Stopped;
-                            self.show_assembler_info_window = true;
-                        }
-                    }
-                }
-                _ => unimplemented!(),
-            },
-            Opcode::Lsr => match current_instruction.1 {
-                Operand::Immediate(v) => {
-                    self.acc >>= v;
-                    self.zero = self.acc == 0;
-                    self.sign = self.acc & 0x8000 != 0;
-                }
-                Operand::Address(a) => {
-                    self.mar = a;
-                    if a > 255 {
-                        self.execution_info = Some(ExecutionInfo::AddressNotInMemory {
-                            ins_address: cur_ins_add,
-                            requested_address: a,
-                        });
-                        self.execution_state = ExecutionState::Stopped;
-                        self.show_assembler_info_window = true;
-                        return;
-                    }
-                    self.mdr = self.memory.as_flattened()[a as usize];
-                    match self.mdr {
-                        MemoryData::Value(v) => {
-                            self.acc >>= v;
-                            self.zero = self.acc == 0;
-                            self.sign = self.acc & 0x8000 != 0;
-                        }
-                        MemoryData::Instruction(_, _) => {
-                            self.execution_info = Some(ExecutionInfo::InvalidLoad {
-                                ins_address: cur_ins_add,
-                                requested_address: a,
-                            });
-                            self.execution_state = ExecutionState::Stopped;
-                            self.show_assembler_info_window = true;
-                        }
-                    }
-                }
-                _ => unimplemented!(),
-            },
-            Opcode::Data(_) => unreachable!(),
         }
     }
 }
 
+/// Returns the printable ASCII character `value` is the code of, if any.
+fn ascii_char(value: u16) -> Option<char> {
+    u8::try_from(value)
+        .ok()
+        .filter(|b| b.is_ascii_graphic() || *b == b' ')
+        .map(char::from)
+}
+
+/// A drag value for a 16-bit value, shown and typed in `format`.
+fn value_drag(value: &mut u16, format: NumberFormat) -> egui::DragValue<'_> {
+    egui::DragValue::new(value)
+        .speed(1.0)
+        .range(0..=0xffff)
+        .custom_formatter(move |n, _| format.format(n as u16))
+        .custom_parser(move |s| format.parse(s).map(f64::from))
+}
+
+/// A drag value for a memory address, shown and typed in `format`.
+fn address_drag(address: &mut u16, format: NumberFormat) -> egui::DragValue<'_> {
+    egui::DragValue::new(address)
+        .speed(1.0)
+        .range(0..=255)
+        .custom_formatter(move |n, _| format.format_address(n as u16))
+        .custom_parser(move |s| format.parse(s).filter(|&a| a <= 255).map(f64::from))
+}
+
+/// Lays out source code with syntax highlighting, underlining where each diagnostic applies.
+fn source_layout_job(ui: &egui::Ui, source: &str, diagnostics: &[Diagnostic]) -> LayoutJob {
+    let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+    let text_color = ui.visuals().widgets.inactive.text_color();
+    let mut job = LayoutJob::default();
+    for (line_index, line) in source.split('\n').enumerate() {
+        if line_index > 0 {
+            job.append("\n", 0.0, TextFormat::simple(font_id.clone(), text_color));
+        }
+        let highlights = highlight_line(line);
+        let underlines: Vec<(std::ops::Range<usize>, bool)> = diagnostics
+            .iter()
+            .filter(|d| d.span().line == line_index + 1)
+            .map(|d| {
+                let span = d.span();
+                (
+                    byte_index(line, span.start)..byte_index(line, span.end),
+                    d.is_error(),
+                )
+            })
+            .collect();
+        let mut boundaries: Vec<usize> = highlights
+            .iter()
+            .map(|(range, _)| range)
+            .chain(underlines.iter().map(|(range, _)| range))
+            .flat_map(|range| [range.start, range.end])
+            .chain([0, line.len()])
+            .collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+        for piece in boundaries.windows(2) {
+            let (start, end) = (piece[0], piece[1]);
+            let color = highlights
+                .iter()
+                .find(|(range, _)| range.start <= start && end <= range.end)
+                .map_or(text_color, |&(_, highlight)| highlight_color(highlight));
+            // Errors are underlined in preference to warnings
+            let underline = underlines
+                .iter()
+                .filter(|(range, _)| range.start <= start && end <= range.end)
+                .map(|&(_, is_error)| is_error)
+                .max()
+                .map_or(egui::Stroke::NONE, |is_error| {
+                    let color = if is_error {
+                        MfColors::RED_400
+                    } else {
+                        MfColors::YELLOW_400
+                    };
+                    egui::Stroke::from((1.5, color))
+                });
+            job.append(
+                &line[start..end],
+                0.0,
+                TextFormat {
+                    font_id: font_id.clone(),
+                    color,
+                    underline,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+    job
+}
+
+fn highlight_color(highlight: Highlight) -> Color32 {
+    match highlight {
+        Highlight::Label => MfColors::BLUE_400,
+        Highlight::Symbol => MfColors::BLUE_300,
+        Highlight::Mnemonic => MfColors::PURPLE_400,
+        Highlight::Directive => MfColors::PURPLE_300,
+        Highlight::Register => MfColors::RED_300,
+        Highlight::Immediate => MfColors::GREEN_400,
+        Highlight::Number => MfColors::GREEN_300,
+        Highlight::Hexadecimal => MfColors::ORANGE_400,
+        Highlight::Binary => MfColors::YELLOW_600,
+        Highlight::Character | Highlight::String => MfColors::ORANGE_300,
+        Highlight::Comment => MfColors::GRAY_500,
+    }
+}
+
+/// Returns the screen rectangle of each source line, merging the rows it wraps onto.
+fn line_rects(galley: &Galley, galley_pos: egui::Pos2) -> Vec<egui::Rect> {
+    let mut rects = Vec::new();
+    let mut current = egui::Rect::NOTHING;
+    for row in &galley.rows {
+        current = current.union(row.rect.translate(galley_pos.to_vec2()));
+        if row.ends_with_newline {
+            rects.push(current);
+            current = egui::Rect::NOTHING;
+        }
+    }
+    rects.push(current);
+    rects
+}
+
+/// Returns the byte index of the character at char index `index`, or the length of `text` if
+/// it is past the end.
+fn byte_index(text: &str, index: usize) -> usize {
+    text.char_indices()
+        .nth(index)
+        .map_or(text.len(), |(i, _)| i)
+}
+
+/// Returns the character index at which 1-based line `line` of `source` starts.
+fn line_start_char_index(source: &str, line: usize) -> usize {
+    source
+        .split('\n')
+        .take(line.saturating_sub(1))
+        .map(|l| l.chars().count() + 1)
+        .sum()
+}
+
 pub struct CaieAsmApp {
     tree: DockState<String>,
     context: AppContext,
@@ -1043,40 +1959,65 @@ impl Default for CaieAsmApp {
         let [a, b] = tree.main_surface_mut().split_right(
             NodeIndex::root(),
             0.3,
-            vec!["Registers".to_owned()],
+            vec!["Registers".to_owned(), "Symbols".to_owned()],
+        );
+        let [_, _] = tree.main_surface_mut().split_below(
+            a,
+            0.7,
+            vec![
+                "Console".to_owned(),
+                "Breakpoints".to_owned(),
+                "Timeline".to_owned(),
+                "Trace Table".to_owned(),
+                "Worksheet".to_owned(),
+            ],
+        );
+        let [_, _] = tree.main_surface_mut().split_below(
+            b,
+            0.3,
+            vec![
+                "Memory".to_owned(),
+                "Control Flow".to_owned(),
+                "Tests".to_owned(),
+            ],
         );
-        let [_, _] = tree
-            .main_surface_mut()
-            .split_below(a, 0.7, vec!["Console".to_owned()]);
-        let [_, _] = tree
-            .main_surface_mut()
-            .split_below(b, 0.3, vec!["Memory".to_owned()]);
 
         Self {
             tree,
             context: AppContext {
                 source_code: DEFAULT_PROGRAM.to_string(),
-                memory: [[MemoryData::Value(0); 16]; 16],
+                machine: Machine::default(),
                 program_load_location: 0,
-                pc: 0,
-                cir: (Opcode::End, Operand::Empty),
-                ix: 0,
-                mdr: MemoryData::Value(0),
-                mar: 0,
-                acc: 0,
-                carry: false,
-                zero: false,
-                overflow: false,
-                sign: false,
-                output: String::new(),
                 input: String::new(),
                 execution_state: ExecutionState::Stopped,
                 highlight_pc_location: true,
                 pc_highlight_color: [236, 111, 39],
-                assembler_error: None,
+                assembler_diagnostics: Vec::new(),
+                diagnosed_source: String::new(),
                 show_assembler_error_window: false,
+                jump_to_span: None,
+                program: None,
+                assembled_source: String::new(),
+                last_scrolled_pc: None,
+                breakpoints: Vec::new(),
+                breakpoint_skipped: None,
+                history: History::default(),
+                trace: Trace::default(),
+                trace_columns: TraceColumn::defaults(),
+                trace_new_address: 0,
+                worksheet: Worksheet::default(),
+                worksheet_marks: None,
+                worksheet_trace: None,
+                test_cases: Vec::new(),
+                encoding: None,
+                instruction_set: InstructionSet::default(),
+                completion: None,
+                test_results: Vec::new(),
+                test_error: None,
                 show_assembler_info_window: false,
-                value_as_hex: true,
+                number_format: NumberFormat::Hexadecimal,
+                show_ascii_column: false,
+                highlighted_address: None,
                 clock_speed: 4,
                 execution_info: None,
                 last_step_time: Local::now(),
@@ -1104,13 +2045,13 @@ impl eframe::App for CaieAsmApp {
         if self.context.execution_state == ExecutionState::Executing {
             if self.context.clock_speed == 0 {
                 self.context.last_step_time = Local::now();
-                self.context.step();
+                self.context.run_step();
                 self.context.ins_executed += 1;
             } else {
                 let now = Local::now();
                 let elapsed = now - self.context.last_step_time;
                 if elapsed.num_milliseconds() as f32 >= 1000. / (self.context.clock_speed) as f32 {
-                    self.context.step();
+                    self.context.run_step();
                     self.context.last_step_time = now;
                     self.context.ins_executed += 1;
                 }
@@ -1141,8 +2082,20 @@ impl eframe::App for CaieAsmApp {
                     ui.radio_value(&mut self.context.clock_speed, 32, "32 Hz");
                     ui.radio_value(&mut self.context.clock_speed, 0, "Unlimited");
                 });
+                ui.menu_button(MDI_CHIP.to_owned() + " Instruction set", |ui| {
+                    self.context.instruction_set_settings(ui)
+                });
+                ui.menu_button(MDI_CODE_BRACES.to_owned() + " Machine code", |ui| {
+                    self.context.machine_code_settings(ui)
+                });
                 ui.separator();
 
+                if self.context.execution_state == ExecutionState::Paused
+                    && ui.button(MDI_PLAY.to_owned() + " Continue").clicked()
+                {
+                    self.context.execution_state = ExecutionState::Executing;
+                    self.context.breakpoint_skipped = Some(self.context.machine.pc);
+                }
                 if self.context.execution_state != ExecutionState::Stopped {
                     if ui.button(MDI_STOP.to_owned() + " Terminate").clicked() {
                         self.context.execution_state = ExecutionState::Stopped;
@@ -1150,6 +2103,16 @@ impl eframe::App for CaieAsmApp {
                 } else if ui.button(MDI_PLAY.to_owned() + " Execute").clicked() {
                     self.context.execution_state = ExecutionState::Executing;
                 }
+                if ui
+                    .add_enabled(
+                        self.context.can_travel()
+                            && self.context.history.position() > self.context.history.first(),
+                        egui::Button::new(MDI_STEP_BACKWARD.to_owned() + " Step back"),
+                    )
+                    .clicked()
+                {
+                    self.context.travel_to(self.context.history.position() - 1);
+                }
                 if ui.button(MDI_STEP_FORWARD.to_owned() + " Step").clicked() {
                     self.context.step();
                 }
@@ -1159,18 +2122,14 @@ impl eframe::App for CaieAsmApp {
                     .clicked()
                 {
                     self.context.execution_state = ExecutionState::Stopped;
-                    self.context.pc = 0;
-                    self.context.cir = (Opcode::End, Operand::Empty);
-                    self.context.ix = 0;
-                    self.context.mdr = MemoryData::Value(0);
-                    self.context.mar = 0;
-                    self.context.acc = 0;
-                    self.context.carry = false;
-                    self.context.zero = false;
-                    self.context.overflow = false;
-                    self.context.sign = false;
-                    self.context.output.clear();
-                    self.context.memory = [[MemoryData::Value(0); 16]; 16];
+                    self.context.machine = Machine {
+                        instruction_set: self.context.instruction_set,
+                        ..Default::default()
+                    };
+                    self.context.program = None;
+                    self.context.worksheet_trace = None;
+                    self.context.history.clear();
+                    self.context.trace = Trace::default();
                 }
 
                 ui.separator();
@@ -1207,8 +2166,11 @@ impl eframe::App for CaieAsmApp {
                 .show(ctx, &mut self.context);
         });
 
-        if self.context.show_assembler_info_window && self.context.execution_info.is_some() {
-            let (title, icon, color, summary, content) = match self.context.execution_info.as_ref().unwrap() {
+        if let (true, Some(info)) = (
+            self.context.show_assembler_info_window,
+            &self.context.execution_info,
+        ) {
+            let (title, icon, color, summary, content) = match info {
                 ExecutionInfo::ExecutionTerminated { ins_address } => (
                     "Execution terminated",
                     MDI_OCTAGON,
@@ -1259,6 +2221,26 @@ impl eframe::App for CaieAsmApp {
                         ins_address, ins_address, requested_address, requested_address
                     )
                 ),
+                ExecutionInfo::InvalidInstruction { ins_address } => (
+                    "Aborted",
+                    MDI_CLOSE_OCTAGON,
+                    MfColors::RED_500,
+                    "Invalid instruction.",
+                    format!(
+                        "Execution aborted at address {:X}₁₆ = {}₁₀, because the instruction there has an operand that its opcode does not accept.",
+                        ins_address, ins_address
+                    )
+                ),
+                ExecutionInfo::UnsupportedInstruction { ins_address, instruction_set } => (
+                    "Aborted",
+                    MDI_CLOSE_OCTAGON,
+                    MfColors::RED_500,
+                    "Unsupported instruction.",
+                    format!(
+                        "Execution aborted at address {:X}₁₆ = {}₁₀, because the instruction there is not in the {} instruction set.",
+                        ins_address, ins_address, instruction_set.name()
+                    )
+                ),
             };
             egui::Window::new(title)
                 .open(&mut self.context.show_assembler_info_window)
@@ -1281,29 +2263,62 @@ impl eframe::App for CaieAsmApp {
                 });
         }
 
-        egui::Window::new("Assembler error")
+        egui::Window::new("Assembler diagnostics")
             .open(&mut self.context.show_assembler_error_window)
             .resizable(false)
             .collapsible(false)
             .show(ctx, |ui| {
-                if let Some(e) = &self.context.assembler_error {
-                    ui.with_layout(
-                        egui::Layout::top_down_justified(egui::Align::Center),
-                        |ui| {
+                let diagnostics = &self.context.assembler_diagnostics;
+                let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+                let warnings = diagnostics.len() - errors;
+                ui.with_layout(
+                    egui::Layout::top_down_justified(egui::Align::Center),
+                    |ui| {
+                        if errors > 0 {
                             ui.label(
                                 RichText::new(MDI_PACKAGE_VARIANT_CLOSED_REMOVE)
                                     .color(MfColors::RED_500)
                                     .font(FontId::proportional(32.0)),
                             );
-                            ui.label("The assembler reported an error.");
-                            ui.separator();
-                        },
-                    );
-                    ui.label(e.to_string());
-                } else {
-                    ui.label("The assembler is alright – no error found.");
-                    ui.label("This is a bug. Please report this to Micfong.");
-                }
+                            ui.label(format!(
+                                "The assembler reported {} error(s) and {} warning(s).",
+                                errors, warnings
+                            ));
+                        } else {
+                            ui.label(
+                                RichText::new(MDI_ALERT)
+                                    .color(MfColors::YELLOW_500)
+                                    .font(FontId::proportional(32.0)),
+                            );
+                            ui.label(format!(
+                                "The program was loaded, but the assembler reported {} warning(s).",
+                                warnings
+                            ));
+                        }
+                        ui.separator();
+                    },
+                );
+                ui.colored_label(MfColors::GRAY_700, "Click on a message to go to its line.");
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        for diagnostic in diagnostics {
+                            let color = if diagnostic.is_error() {
+                                MfColors::RED_400
+                            } else {
+                                MfColors::YELLOW_400
+                            };
+                            if ui
+                                .selectable_label(
+                                    false,
+                                    RichText::new(diagnostic.to_string()).color(color),
+                                )
+                                .clicked()
+                            {
+                                self.context.jump_to_span = Some(diagnostic.span());
+                            }
+                        }
+                    });
             });
 
         egui::Window::new("Export")
@@ -1364,3 +2379,23 @@ impl eframe::App for CaieAsmApp {
             });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exported_state_can_be_imported() {
+        let mut app = CaieAsmApp::default();
+        app.context.encoding = Some(Encoding::default());
+        app.context.machine.encoding = Some(Encoding::default());
+        app.context.instruction_set = InstructionSet::Cambridge9608;
+        app.context.machine.instruction_set = InstructionSet::ExamOnly;
+
+        let exported = serde_json::to_string(&app.context).unwrap();
+        let imported: AppContext = serde_json::from_str(&exported).unwrap();
+        assert_eq!(imported.instruction_set, InstructionSet::Cambridge9608);
+        assert_eq!(imported.machine.instruction_set, InstructionSet::ExamOnly);
+        assert!(imported.machine.encoding.is_some());
+    }
+}
@@ -1,362 +1,1072 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+};
 
-use crate::{AssemblerError, Opcode, Operand};
+use serde::{Deserialize, Serialize};
 
-pub fn assemble(source: &str) -> Result<Vec<(Opcode, Operand)>, AssemblerError> {
-    let lines = source.lines();
-    let mut symbol_table: HashMap<&str, usize> = HashMap::new();
-    let mut memory_offset = 0;
-    let mut result = Vec::new();
+use crate::{
+    encoding::{Encoding, OperandKind},
+    expression::{
+        character_literal_len, evaluate, starts_with_immediate, symbol_names, ExpressionError,
+        Symbols, Value,
+    },
+    instruction_set::{opcode_of, InstructionSet},
+    AssemblerError, AssemblerWarning, Diagnostic, MemoryData, Opcode, Operand, Register, Span,
+};
 
-    // First pass: create symbol table
-    for line in lines.clone() {
-        let line = line.split("//").next().unwrap_or("").trim();
-        let mut parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.is_empty() {
-            continue;
+/// An assembled program, ready to be loaded into memory at `load_address`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Program {
+    /// The memory words, in the order they are loaded starting from `load_address`.
+    pub words: Vec<MemoryData>,
+    /// Every label in the source, with the absolute address it refers to.
+    pub symbols: BTreeMap<String, u16>,
+    /// Where each label is defined.
+    #[serde(default)]
+    pub definitions: BTreeMap<String, LabelDefinition>,
+    /// Every constant defined with `EQU`, with its value.
+    #[serde(default)]
+    pub constants: BTreeMap<String, u16>,
+    /// Every use of a label or constant, in source order.
+    #[serde(default)]
+    pub references: Vec<Reference>,
+    /// Indices into `words` of the words whose operand (or value) refers to a label, and so had
+    /// `load_address` added to it.
+    #[serde(default)]
+    pub relocations: Vec<usize>,
+    /// Ranges of indices into `words` of the words skipped over by `ORG`. They are zero, but are
+    /// not written to memory when the program is loaded.
+    #[serde(default)]
+    pub gaps: Vec<Range<usize>>,
+    /// The 1-based source line each word was assembled from, indexed like `words`.
+    pub source_lines: Vec<usize>,
+    /// The span of the instruction (opcode and operands) each word was assembled from, indexed
+    /// like `words`.
+    #[serde(default)]
+    pub spans: Vec<Span>,
+    pub load_address: u16,
+    /// Warnings reported while assembling the program, to which the app adds those of
+    /// [`crate::lint::lint`].
+    pub warnings: Vec<Diagnostic>,
+    /// The layout instructions are stored with, if they have been encoded as machine code.
+    #[serde(default)]
+    pub encoding: Option<Encoding>,
+    /// The instruction set the program was assembled for.
+    #[serde(default)]
+    pub instruction_set: InstructionSet,
+}
+
+impl Program {
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Returns whether the word at `index` was skipped over by `ORG`.
+    pub fn is_gap(&self, index: usize) -> bool {
+        self.gaps.iter().any(|gap| gap.contains(&index))
+    }
+
+    /// Returns the address execution starts at: the first word, or the address an `ORG` at the
+    /// start of the program moves to.
+    pub fn entry_address(&self) -> u16 {
+        let offset = self.gaps.iter().fold(
+            0,
+            |offset, gap| if gap.start == offset { gap.end } else { offset },
+        );
+        self.load_address + offset as u16
+    }
+
+    /// Returns the source line that produced the word at `address`, if it belongs to the program.
+    pub fn line_of(&self, address: u16) -> Option<usize> {
+        let offset = address.checked_sub(self.load_address)?;
+        self.source_lines.get(offset as usize).copied()
+    }
+
+    /// Returns the address of the word assembled from source line `line`, if it produced one.
+    pub fn address_of_line(&self, line: usize) -> Option<u16> {
+        let offset = self.source_lines.iter().position(|&l| l == line)?;
+        Some(self.load_address + offset as u16)
+    }
+
+    /// Returns the address a label refers to.
+    pub fn address_of(&self, label: &str) -> Option<u16> {
+        self.symbols.get(label).copied()
+    }
+
+    /// Replaces every instruction with its machine code in `encoding`.
+    ///
+    /// Fails if the layout is invalid or an operand does not fit in the operand field, reporting
+    /// every such instruction.
+    pub fn encode(&mut self, encoding: &Encoding) -> Result<(), Vec<Diagnostic>> {
+        if let Err(reason) = encoding.validate() {
+            return Err(vec![Diagnostic::Error {
+                span: Span {
+                    line: 1,
+                    start: 0,
+                    end: 0,
+                },
+                error: AssemblerError::InvalidEncoding { reason },
+            }]);
         }
-        if parts[0].ends_with(':') {
-            symbol_table.insert(parts[0].trim_end_matches(":"), memory_offset);
-            parts.remove(0);
+        let mut diagnostics = Vec::new();
+        for (i, word) in self.words.iter_mut().enumerate() {
+            let MemoryData::Instruction(opcode, operand) = *word else {
+                continue;
+            };
+            match encoding.encode(opcode, operand) {
+                Some(code) => *word = MemoryData::Value(code),
+                None => {
+                    let span = self.spans[i];
+                    diagnostics.push(Diagnostic::Error {
+                        span,
+                        error: AssemblerError::OperandTooLarge {
+                            line_index: span.line,
+                            operand,
+                            operand_bits: encoding.operand_bits(),
+                        },
+                    })
+                }
+            }
         }
-        if !parts.is_empty() {
-            memory_offset += 1
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
         }
+        self.encoding = Some(encoding.clone());
+        Ok(())
     }
+}
 
-    // Second pass: assemble
-    for (line_index, line) in lines.enumerate() {
-        let line = line.split("//").next().unwrap_or("").trim();
-        let mut parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.is_empty() {
-            continue;
-        }
-        if parts[0].ends_with(':') {
-            parts.remove(0);
+/// Where a label is defined, and whether it names an instruction or data.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct LabelDefinition {
+    /// The span of the label, including its colon.
+    pub span: Span,
+    pub is_code: bool,
+}
+
+/// A use of a label or constant in an operand.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Reference {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A whitespace-separated word of source code, or a string literal.
+pub(crate) struct Token<'a> {
+    pub text: &'a str,
+    /// Byte offset of the token in its line.
+    pub offset: usize,
+    pub span: Span,
+}
+
+/// Splits the code part of a line, i.e. everything before a `//` that is not in a string or
+/// character literal, into tokens.
+///
+/// A token starting with `"` is a string literal, which may contain whitespace. Character
+/// literals, such as `' '` or `'"'`, may appear anywhere in a token.
+pub(crate) fn tokenize(line: &str, line_number: usize) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut token_start = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    // Byte index of the end of the character literal being read
+    let mut literal_end = 0;
+    let mut code_end = line.len();
+    let mut column = 0;
+    for (i, c) in line.char_indices() {
+        if i < literal_end {
+            // Part of the token, whatever it is
+        } else if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => (),
+            }
+        } else if line[i..].starts_with("//") {
+            code_end = i;
+            break;
+        } else if c.is_whitespace() {
+            if let Some((start, start_column)) = token_start.take() {
+                tokens.push(Token {
+                    text: &line[start..i],
+                    offset: start,
+                    span: Span {
+                        line: line_number,
+                        start: start_column,
+                        end: column,
+                    },
+                });
+            }
+        } else {
+            in_string = token_start.is_none() && c == '"';
+            token_start.get_or_insert((i, column));
+            if c == '\'' {
+                literal_end = i + 1 + character_literal_len(&line[i + 1..]);
+            }
         }
-        if parts.is_empty() {
-            continue;
+        column += 1;
+    }
+    if let Some((start, start_column)) = token_start {
+        tokens.push(Token {
+            text: &line[start..code_end],
+            offset: start,
+            span: Span {
+                line: line_number,
+                start: start_column,
+                end: column,
+            },
+        });
+    }
+    tokens
+}
+
+/// Records where the labels and constants in an operand token are used.
+fn collect_references<'a>(token: &Token<'a>, references: &mut Vec<(&'a str, Span)>) {
+    for (offset, name) in symbol_names(token.text) {
+        let start = token.span.start + token.text[..offset].chars().count();
+        references.push((
+            name,
+            Span {
+                line: token.span.line,
+                start,
+                end: start + name.chars().count(),
+            },
+        ));
+    }
+}
+
+/// Parses a string literal such as `"Hi\n"` into one word per character.
+///
+/// The escapes `\"`, `\\`, `\n`, `\t` and `\0` are understood.
+fn parse_string(text: &str) -> Option<Vec<u16>> {
+    let mut chars = text.strip_prefix('"')?.chars();
+    let mut words = Vec::new();
+    loop {
+        let c = match chars.next()? {
+            '"' => break,
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                '0' => '\0',
+                c @ ('"' | '\\') => c,
+                _ => return None,
+            },
+            c => c,
+        };
+        words.push(u16::try_from(u32::from(c)).ok()?);
+    }
+    chars.next().is_none().then_some(words)
+}
+
+/// Reads the single operand of a directive, e.g. the `10` of `DS 10`.
+///
+/// The operand is an expression, which may only use labels defined before the directive.
+fn directive_operand(
+    parts: &[Token<'_>],
+    symbols: &Symbols<'_>,
+    load_address: u16,
+) -> Result<(u16, Span), Diagnostic> {
+    let directive = &parts[0];
+    match parts {
+        [_] => Err(Diagnostic::Error {
+            span: directive.span,
+            error: AssemblerError::MissingDirectiveOperand {
+                line_index: directive.span.line,
+                directive: directive.text.to_string(),
+            },
+        }),
+        [_, operand] => {
+            let text = operand.text.strip_prefix('#').unwrap_or(operand.text);
+            match number_operand(operand.text, text, operand.span.line, symbols, load_address) {
+                Ok((value, _)) => Ok((value, operand.span)),
+                Err(error) => Err(Diagnostic::Error {
+                    span: operand.span,
+                    error,
+                }),
+            }
         }
-        if parts.len() > 2 {
-            return Err(AssemblerError::TooManyOperands {
-                line_index: line_index + 1,
+        _ => Err(Diagnostic::Error {
+            span: Span {
+                end: parts[parts.len() - 1].span.end,
+                ..parts[2].span
+            },
+            error: AssemblerError::TooManyOperands {
+                line_index: directive.span.line,
                 operands_found: parts.len() - 1,
-            });
+            },
+        }),
+    }
+}
+
+/// What a line of source code produces, as worked out by the first pass.
+enum Statement<'a> {
+    /// An instruction or a data value, assembled in the second pass.
+    Instruction(&'a [Token<'a>]),
+    /// Words whose values are already known: the characters of a string literal, or the zeros
+    /// reserved by `DS`.
+    Words(Span, Vec<u16>),
+    /// Words skipped over by `ORG`.
+    Gap(Span, usize),
+}
+
+/// Assembles `source` into a program to be loaded at `load_address`.
+///
+/// Labels are relative to the first word, so operands that use them are offset by
+/// `load_address` and listed in [`Program::relocations`]. Numeric addresses are absolute. An
+/// operand's expression can be prefixed with `@` to never relocate it, or `~` to always do so,
+/// e.g. `LDD ~5` loads from the sixth word of the program.
+///
+/// Assembly does not stop at the first error: every error and warning found is returned, sorted
+/// by position. Warnings of a successful assembly are kept in [`Program::warnings`].
+///
+/// Besides instructions and data values, lines may hold these directives:
+///
+/// - `ORG address` continues the program at an absolute address. Memory in the gap is left as it
+///   is when the program is loaded, and a program that starts with `ORG` starts executing at
+///   that address, see [`Program::entry_address`].
+/// - `NAME EQU value` defines a constant, used as `NAME` for an address or `#NAME` for a number.
+/// - `DS count` reserves `count` words of zeros.
+/// - A string literal such as `"Hello"` stores one word per character.
+pub fn assemble(source: &str, load_address: u16) -> Result<Program, Vec<Diagnostic>> {
+    assemble_for(source, load_address, InstructionSet::default())
+}
+
+/// Like [`assemble`], but only accepts the instruction forms in `instruction_set`.
+pub fn assemble_for(
+    source: &str,
+    load_address: u16,
+    instruction_set: InstructionSet,
+) -> Result<Program, Vec<Diagnostic>> {
+    let lines: Vec<Vec<Token<'_>>> = source
+        .lines()
+        .enumerate()
+        .map(|(line_index, line)| tokenize(line, line_index + 1))
+        .collect();
+    let mut symbol_table: HashMap<&str, usize> = HashMap::new();
+    let mut constants: HashMap<&str, u16> = HashMap::new();
+    let mut label_lengths: HashMap<&str, usize> = HashMap::new();
+    let mut definitions: BTreeMap<String, LabelDefinition> = BTreeMap::new();
+    // Labels waiting for the statement they name, to record its length and kind
+    let mut pending_labels = Vec::new();
+    let mut label_lines: HashMap<&str, usize> = HashMap::new();
+    let mut statements = Vec::new();
+    let mut references = Vec::new();
+    let mut memory_offset = 0;
+    let mut diagnostics = Vec::new();
+    let mut result = Vec::new();
+    let mut word_spans = Vec::new();
+    let mut gaps = Vec::new();
+
+    // First pass: create symbol table and work out directives
+    for tokens in &lines {
+        let mut parts = tokens.as_slice();
+        // A label, or the name of a constant defined by EQU
+        let mut name = None;
+        if let Some(label) = parts.first().filter(|t| t.text.ends_with(':')) {
+            name = Some((label.text.trim_end_matches(':'), label.span));
+            parts = &parts[1..];
+        } else if parts.get(1).is_some_and(|t| t.text == "EQU") {
+            name = Some((parts[0].text, parts[0].span));
+            parts = &parts[1..];
         }
-        let opcode = match Opcode::try_from(parts[0]) {
-            Ok(opcode) => opcode,
-            Err(_) => {
-                return Err(AssemblerError::UnknownOpcode {
-                    line_index: line_index + 1,
-                    opcode: parts[0].to_string(),
-                })
+        if let Some((name, span)) = name {
+            if let Some(first_line) = label_lines.insert(name, span.line) {
+                diagnostics.push(Diagnostic::Warning {
+                    span,
+                    warning: AssemblerWarning::DuplicateLabel {
+                        line_index: span.line,
+                        label: name.to_string(),
+                        first_line,
+                    },
+                });
             }
-        };
-        if parts.len() == 1 {
-            match opcode {
-                Opcode::In => {
-                    result.push((opcode, Operand::Empty));
-                }
-                Opcode::Out => {
-                    result.push((opcode, Operand::Empty));
-                }
-                Opcode::End => {
-                    result.push((opcode, Operand::Empty));
-                }
-                Opcode::Data(v) => {
-                    result.push((opcode, Operand::Immediate(v)));
-                }
-                _ => {
-                    return Err(AssemblerError::MissingOperand {
-                        line_index: line_index + 1,
-                        opcode,
-                    })
-                }
+        }
+        let Some(first) = parts.first() else {
+            if let Some((name, span)) = name {
+                symbol_table.insert(name, memory_offset);
+                pending_labels.push((name, span));
             }
-        } else {
-            let operand = match Operand::str_to_operand(parts[1], &symbol_table) {
-                Ok(operand) => operand,
-                Err(_) => {
-                    return Err(AssemblerError::MalformedOperand {
-                        line_index: line_index + 1,
-                        operand: parts[1].to_string(),
-                    })
-                }
-            };
-            match opcode {
-                Opcode::Ldm => {
-                    if let Operand::Immediate(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Number".to_string(),
-                        });
-                    }
-                }
-                Opcode::Ldd => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address".to_string(),
-                        });
-                    }
-                }
-                Opcode::Ldi => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address".to_string(),
-                        });
-                    }
-                }
-                Opcode::Ldx => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address".to_string(),
-                        });
-                    }
-                }
-                Opcode::Ldr => {
-                    if let Operand::Immediate(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Number".to_string(),
-                        });
-                    }
-                }
-                Opcode::Mov => {
-                    if let Operand::Register(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Register".to_string(),
-                        });
-                    }
-                }
-                Opcode::Sto => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address".to_string(),
-                        });
-                    }
-                }
-                Opcode::Add => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else if let Operand::Immediate(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address/Number".to_string(),
-                        });
-                    }
-                }
-                Opcode::Sub => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else if let Operand::Immediate(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address/Number".to_string(),
-                        });
-                    }
-                }
-                Opcode::Inc => {
-                    if let Operand::Register(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Register".to_string(),
-                        });
-                    }
-                }
-                Opcode::Dec => {
-                    if let Operand::Register(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Register".to_string(),
-                        });
-                    }
-                }
-                Opcode::Jmp => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address".to_string(),
-                        });
-                    }
-                }
-                Opcode::Cmp => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else if let Operand::Immediate(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address/Number".to_string(),
-                        });
-                    }
-                }
-                Opcode::Cmi => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address".to_string(),
-                        });
-                    }
-                }
-                Opcode::Jpe => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address".to_string(),
-                        });
-                    }
-                }
-                Opcode::Jpn => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address".to_string(),
-                        });
+            continue;
+        };
+        let symbols = Symbols {
+            labels: &symbol_table,
+            constants: &constants,
+            lengths: &label_lengths,
+        };
+        let span = Span {
+            end: parts[parts.len() - 1].span.end,
+            ..first.span
+        };
+        if matches!(first.text, "EQU" | "ORG" | "DS") {
+            parts[1..]
+                .iter()
+                .for_each(|token| collect_references(token, &mut references));
+        }
+        let statement = match first.text {
+            "EQU" => {
+                let Some((name, _)) = name else {
+                    diagnostics.push(Diagnostic::Error {
+                        span: first.span,
+                        error: AssemblerError::MissingConstantName {
+                            line_index: span.line,
+                        },
+                    });
+                    continue;
+                };
+                match directive_operand(parts, &symbols, load_address) {
+                    Ok((value, _)) => {
+                        constants.insert(name, value);
                     }
+                    Err(diagnostic) => diagnostics.push(diagnostic),
                 }
-                Opcode::And => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else if let Operand::Immediate(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address/Number".to_string(),
-                        });
+                continue;
+            }
+            "ORG" => {
+                let (address, operand_span) = match directive_operand(parts, &symbols, load_address)
+                {
+                    Ok(operand) => operand,
+                    Err(diagnostic) => {
+                        diagnostics.push(diagnostic);
+                        continue;
                     }
+                };
+                let current = load_address as usize + memory_offset;
+                if (address as usize) < current {
+                    diagnostics.push(Diagnostic::Error {
+                        span: operand_span,
+                        error: AssemblerError::OrgBeforeLocation {
+                            line_index: span.line,
+                            address,
+                            current: current as u16,
+                        },
+                    });
+                    continue;
                 }
-                Opcode::Xor => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else if let Operand::Immediate(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address/Number".to_string(),
-                        });
-                    }
+                Statement::Gap(span, address as usize - current)
+            }
+            "DS" => match directive_operand(parts, &symbols, load_address) {
+                Ok((count, _)) => Statement::Words(span, vec![0; count as usize]),
+                Err(diagnostic) => {
+                    diagnostics.push(diagnostic);
+                    continue;
                 }
-                Opcode::Or => {
-                    if let Operand::Address(_) = operand {
-                        result.push((opcode, operand));
-                    } else if let Operand::Immediate(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Address/Number".to_string(),
-                        });
-                    }
+            },
+            text if text.starts_with('"') => {
+                if parts.len() > 1 {
+                    diagnostics.push(Diagnostic::Error {
+                        span: Span {
+                            start: parts[1].span.start,
+                            ..span
+                        },
+                        error: AssemblerError::TooManyOperands {
+                            line_index: span.line,
+                            operands_found: parts.len() - 1,
+                        },
+                    });
+                    continue;
                 }
-                Opcode::Lsl => {
-                    if let Operand::Immediate(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Number".to_string(),
+                match parse_string(text) {
+                    Some(words) => Statement::Words(span, words),
+                    None => {
+                        diagnostics.push(Diagnostic::Error {
+                            span,
+                            error: AssemblerError::MalformedString {
+                                line_index: span.line,
+                                string: text.to_string(),
+                            },
                         });
+                        continue;
                     }
                 }
-                Opcode::Lsr => {
-                    if let Operand::Immediate(_) = operand {
-                        result.push((opcode, operand));
-                    } else {
-                        return Err(AssemblerError::IncorrectOperand {
-                            line_index: line_index + 1,
-                            opcode,
-                            operand_given: operand,
-                            operand_type_expected: "Number".to_string(),
-                        });
-                    }
+            }
+            _ => Statement::Instruction(parts),
+        };
+        let len = match &statement {
+            Statement::Instruction(_) => 1,
+            Statement::Words(_, words) => words.len(),
+            Statement::Gap(_, len) => *len,
+        };
+        // A label on an ORG line names the address it moves to, not the skipped words
+        if first.text == "ORG" {
+            memory_offset += len;
+        }
+        if let Some((name, span)) = name {
+            symbol_table.insert(name, memory_offset);
+            pending_labels.push((name, span));
+        }
+        if first.text != "ORG" {
+            memory_offset += len;
+            let is_code = matches!(statement, Statement::Instruction(parts) if opcode_of(parts[0].text).is_some());
+            for (label, span) in pending_labels.drain(..) {
+                label_lengths.insert(label, len);
+                definitions.insert(label.to_string(), LabelDefinition { span, is_code });
+            }
+        }
+        statements.push(statement);
+    }
+    for (label, span) in pending_labels {
+        label_lengths.insert(label, 0);
+        definitions.insert(
+            label.to_string(),
+            LabelDefinition {
+                span,
+                is_code: false,
+            },
+        );
+    }
+    let symbols = Symbols {
+        labels: &symbol_table,
+        constants: &constants,
+        lengths: &label_lengths,
+    };
+
+    // Second pass: assemble
+    for statement in &statements {
+        let parts = match statement {
+            Statement::Instruction(parts) => *parts,
+            Statement::Words(span, words) => {
+                for &word in words {
+                    word_spans.push(*span);
+                    result.push((Opcode::Data(word), Operand::Immediate(word), false));
                 }
-                _ => {
-                    return Err(AssemblerError::RedundantOperand {
-                        line_index: line_index + 1,
-                        opcode,
-                        operand,
-                    });
+                continue;
+            }
+            Statement::Gap(span, len) => {
+                gaps.push(result.len()..result.len() + len);
+                for _ in 0..*len {
+                    word_spans.push(*span);
+                    result.push((Opcode::Data(0), Operand::Immediate(0), false));
                 }
+                continue;
+            }
+        };
+        let opcode_token = &parts[0];
+        let operands = if opcode_of(opcode_token.text).is_some() {
+            &parts[1..]
+        } else {
+            parts
+        };
+        operands
+            .iter()
+            .for_each(|token| collect_references(token, &mut references));
+        word_spans.push(Span {
+            end: parts[parts.len() - 1].span.end,
+            ..opcode_token.span
+        });
+        let texts: Vec<&str> = parts.iter().map(|t| t.text).collect();
+        match assemble_instruction(
+            opcode_token.span.line - 1,
+            &texts,
+            &symbols,
+            load_address,
+            instruction_set,
+        ) {
+            Ok(instruction) => result.push(instruction),
+            Err(error) => {
+                let span = match error {
+                    AssemblerError::UnknownOpcode { .. }
+                    | AssemblerError::UnsupportedOpcode { .. }
+                    | AssemblerError::MissingOperand { .. } => opcode_token.span,
+                    AssemblerError::MalformedOperand { ref operand, .. }
+                    | AssemblerError::UndefinedSymbol { ref operand, .. }
+                    | AssemblerError::OperandOutOfRange { ref operand, .. } => parts
+                        .iter()
+                        .rev()
+                        .find(|t| t.text == operand)
+                        .map_or(opcode_token.span, |t| t.span),
+                    AssemblerError::TooManyOperands { .. } => Span {
+                        end: parts[parts.len() - 1].span.end,
+                        ..parts[2].span
+                    },
+                    _ => parts[1].span,
+                };
+                diagnostics.push(Diagnostic::Error { span, error });
             }
         }
     }
-    Ok(result)
+
+    let memory_available = 256usize.saturating_sub(load_address as usize);
+    if word_spans.len() > memory_available {
+        diagnostics.push(Diagnostic::Error {
+            span: word_spans[memory_available],
+            error: AssemblerError::ProgramTooLong {
+                program_size: word_spans.len(),
+                memory_available,
+            },
+        });
+    }
+    diagnostics.sort_by_key(|d| (d.span().line, d.span().start));
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(diagnostics);
+    }
+
+    let words = result
+        .iter()
+        .map(|&(opcode, operand, _)| match opcode {
+            Opcode::Data(v) => MemoryData::Value(v),
+            _ => MemoryData::Instruction(opcode, operand),
+        })
+        .collect();
+    let relocations = result
+        .iter()
+        .enumerate()
+        .filter(|(_, &(_, _, relocated))| relocated)
+        .map(|(i, _)| i)
+        .collect();
+    let references = references
+        .into_iter()
+        .filter(|(name, _)| symbol_table.contains_key(name) || constants.contains_key(name))
+        .map(|(name, span)| Reference {
+            name: name.to_string(),
+            span,
+        })
+        .collect();
+    let symbols = symbol_table
+        .into_iter()
+        .map(|(label, offset)| (label.to_string(), load_address + offset as u16))
+        .collect();
+    let constants = constants
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+    Ok(Program {
+        words,
+        symbols,
+        definitions,
+        constants,
+        references,
+        relocations,
+        gaps,
+        source_lines: word_spans.iter().map(|span| span.line).collect(),
+        spans: word_spans,
+        load_address,
+        warnings: diagnostics,
+        encoding: None,
+        instruction_set,
+    })
+}
+
+/// Evaluates `expression`, part of the operand `operand`, for use as a number.
+///
+/// Negative numbers evaluate to their two's complement. Also returns whether the value was
+/// relocated, see [`relocate`].
+fn number_operand(
+    operand: &str,
+    expression: &str,
+    line_index: usize,
+    symbols: &Symbols<'_>,
+    load_address: u16,
+) -> Result<(u16, bool), AssemblerError> {
+    let (expression, forced) = relocation_prefix(expression);
+    let value = evaluate_operand(operand, expression, line_index, symbols)?;
+    let (value, relocated) = relocate(value, forced, load_address);
+    check_range(operand, value, i16::MIN.into(), u16::MAX.into(), line_index)?;
+    Ok((value as u16, relocated))
+}
+
+/// Splits a `@` (never relocate) or `~` (always relocate) prefix off an expression.
+fn relocation_prefix(expression: &str) -> (&str, Option<bool>) {
+    if let Some(expression) = expression.strip_prefix('@') {
+        (expression, Some(false))
+    } else if let Some(expression) = expression.strip_prefix('~') {
+        (expression, Some(true))
+    } else {
+        (expression, None)
+    }
+}
+
+/// Works out the value of an expression when the program is loaded at `load_address`, and
+/// whether the load address was added to it.
+///
+/// Labels are relative to the start of the program, so expressions using them are relocated,
+/// while numbers are absolute. A prefix can force either behaviour: `@label` is the label's
+/// offset in the program, and `~5` is the sixth word of the program.
+fn relocate(value: Value, forced: Option<bool>, load_address: u16) -> (i64, bool) {
+    match forced {
+        Some(false) => (value.value, false),
+        Some(true) => (value.value + i64::from(load_address), true),
+        None => (value.absolute(load_address), value.labels != 0),
+    }
+}
+
+fn evaluate_operand(
+    operand: &str,
+    expression: &str,
+    line_index: usize,
+    symbols: &Symbols<'_>,
+) -> Result<Value, AssemblerError> {
+    evaluate(expression, symbols).map_err(|e| match e {
+        ExpressionError::Malformed => AssemblerError::MalformedOperand {
+            line_index,
+            operand: operand.to_string(),
+        },
+        ExpressionError::UndefinedSymbol(symbol) => AssemblerError::UndefinedSymbol {
+            line_index,
+            symbol,
+            operand: operand.to_string(),
+        },
+    })
+}
+
+fn check_range(
+    operand: &str,
+    value: i64,
+    min: i64,
+    max: i64,
+    line_index: usize,
+) -> Result<(), AssemblerError> {
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(AssemblerError::OperandOutOfRange {
+            line_index,
+            operand: operand.to_string(),
+            value,
+            min,
+            max,
+        })
+    }
+}
+
+/// Parses an instruction's operand: a register, `#` followed by an expression for a number, or
+/// an expression for an address (or for a number, if it starts with a `&` or `B` number).
+///
+/// Also returns whether the operand was relocated, see [`relocate`].
+fn parse_operand(
+    text: &str,
+    line_index: usize,
+    symbols: &Symbols<'_>,
+    load_address: u16,
+) -> Result<(Operand, bool), AssemblerError> {
+    match text {
+        "IX" => return Ok((Operand::Register(Register::Ix), false)),
+        "ACC" => return Ok((Operand::Register(Register::Acc), false)),
+        _ => (),
+    }
+    if let Some(expression) = text.strip_prefix('#') {
+        let (value, relocated) =
+            number_operand(text, expression, line_index, symbols, load_address)?;
+        Ok((Operand::Immediate(value), relocated))
+    } else if starts_with_immediate(text) {
+        let (value, relocated) = number_operand(text, text, line_index, symbols, load_address)?;
+        Ok((Operand::Immediate(value), relocated))
+    } else {
+        let (expression, forced) = relocation_prefix(text);
+        let value = evaluate_operand(text, expression, line_index, symbols)?;
+        let (value, relocated) = relocate(value, forced, load_address);
+        check_range(text, value, 0, u16::MAX.into(), line_index)?;
+        Ok((Operand::Address(value as u16), relocated))
+    }
+}
+
+fn assemble_instruction(
+    line_index: usize,
+    parts: &[&str],
+    symbols: &Symbols<'_>,
+    load_address: u16,
+    instruction_set: InstructionSet,
+) -> Result<(Opcode, Operand, bool), AssemblerError> {
+    if parts.len() > 2 {
+        return Err(AssemblerError::TooManyOperands {
+            line_index: line_index + 1,
+            operands_found: parts.len() - 1,
+        });
+    }
+    let opcode = if let Some(opcode) = opcode_of(parts[0]) {
+        opcode
+    } else if parts[0].starts_with('#') || starts_with_immediate(parts[0]) {
+        let expression = parts[0].strip_prefix('#').unwrap_or(parts[0]);
+        let (value, relocated) =
+            number_operand(parts[0], expression, line_index + 1, symbols, load_address)?;
+        if parts.len() == 1 {
+            return Ok((Opcode::Data(value), Operand::Immediate(value), relocated));
+        }
+        Opcode::Data(value)
+    } else {
+        return Err(AssemblerError::UnknownOpcode {
+            line_index: line_index + 1,
+            opcode: parts[0].to_string(),
+        });
+    };
+    if !matches!(opcode, Opcode::Data(_)) && !instruction_set.has_opcode(opcode) {
+        return Err(AssemblerError::UnsupportedOpcode {
+            line_index: line_index + 1,
+            opcode,
+            instruction_set,
+        });
+    }
+    // Data values are not in any instruction set, so they take no operand
+    let takes_operand = instruction_set
+        .operand_kinds(opcode)
+        .any(|kind| kind != OperandKind::None);
+    if parts.len() == 1 {
+        return if takes_operand {
+            Err(AssemblerError::MissingOperand {
+                line_index: line_index + 1,
+                opcode,
+            })
+        } else {
+            Ok((opcode, Operand::Empty, false))
+        };
+    }
+    let (operand, relocated) = parse_operand(parts[1], line_index + 1, symbols, load_address)?;
+    if !takes_operand {
+        Err(AssemblerError::RedundantOperand {
+            line_index: line_index + 1,
+            opcode,
+            operand,
+        })
+    } else if instruction_set.allows(opcode, operand) {
+        Ok((opcode, operand, relocated))
+    } else {
+        Err(AssemblerError::IncorrectOperand {
+            line_index: line_index + 1,
+            opcode,
+            operand_given: operand,
+            operand_type_expected: instruction_set.expected_operand(opcode),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe(word: &MemoryData) -> String {
+        match word {
+            MemoryData::Instruction(opcode, operand) => format!("{:?} {:?}", opcode, operand),
+            MemoryData::Value(value) => value.to_string(),
+        }
+    }
+
+    fn errors(source: &str) -> Vec<(AssemblerError, Span)> {
+        match assemble(source, 0) {
+            Ok(_) => panic!("{:?} assembled", source),
+            Err(diagnostics) => diagnostics
+                .into_iter()
+                .filter_map(|diagnostic| match diagnostic {
+                    Diagnostic::Error { span, error } => Some((error, span)),
+                    Diagnostic::Warning { .. } => None,
+                })
+                .collect(),
+        }
+    }
+
+    fn token_texts(line: &str) -> Vec<&str> {
+        tokenize(line, 1).iter().map(|token| token.text).collect()
+    }
+
+    fn words(source: &str) -> Vec<MemoryData> {
+        match assemble(source, 0) {
+            Ok(program) => program.words,
+            Err(diagnostics) => panic!("{:?}", diagnostics),
+        }
+    }
+
+    #[test]
+    fn assembles_a_program_with_its_symbols_and_lines() {
+        let program = assemble("start: LDM #1\n ADD count\n\n JMP start\ncount: #10", 4).unwrap();
+        let words: Vec<String> = program.words.iter().map(describe).collect();
+        assert_eq!(
+            words,
+            ["LDM Number(1)", "ADD Address(7)", "JMP Address(4)", "10"]
+        );
+        assert_eq!(program.source_lines, [1, 2, 4, 5]);
+        assert_eq!(program.address_of("count"), Some(7));
+        assert_eq!(program.line_of(6), Some(4));
+        assert_eq!(program.address_of_line(4), Some(6));
+        assert_eq!(program.address_of_line(3), None);
+    }
+
+    #[test]
+    fn reports_every_error_with_its_span() {
+        let errors = errors("LDM #1\nFOO 3\nLDM 5 6\nLDD\nEND");
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(
+            &errors[0].0,
+            AssemblerError::UnknownOpcode { line_index: 2, opcode } if opcode == "FOO"
+        ));
+        assert_eq!(
+            errors[0].1,
+            Span {
+                line: 2,
+                start: 0,
+                end: 3
+            }
+        );
+        assert!(matches!(
+            errors[1].0,
+            AssemblerError::TooManyOperands {
+                line_index: 3,
+                operands_found: 2
+            }
+        ));
+        assert_eq!(
+            errors[1].1,
+            Span {
+                line: 3,
+                start: 6,
+                end: 7
+            }
+        );
+        assert!(matches!(
+            errors[2].0,
+            AssemblerError::MissingOperand { line_index: 4, .. }
+        ));
+        assert_eq!(
+            errors[2].1,
+            Span {
+                line: 4,
+                start: 0,
+                end: 3
+            }
+        );
+    }
+
+    #[test]
+    fn operands_must_fit_the_instruction() {
+        let errors = errors("LDM 5\nEND 1");
+        assert!(matches!(
+            errors[0],
+            (
+                AssemblerError::IncorrectOperand { line_index: 1, .. },
+                Span {
+                    line: 1,
+                    start: 4,
+                    end: 5
+                }
+            )
+        ));
+        assert!(matches!(
+            errors[1].0,
+            AssemblerError::RedundantOperand { line_index: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn duplicate_labels_are_warnings() {
+        let program = assemble("x: LDM #1\nx: END", 0).unwrap();
+        assert!(matches!(
+            &program.warnings[..],
+            [Diagnostic::Warning {
+                warning: AssemblerWarning::DuplicateLabel {
+                    line_index: 2,
+                    first_line: 1,
+                    ..
+                },
+                ..
+            }]
+        ));
+        assert_eq!(program.address_of("x"), Some(1));
+    }
+
+    #[test]
+    fn constants_are_numbers_or_addresses() {
+        let program = assemble("size EQU 3\nLDM #size\nLDD size\nEND", 0).unwrap();
+        let words: Vec<String> = program.words.iter().map(describe).collect();
+        assert_eq!(words[..2], ["LDM Number(3)", "LDD Address(3)"]);
+        assert_eq!(program.constants["size"], 3);
+    }
+
+    #[test]
+    fn ds_reserves_zeros_and_strings_store_characters() {
+        let program = assemble("END\nbuffer: DS 2\ntext: \"Hi\"", 0).unwrap();
+        let words: Vec<String> = program.words[1..].iter().map(describe).collect();
+        assert_eq!(words, ["0", "0", "72", "105"]);
+        assert_eq!(program.source_lines, [1, 2, 2, 3, 3]);
+        assert_eq!(program.address_of("text"), Some(3));
+    }
+
+    #[test]
+    fn equ_needs_a_name() {
+        assert!(matches!(
+            errors("EQU 5\nEND")[..],
+            [(AssemblerError::MissingConstantName { line_index: 1 }, _)]
+        ));
+    }
+
+    #[test]
+    fn operands_are_expressions() {
+        let source = "size EQU 3\nLDM #size+'A'\nLDD data-1\nLDM #len(data)\ndata: #-size";
+        let program = assemble(source, 0).unwrap();
+        let words: Vec<String> = program.words.iter().map(describe).collect();
+        assert_eq!(
+            words,
+            ["LDM Number(68)", "LDD Address(2)", "LDM Number(1)", "65533"]
+        );
+    }
+
+    #[test]
+    fn undefined_symbols_are_errors() {
+        assert!(matches!(
+            &errors("LDD missing+1\nEND")[..],
+            [(
+                AssemblerError::UndefinedSymbol { line_index: 1, symbol, .. },
+                Span { line: 1, start: 4, end: 13 }
+            )] if symbol == "missing"
+        ));
+    }
+
+    #[test]
+    fn labels_are_relocated_unless_forced() {
+        let program = assemble("LDD x\nLDD @x\nLDD ~3\nLDD 3\nLDM #x\nx: #x", 10).unwrap();
+        let words: Vec<String> = program.words.iter().map(describe).collect();
+        assert_eq!(
+            words,
+            [
+                "LDD Address(15)",
+                "LDD Address(5)",
+                "LDD Address(13)",
+                "LDD Address(3)",
+                "LDM Number(15)",
+                "15"
+            ]
+        );
+        assert_eq!(program.relocations, [0, 2, 4, 5]);
+    }
+
+    #[test]
+    fn character_literals_are_part_of_one_token() {
+        assert_eq!(token_texts("LDM #' '"), ["LDM", "#' '"]);
+        assert_eq!(token_texts("LDM #'\"' // c"), ["LDM", "#'\"'"]);
+        assert_eq!(token_texts("LDM #'/' // c"), ["LDM", "#'/'"]);
+        assert_eq!(token_texts("x: #'\\''"), ["x:", "#'\\''"]);
+    }
+
+    #[test]
+    fn only_tokens_starting_with_a_quote_are_strings() {
+        assert_eq!(token_texts("s: \"a b // c\" // d"), ["s:", "\"a b // c\""]);
+        assert_eq!(token_texts("LDM #1\" // c"), ["LDM", "#1\""]);
+    }
+
+    #[test]
+    fn org_leaves_a_gap_and_moves_the_entry_address() {
+        let program = assemble("ORG 16\nstart: LDM #1\nORG 20\nEND", 4).unwrap();
+        assert_eq!(program.gaps, [0..12, 13..16]);
+        assert_eq!(program.entry_address(), 16);
+        assert_eq!(program.symbols["start"], 16);
+        assert!(program.is_gap(15));
+        assert!(!program.is_gap(12));
+
+        let program = assemble("LDM #1\nORG 16\nEND", 0).unwrap();
+        assert_eq!(program.entry_address(), 0);
+    }
+
+    #[test]
+    fn character_immediates_assemble() {
+        assert_eq!(
+            words("LDM #' '\nLDM #'\"' // c\nLDM #-1"),
+            [
+                MemoryData::Instruction(Opcode::Ldm, Operand::Immediate(32)),
+                MemoryData::Instruction(Opcode::Ldm, Operand::Immediate(34)),
+                MemoryData::Instruction(Opcode::Ldm, Operand::Immediate(0xFFFF)),
+            ]
+        );
+    }
 }
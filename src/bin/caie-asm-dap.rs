@@ -0,0 +1,516 @@
+//! Debug adapter for CAIE assembly, speaking the Debug Adapter Protocol over stdio.
+//!
+//! Launches a source file on the headless emulator, stops at line breakpoints, steps one
+//! instruction at a time, and shows the registers, the flags and memory as variables. Characters
+//! for `IN` are typed into the debug console, and `OUT` writes to it.
+//!
+//! Launch arguments: `program` (path of the source file), and optionally `loadAt`,
+//! `instructionSet` (`9618`, `9608` or `exam-only`), `machineCode`, `stopOnEntry`, `input`
+//! (characters for `IN` to read before asking) and `maxSteps` (instructions to execute before
+//! pausing a run that may never stop, 10000 by default).
+
+use std::{collections::VecDeque, io, ops::ControlFlow, path::Path, process::ExitCode};
+
+use eframe_template::{
+    assembler::{assemble_for, Program},
+    encoding::Encoding,
+    instruction_set::InstructionSet,
+    machine::{Machine, StepOutcome},
+    number_format::NumberFormat,
+    protocol::{read_message, write_message},
+    trace::format_instruction,
+    ExecutionInfo, MemoryData,
+};
+use serde_json::{json, Value};
+
+/// The emulator has a single thread of execution, with this ID.
+const THREAD_ID: u64 = 1;
+
+const REGISTERS: u64 = 1;
+const FLAGS: u64 = 2;
+const MEMORY: u64 = 3;
+
+#[derive(Default)]
+struct Adapter {
+    /// Path of the launched source file.
+    path: String,
+    program: Option<Program>,
+    machine: Machine,
+    /// Source lines that breakpoints were requested on.
+    breakpoint_lines: Vec<usize>,
+    breakpoints: Vec<u16>,
+    /// Characters typed into the debug console that `IN` has not read yet.
+    input: VecDeque<char>,
+    /// Whether an `IN` instruction is waiting for a character.
+    awaiting_input: bool,
+    /// Why execution stopped, once it has.
+    finished: Option<ExecutionInfo>,
+    /// Bytes of the machine's output already sent to the debug console.
+    output_sent: usize,
+    stop_on_entry: bool,
+    max_steps: u64,
+    /// Where execution last stopped, so that resuming there does not stop at the same breakpoint
+    /// again.
+    stopped_at: Option<u16>,
+    /// Whether the client counts lines and columns from 0, as asked for in `initialize`. Lines in
+    /// the adapter count from 1.
+    lines_start_at_0: bool,
+    columns_start_at_0: bool,
+}
+
+fn main() -> ExitCode {
+    let mut adapter = Adapter::default();
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let mut seq = 0;
+    loop {
+        let request = match read_message(&mut stdin) {
+            Ok(Some(request)) => request,
+            Ok(None) => return ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("error: cannot read message: {}", e);
+                return ExitCode::from(1);
+            }
+        };
+        for mut message in adapter.handle(&request) {
+            seq += 1;
+            message["seq"] = json!(seq);
+            if let Err(e) = write_message(&mut stdout, &message) {
+                eprintln!("error: cannot write message: {}", e);
+                return ExitCode::from(1);
+            }
+        }
+        if request["command"] == "disconnect" {
+            return ExitCode::SUCCESS;
+        }
+    }
+}
+
+fn event(name: &str, body: Value) -> Value {
+    json!({ "type": "event", "event": name, "body": body })
+}
+
+fn output(text: &str) -> Value {
+    event("output", json!({ "category": "console", "output": text }))
+}
+
+fn stopped(reason: &str, description: Option<String>) -> Value {
+    event(
+        "stopped",
+        json!({
+            "reason": reason,
+            "description": description,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        }),
+    )
+}
+
+impl Adapter {
+    /// Handles a request, returning its response and any events to send after it.
+    fn handle(&mut self, request: &Value) -> Vec<Value> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+        let mut events = Vec::new();
+        let result = match command {
+            "initialize" => {
+                self.lines_start_at_0 = arguments["linesStartAt1"] == false;
+                self.columns_start_at_0 = arguments["columnsStartAt1"] == false;
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsEvaluateForHovers": true,
+                }))
+            }
+            "launch" => self.launch(arguments, &mut events),
+            "setBreakpoints" => Ok(self.set_breakpoints(arguments)),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => {
+                events = if self.stop_on_entry {
+                    self.stopped_at = Some(self.machine.pc);
+                    vec![stopped("entry", None)]
+                } else {
+                    self.resume(false)
+                };
+                Ok(Value::Null)
+            }
+            "threads" => Ok(json!({
+                "threads": [{ "id": THREAD_ID, "name": "CAIE processor" }],
+            })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({
+                "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                    { "name": "Flags", "variablesReference": FLAGS, "expensive": false },
+                    { "name": "Memory", "variablesReference": MEMORY, "expensive": true },
+                ],
+            })),
+            "variables" => Ok(json!({
+                "variables": self
+                    .variables(arguments["variablesReference"].as_u64().unwrap_or_default())
+                    .into_iter()
+                    .map(|(name, value)| json!({
+                        "name": name,
+                        "value": value,
+                        "variablesReference": 0,
+                    }))
+                    .collect::<Vec<_>>(),
+            })),
+            "continue" => {
+                events = self.resume(false);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            // There are no subroutines, so every kind of step executes one instruction
+            "next" | "stepIn" | "stepOut" => {
+                events = self.resume(true);
+                Ok(Value::Null)
+            }
+            // Execution has always stopped by the time a request is read
+            "pause" => Ok(Value::Null),
+            "evaluate" => self.evaluate(arguments),
+            "disconnect" => Ok(Value::Null),
+            _ => Err(format!("unknown command: {}", command)),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        std::iter::once(response).chain(events).collect()
+    }
+
+    /// Assembles and loads the program. Configuration requests are accepted once it is loaded.
+    fn launch(&mut self, arguments: &Value, events: &mut Vec<Value>) -> Result<Value, String> {
+        let path = arguments["program"]
+            .as_str()
+            .ok_or("missing launch argument: program")?;
+        let load_at = match arguments["loadAt"].as_u64() {
+            Some(address) if address <= 255 => address as u16,
+            Some(address) => return Err(format!("loadAt is not an address: {}", address)),
+            None => 0,
+        };
+        let instruction_set = match arguments["instructionSet"].as_str() {
+            Some(name) => InstructionSet::from_name(name)
+                .ok_or_else(|| format!("unknown instruction set: {}", name))?,
+            None => InstructionSet::default(),
+        };
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+
+        let assembled = assemble_for(&source, load_at, instruction_set).and_then(|mut program| {
+            if arguments["machineCode"].as_bool().unwrap_or(false) {
+                program.encode(&Encoding::default())?;
+            }
+            Ok(program)
+        });
+        let program = match assembled {
+            Ok(program) => program,
+            Err(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    let span = diagnostic.span();
+                    events.push(output(&format!(
+                        "{}:{}:{}: {}\n",
+                        path,
+                        span.line,
+                        span.start + 1,
+                        diagnostic
+                    )));
+                }
+                return Err(format!("{} has errors", path));
+            }
+        };
+        for diagnostic in &program.warnings {
+            let span = diagnostic.span();
+            events.push(output(&format!(
+                "{}:{}:{}: {}\n",
+                path,
+                span.line,
+                span.start + 1,
+                diagnostic
+            )));
+        }
+
+        self.machine = Machine::default();
+        self.machine.load_program(&program);
+        self.machine.pc = program.entry_address();
+        self.path = path.to_owned();
+        self.input = arguments["input"]
+            .as_str()
+            .unwrap_or_default()
+            .chars()
+            .collect();
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.max_steps = arguments["maxSteps"].as_u64().unwrap_or(10000);
+        self.program = Some(program);
+        self.resolve_breakpoints();
+        events.push(event("initialized", json!({})));
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        self.breakpoint_lines = arguments["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|breakpoint| breakpoint["line"].as_u64())
+            .map(|line| line as usize + usize::from(self.lines_start_at_0))
+            .collect();
+        let lines = self.resolve_breakpoints();
+        json!({
+            "breakpoints": lines
+                .into_iter()
+                .map(|line| match line {
+                    Some(line) => json!({
+                        "verified": true,
+                        "line": line - usize::from(self.lines_start_at_0),
+                    }),
+                    None => json!({
+                        "verified": false,
+                        "message": "No instruction or data at or after this line",
+                    }),
+                })
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Moves each breakpoint to the first line at or after it that assembles to a word, and
+    /// returns the lines the breakpoints ended up on.
+    fn resolve_breakpoints(&mut self) -> Vec<Option<usize>> {
+        self.breakpoints.clear();
+        let Some(program) = &self.program else {
+            return vec![None; self.breakpoint_lines.len()];
+        };
+        self.breakpoint_lines
+            .iter()
+            .map(|&line| {
+                let line = program
+                    .source_lines
+                    .iter()
+                    .copied()
+                    .filter(|&l| l >= line)
+                    .min()?;
+                self.breakpoints.extend(program.address_of_line(line));
+                Some(line)
+            })
+            .collect()
+    }
+
+    /// Executes one instruction, or runs until a breakpoint, an `IN` with no input, or the end
+    /// of the program. Returns the events to send.
+    fn resume(&mut self, single_step: bool) -> Vec<Value> {
+        if self.program.is_none() {
+            return vec![];
+        }
+        if self.finished.is_some() {
+            return self.terminate();
+        }
+        let mut events = Vec::new();
+        let resumed_at = self.stopped_at.take();
+        let reason = 'stop: {
+            if self.awaiting_input {
+                match self.input.pop_front() {
+                    Some(c) => {
+                        self.machine.input(c);
+                        self.awaiting_input = false;
+                    }
+                    None => return self.wait_for_input(events),
+                }
+                if single_step {
+                    break 'stop stopped("step", None);
+                }
+            }
+            if resumed_at != Some(self.machine.pc) && self.breakpoints.contains(&self.machine.pc) {
+                break 'stop stopped("breakpoint", None);
+            }
+            let Self {
+                machine,
+                input,
+                breakpoints,
+                ..
+            } = self;
+            let (outcome, steps) = machine.run_with_input(
+                &mut std::iter::from_fn(|| input.pop_front()),
+                self.max_steps,
+                |machine, _| {
+                    if single_step || breakpoints.contains(&machine.pc) {
+                        ControlFlow::Break(())
+                    } else {
+                        ControlFlow::Continue(())
+                    }
+                },
+            );
+            match outcome {
+                StepOutcome::Continue if single_step => stopped("step", None),
+                StepOutcome::Continue => stopped("breakpoint", None),
+                StepOutcome::AwaitingInput => {
+                    self.awaiting_input = true;
+                    return self.wait_for_input(events);
+                }
+                StepOutcome::Stopped(ExecutionInfo::TooManySteps { .. }) => {
+                    events.push(output(&format!(
+                        "Paused after {} instructions without reaching END\n",
+                        steps
+                    )));
+                    stopped("pause", None)
+                }
+                StepOutcome::Stopped(info) => {
+                    let message = match info {
+                        ExecutionInfo::ExecutionTerminated { .. } => None,
+                        _ => Some(stopped("exception", Some(info.to_string()))),
+                    };
+                    self.finished = Some(info);
+                    match message {
+                        Some(message) => message,
+                        None => {
+                            events.extend(self.flush_output());
+                            events.extend(self.terminate());
+                            return events;
+                        }
+                    }
+                }
+            }
+        };
+        self.stopped_at = Some(self.machine.pc);
+        events.extend(self.flush_output());
+        events.push(reason);
+        events
+    }
+
+    /// Pauses at an `IN` instruction that is waiting for a character from the debug console.
+    fn wait_for_input(&mut self, mut events: Vec<Value>) -> Vec<Value> {
+        events.push(output(&format!(
+            "IN at address {:02X} is waiting for input: type it in the debug console\n",
+            self.machine.pc.wrapping_sub(1)
+        )));
+        // The next instruction is not reached until IN has its character, so a breakpoint on it
+        // still applies
+        events.extend(self.flush_output());
+        events.push(stopped("pause", Some("Waiting for input".to_owned())));
+        events
+    }
+
+    /// Sends the characters written by `OUT` since the last flush.
+    fn flush_output(&mut self) -> Option<Value> {
+        let text = &self.machine.output[self.output_sent..];
+        self.output_sent = self.machine.output.len();
+        (!text.is_empty()).then(|| event("output", json!({ "category": "stdout", "output": text })))
+    }
+
+    fn terminate(&self) -> Vec<Value> {
+        let (message, exit_code) = match &self.finished {
+            Some(info @ ExecutionInfo::ExecutionTerminated { .. }) => (info.to_string(), 0),
+            Some(info) => (format!("error: {}", info), 3),
+            None => return vec![],
+        };
+        vec![
+            output(&format!("\n{}\n", message)),
+            event("exited", json!({ "exitCode": exit_code })),
+            event("terminated", json!({})),
+        ]
+    }
+
+    fn stack_trace(&self) -> Value {
+        let pc = self.machine.pc;
+        let word = match self.machine.instruction_at(pc) {
+            Some((opcode, operand)) => {
+                format_instruction(opcode, operand, NumberFormat::Hexadecimal)
+            }
+            None => self
+                .machine
+                .memory
+                .as_flattened()
+                .get(pc as usize)
+                .map(format_word)
+                .unwrap_or_default(),
+        };
+        let mut frame = json!({
+            "id": 0,
+            "name": format!("{:02X}  {}", pc, word),
+            "line": 0,
+            "column": 0,
+        });
+        if let Some(line) = self.program.as_ref().and_then(|p| p.line_of(pc)) {
+            let name = Path::new(&self.path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            frame["source"] = json!({ "name": name, "path": self.path });
+            frame["line"] = json!(line - usize::from(self.lines_start_at_0));
+            frame["column"] = json!(1 - usize::from(self.columns_start_at_0));
+        }
+        json!({ "stackFrames": [frame], "totalFrames": 1 })
+    }
+
+    /// Returns the names and values of the variables in a scope.
+    fn variables(&self, reference: u64) -> Vec<(String, String)> {
+        let machine = &self.machine;
+        let flag = |set: bool| u8::from(set).to_string();
+        match reference {
+            REGISTERS => vec![
+                ("PC".to_owned(), format!("{:02X}", machine.pc)),
+                ("ACC".to_owned(), format!("{:04X}", machine.acc)),
+                ("IX".to_owned(), format!("{:04X}", machine.ix)),
+                ("MAR".to_owned(), format!("{:02X}", machine.mar)),
+                ("MDR".to_owned(), format_word(&machine.mdr)),
+                (
+                    "CIR".to_owned(),
+                    format_instruction(machine.cir.0, machine.cir.1, NumberFormat::Hexadecimal),
+                ),
+            ],
+            FLAGS => vec![
+                ("Carry".to_owned(), flag(machine.carry)),
+                ("Zero".to_owned(), flag(machine.zero)),
+                ("Overflow".to_owned(), flag(machine.overflow)),
+                ("Sign".to_owned(), flag(machine.sign)),
+            ],
+            MEMORY => machine
+                .memory
+                .as_flattened()
+                .iter()
+                .enumerate()
+                .map(|(address, word)| (format!("{:02X}", address), format_word(word)))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Text typed into the debug console is input for `IN`. Elsewhere, such as when hovering,
+    /// registers and flags can be evaluated by name.
+    fn evaluate(&mut self, arguments: &Value) -> Result<Value, String> {
+        let expression = arguments["expression"].as_str().unwrap_or_default();
+        if arguments["context"] != "repl" {
+            return [REGISTERS, FLAGS]
+                .into_iter()
+                .flat_map(|reference| self.variables(reference))
+                .find(|(name, _)| name.eq_ignore_ascii_case(expression))
+                .map(|(_, value)| json!({ "result": value, "variablesReference": 0 }))
+                .ok_or_else(|| format!("not a register or flag: {}", expression));
+        }
+
+        self.input.extend(expression.chars());
+        let mut result = format!("Queued {} characters for IN", expression.chars().count());
+        // Complete a waiting IN at once, so the ACC can be inspected before continuing
+        if self.awaiting_input {
+            if let Some(c) = self.input.pop_front() {
+                self.machine.input(c);
+                self.awaiting_input = false;
+                result = format!("IN read {:?}; {} characters queued", c, self.input.len());
+            }
+        }
+        Ok(json!({ "result": result, "variablesReference": 0 }))
+    }
+}
+
+fn format_word(word: &MemoryData) -> String {
+    match *word {
+        MemoryData::Instruction(opcode, operand) => {
+            format_instruction(opcode, operand, NumberFormat::Hexadecimal)
+        }
+        MemoryData::Value(v) => format!("{:04X}", v),
+    }
+}
@@ -0,0 +1,422 @@
+//! Language server for CAIE assembly, speaking the Language Server Protocol over stdio.
+//!
+//! Reports the assembler's errors and warnings and the linter's warnings as diagnostics, and
+//! provides hover documentation for mnemonics, go-to-definition and find-references for labels
+//! and constants, completion of mnemonics and labels, and formatting. The instruction set can be
+//! chosen with the `instructionSet` initialization option (`9618`, `9608` or `exam-only`).
+
+use std::{collections::HashMap, io, ops::Range, process::ExitCode};
+
+use eframe_template::{
+    assembler::assemble_for,
+    encoding::form_syntax,
+    format::format_source,
+    highlight::{highlight_line, Highlight},
+    instruction_set::{example, form_rtl, opcode_of, InstructionSet, MNEMONICS},
+    lint::lint,
+    protocol::{read_message, write_message},
+    Diagnostic,
+};
+use serde_json::{json, Value};
+
+/// JSON-RPC error code for a request the server does not handle.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+#[derive(Default)]
+struct Server {
+    /// Open documents by URI.
+    documents: HashMap<String, String>,
+    instruction_set: InstructionSet,
+    shutdown_requested: bool,
+}
+
+/// Where a label or constant is defined or used.
+struct Occurrence {
+    name: String,
+    line: usize,
+    /// Byte range of the name in its line.
+    range: Range<usize>,
+    is_definition: bool,
+}
+
+fn main() -> ExitCode {
+    let mut server = Server::default();
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    loop {
+        let message = match read_message(&mut stdin) {
+            Ok(Some(message)) => message,
+            Ok(None) => return ExitCode::from(1),
+            Err(e) => {
+                eprintln!("error: cannot read message: {}", e);
+                return ExitCode::from(1);
+            }
+        };
+        if message["method"] == "exit" {
+            return if server.shutdown_requested {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::from(1)
+            };
+        }
+        for reply in server.handle(&message) {
+            if let Err(e) = write_message(&mut stdout, &reply) {
+                eprintln!("error: cannot write message: {}", e);
+                return ExitCode::from(1);
+            }
+        }
+    }
+}
+
+impl Server {
+    /// Handles a request or notification, returning the messages to send back.
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let Some(id) = message.get("id") else {
+            return self.notification(method, params);
+        };
+        let result = match method {
+            "initialize" => Ok(self.initialize(params)),
+            "shutdown" => {
+                self.shutdown_requested = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => Ok(self.hover(params)),
+            "textDocument/definition" => Ok(self.definition(params)),
+            "textDocument/references" => Ok(self.references(params)),
+            "textDocument/completion" => Ok(self.completion(params)),
+            "textDocument/formatting" => Ok(self.formatting(params)),
+            _ => Err(format!("unknown method: {}", method)),
+        };
+        vec![match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(message) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": METHOD_NOT_FOUND, "message": message },
+            }),
+        }]
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_owned());
+            }
+            // Only full document changes are asked for in the capabilities
+            "textDocument/didChange" => {
+                let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                else {
+                    return vec![];
+                };
+                self.documents.insert(uri.clone(), text.to_owned());
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, vec![])];
+            }
+            _ => return vec![],
+        }
+        vec![publish_diagnostics(&uri, self.diagnostics(&uri))]
+    }
+
+    fn initialize(&mut self, params: &Value) -> Value {
+        if let Some(set) = params["initializationOptions"]["instructionSet"]
+            .as_str()
+            .and_then(InstructionSet::from_name)
+        {
+            self.instruction_set = set;
+        }
+        json!({
+            "capabilities": {
+                "textDocumentSync": 1,
+                "hoverProvider": true,
+                "definitionProvider": true,
+                "referencesProvider": true,
+                "completionProvider": {},
+                "documentFormattingProvider": true,
+            },
+            "serverInfo": { "name": "caie-asm-lsp" },
+        })
+    }
+
+    /// Assembles and lints a document, as the app does for "Assemble and load".
+    fn diagnostics(&self, uri: &str) -> Vec<Value> {
+        let Some(text) = self.documents.get(uri) else {
+            return vec![];
+        };
+        let diagnostics = match assemble_for(text, 0, self.instruction_set) {
+            Ok(mut program) => {
+                program.warnings.extend(lint(&program));
+                program.warnings
+            }
+            Err(diagnostics) => diagnostics,
+        };
+        let lines: Vec<&str> = text.split('\n').collect();
+        diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let span = diagnostic.span();
+                let line = lines.get(span.line - 1).copied().unwrap_or_default();
+                let (severity, message) = match diagnostic {
+                    Diagnostic::Error { error, .. } => (1, error.to_string()),
+                    Diagnostic::Warning { warning, .. } => (2, warning.to_string()),
+                };
+                json!({
+                    "range": {
+                        "start": position(span.line - 1, utf16_column(line, char_byte(line, span.start))),
+                        "end": position(span.line - 1, utf16_column(line, char_byte(line, span.end))),
+                    },
+                    "severity": severity,
+                    "source": "caie-asm",
+                    "message": message,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the lines of the document in `params` and the position in it as a line index and
+    /// a byte index in that line.
+    fn document_position<'a>(&'a self, params: &Value) -> Option<(Vec<&'a str>, usize, usize)> {
+        let text = self
+            .documents
+            .get(params["textDocument"]["uri"].as_str()?)?;
+        let lines: Vec<&str> = text.split('\n').collect();
+        let line = usize::try_from(params["position"]["line"].as_u64()?).ok()?;
+        let character = usize::try_from(params["position"]["character"].as_u64()?).ok()?;
+        let byte = utf16_byte(lines.get(line)?, character);
+        Some((lines, line, byte))
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let Some((lines, line, byte)) = self.document_position(params) else {
+            return Value::Null;
+        };
+        let Some((range, _)) =
+            highlight_line(lines[line])
+                .into_iter()
+                .find(|(range, highlight)| {
+                    *highlight == Highlight::Mnemonic && range.start <= byte && byte <= range.end
+                })
+        else {
+            return Value::Null;
+        };
+        let Some(opcode) = opcode_of(&lines[line][range.clone()]) else {
+            return Value::Null;
+        };
+        let kinds: Vec<_> = self.instruction_set.operand_kinds(opcode).collect();
+        let mut documentation = if kinds.is_empty() {
+            format!(
+                "{} is not in the {} instruction set.\n",
+                opcode,
+                self.instruction_set.name()
+            )
+        } else {
+            kinds
+                .iter()
+                .map(|&kind| {
+                    format!(
+                        "`{}` — {}\n\n",
+                        form_syntax(opcode, kind),
+                        form_rtl(opcode, kind)
+                    )
+                })
+                .collect()
+        };
+        documentation += &format!("Example:\n```\n{}\n```", example(opcode));
+        json!({
+            "contents": { "kind": "markdown", "value": documentation },
+            "range": range_json(lines[line], line, range),
+        })
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let uri = &params["textDocument"]["uri"];
+        let Some((lines, line, byte)) = self.document_position(params) else {
+            return Value::Null;
+        };
+        let occurrences = occurrences(&lines);
+        let Some(name) = occurrence_at(&occurrences, line, byte).map(|o| &o.name) else {
+            return Value::Null;
+        };
+        // The assembler uses the last definition of a duplicate label
+        match occurrences
+            .iter()
+            .rfind(|o| o.is_definition && o.name == *name)
+        {
+            Some(definition) => location(uri, &lines, definition),
+            None => Value::Null,
+        }
+    }
+
+    fn references(&self, params: &Value) -> Value {
+        let uri = &params["textDocument"]["uri"];
+        let include_declaration = params["context"]["includeDeclaration"]
+            .as_bool()
+            .unwrap_or(true);
+        let Some((lines, line, byte)) = self.document_position(params) else {
+            return Value::Null;
+        };
+        let occurrences = occurrences(&lines);
+        let Some(name) = occurrence_at(&occurrences, line, byte).map(|o| &o.name) else {
+            return Value::Null;
+        };
+        occurrences
+            .iter()
+            .filter(|o| o.name == *name && (include_declaration || !o.is_definition))
+            .map(|o| location(uri, &lines, o))
+            .collect()
+    }
+
+    fn completion(&self, params: &Value) -> Value {
+        let Some((lines, line, byte)) = self.document_position(params) else {
+            return Value::Null;
+        };
+        let before = &lines[line][..byte];
+        let word_start = before
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+            .map_or(0, |i| i + 1);
+        let word = &before[word_start..];
+        let upper = word.to_uppercase();
+
+        let mut items: Vec<Value> = MNEMONICS
+            .iter()
+            .filter(|&&(mnemonic, opcode)| {
+                mnemonic.starts_with(&upper) && self.instruction_set.has_opcode(opcode)
+            })
+            .map(|&(mnemonic, opcode)| {
+                json!({
+                    "label": mnemonic,
+                    // Keyword
+                    "kind": 14,
+                    "detail": self.instruction_set.expected_operand(opcode),
+                })
+            })
+            .collect();
+        let mut names: Vec<&str> = occurrences(&lines)
+            .into_iter()
+            .filter(|o| o.is_definition && o.name.starts_with(word))
+            .map(|o| &lines[o.line][o.range])
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        items.extend(names.into_iter().map(|name| {
+            json!({
+                "label": name,
+                // Variable
+                "kind": 6,
+                "detail": "label",
+            })
+        }));
+        Value::Array(items)
+    }
+
+    fn formatting(&self, params: &Value) -> Value {
+        let Some(text) = params["textDocument"]["uri"]
+            .as_str()
+            .and_then(|uri| self.documents.get(uri))
+        else {
+            return Value::Null;
+        };
+        let formatted = format_source(text);
+        if formatted == *text {
+            return json!([]);
+        }
+        let lines: Vec<&str> = text.split('\n').collect();
+        let last = lines.len() - 1;
+        json!([{
+            "range": {
+                "start": position(0, 0),
+                "end": position(last, utf16_column(lines[last], lines[last].len())),
+            },
+            "newText": formatted,
+        }])
+    }
+}
+
+/// Finds where labels and constants are defined and used.
+fn occurrences(lines: &[&str]) -> Vec<Occurrence> {
+    let mut occurrences = Vec::new();
+    for (line, text) in lines.iter().enumerate() {
+        for (range, highlight) in highlight_line(text) {
+            let is_definition = match highlight {
+                Highlight::Label => true,
+                Highlight::Symbol => false,
+                _ => continue,
+            };
+            let name = text[range.clone()].trim_end_matches(':');
+            occurrences.push(Occurrence {
+                name: name.to_owned(),
+                line,
+                range: range.start..range.start + name.len(),
+                is_definition,
+            });
+        }
+    }
+    occurrences
+}
+
+fn occurrence_at(occurrences: &[Occurrence], line: usize, byte: usize) -> Option<&Occurrence> {
+    occurrences
+        .iter()
+        .find(|o| o.line == line && o.range.start <= byte && byte <= o.range.end)
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn location(uri: &Value, lines: &[&str], occurrence: &Occurrence) -> Value {
+    json!({
+        "uri": uri,
+        "range": range_json(lines[occurrence.line], occurrence.line, occurrence.range.clone()),
+    })
+}
+
+fn range_json(text: &str, line: usize, range: Range<usize>) -> Value {
+    json!({
+        "start": position(line, utf16_column(text, range.start)),
+        "end": position(line, utf16_column(text, range.end)),
+    })
+}
+
+fn position(line: usize, character: usize) -> Value {
+    json!({ "line": line, "character": character })
+}
+
+/// Converts a byte index in `line` to the UTF-16 column positions are measured in.
+fn utf16_column(line: &str, byte: usize) -> usize {
+    line[..byte].encode_utf16().count()
+}
+
+/// Converts a UTF-16 column in `line` to a byte index, clamped to the end of the line.
+fn utf16_byte(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+/// Converts a char index in `line`, as used in spans, to a byte index.
+fn char_byte(line: &str, index: usize) -> usize {
+    line.char_indices()
+        .nth(index)
+        .map_or(line.len(), |(i, _)| i)
+}
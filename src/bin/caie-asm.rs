@@ -0,0 +1,433 @@
+//! Command-line runner for CAIE assembly programs.
+//!
+//! Assembles a source file, loads it the same way "Assemble and load" does, executes it until
+//! it stops, then prints the console output, the final registers and why execution stopped.
+//! It can also lint or format a source file, or disassemble memory exported from the app.
+
+use std::{ops::ControlFlow, process::ExitCode};
+
+use eframe_template::{
+    assembler, disassembler::disassemble, encoding::Encoding, format::format_source,
+    instruction_set::InstructionSet, lint::lint, number_format::NumberFormat, Diagnostic,
+    ExecutionInfo, Machine, MemoryData, StepOutcome,
+};
+use serde::Deserialize;
+
+const USAGE: &str = "Usage: caie-asm run <file> [options]
+       caie-asm lint <file> [--load-at <address>] [--instruction-set <name>]
+       caie-asm fmt <file> [--write | --check]
+       caie-asm disasm <exported state> [--load-at <address>] [--hex]
+
+Run options:
+    --load-at <address>   Memory address to load the program to and start executing from
+                          (decimal, or hex with a & prefix) [default: 0]
+    --input <text>        Characters fed to IN instructions, one per instruction
+    --max-steps <n>       Number of instructions to execute before giving up [default: 10000]
+    --machine-code        Store instructions as 16-bit words (8-bit opcode, 8-bit operand)
+    --instruction-set <name>
+                          Instruction set to accept: 9618, 9608 or exam-only [default: 9618]
+
+Lint options:
+    --load-at <address>   Memory address the program is loaded to [default: 0]
+    --instruction-set <name>
+                          Instruction set to accept: 9618, 9608 or exam-only [default: 9618]
+
+Format options:
+    --write               Replace the file with the formatted source instead of printing it
+    --check               Only check whether the file is already formatted
+
+Disassemble options:
+    --load-at <address>   Address to start disassembling from [default: 0]
+    --hex                 Write values as &hh instead of #n
+
+Exit status:
+    0   the program reached END, lint found nothing to report, or the source was formatted
+    1   the command line or the source file could not be used
+    2   the assembler reported an error
+    3   execution was aborted, ran out of input or exceeded --max-steps
+    4   lint reported warnings, or fmt --check found the file is not formatted";
+
+/// The part of a state exported from the app that is needed to disassemble it.
+#[derive(Deserialize)]
+struct ExportedState {
+    memory: [[MemoryData; 16]; 16],
+    #[serde(default)]
+    encoding: Option<Encoding>,
+}
+
+struct DisasmOptions {
+    path: String,
+    load_at: u16,
+    hex: bool,
+}
+
+struct LintOptions {
+    path: String,
+    load_at: u16,
+    instruction_set: InstructionSet,
+}
+
+struct FmtOptions {
+    path: String,
+    write: bool,
+    check: bool,
+}
+
+struct RunOptions {
+    path: String,
+    load_at: u16,
+    input: String,
+    max_steps: u64,
+    machine_code: bool,
+    instruction_set: InstructionSet,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("run") => match parse_run_options(&args[1..]) {
+            Ok(options) => run(&options),
+            Err(e) => {
+                eprintln!("error: {}\n\n{}", e, USAGE);
+                ExitCode::from(1)
+            }
+        },
+        Some("lint") => match parse_lint_options(&args[1..]) {
+            Ok(options) => lint_file(&options),
+            Err(e) => {
+                eprintln!("error: {}\n\n{}", e, USAGE);
+                ExitCode::from(1)
+            }
+        },
+        Some("fmt") => match parse_fmt_options(&args[1..]) {
+            Ok(options) => fmt(&options),
+            Err(e) => {
+                eprintln!("error: {}\n\n{}", e, USAGE);
+                ExitCode::from(1)
+            }
+        },
+        Some("disasm") => match parse_disasm_options(&args[1..]) {
+            Ok(options) => disasm(&options),
+            Err(e) => {
+                eprintln!("error: {}\n\n{}", e, USAGE);
+                ExitCode::from(1)
+            }
+        },
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn parse_run_options(args: &[String]) -> Result<RunOptions, String> {
+    let mut path = None;
+    let mut load_at = 0;
+    let mut input = String::new();
+    let mut max_steps = 10000;
+    let mut machine_code = false;
+    let mut instruction_set = InstructionSet::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} expects a value", name))
+        };
+        match arg.as_str() {
+            "--load-at" => {
+                let v = value(arg)?;
+                load_at = parse_address(&v).ok_or_else(|| format!("invalid address: {}", v))?;
+            }
+            "--input" => input = value(arg)?,
+            "--machine-code" => machine_code = true,
+            "--instruction-set" => {
+                let v = value(arg)?;
+                instruction_set = InstructionSet::from_name(&v)
+                    .ok_or_else(|| format!("unknown instruction set: {}", v))?;
+            }
+            "--max-steps" => {
+                let v = value(arg)?;
+                max_steps = v
+                    .parse()
+                    .map_err(|_| format!("invalid number of steps: {}", v))?;
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    Ok(RunOptions {
+        path: path.ok_or("no source file given")?,
+        load_at,
+        input,
+        max_steps,
+        machine_code,
+        instruction_set,
+    })
+}
+
+fn parse_lint_options(args: &[String]) -> Result<LintOptions, String> {
+    let mut path = None;
+    let mut load_at = 0;
+    let mut instruction_set = InstructionSet::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} expects a value", name))
+        };
+        match arg.as_str() {
+            "--load-at" => {
+                let v = value(arg)?;
+                load_at = parse_address(&v).ok_or_else(|| format!("invalid address: {}", v))?;
+            }
+            "--instruction-set" => {
+                let v = value(arg)?;
+                instruction_set = InstructionSet::from_name(&v)
+                    .ok_or_else(|| format!("unknown instruction set: {}", v))?;
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    Ok(LintOptions {
+        path: path.ok_or("no source file given")?,
+        load_at,
+        instruction_set,
+    })
+}
+
+fn parse_fmt_options(args: &[String]) -> Result<FmtOptions, String> {
+    let mut path = None;
+    let mut write = false;
+    let mut check = false;
+    for arg in args {
+        match arg.as_str() {
+            "--write" => write = true,
+            "--check" => check = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    if write && check {
+        return Err("--write and --check cannot be used together".to_owned());
+    }
+    Ok(FmtOptions {
+        path: path.ok_or("no source file given")?,
+        write,
+        check,
+    })
+}
+
+fn parse_disasm_options(args: &[String]) -> Result<DisasmOptions, String> {
+    let mut path = None;
+    let mut load_at = 0;
+    let mut hex = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--load-at" => {
+                let v = args.next().ok_or("--load-at expects a value")?;
+                load_at = parse_address(v).ok_or_else(|| format!("invalid address: {}", v))?;
+            }
+            "--hex" => hex = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    Ok(DisasmOptions {
+        path: path.ok_or("no exported state given")?,
+        load_at,
+        hex,
+    })
+}
+
+fn parse_address(s: &str) -> Option<u16> {
+    let address = match s.strip_prefix('&') {
+        Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+        None => s.parse().ok()?,
+    };
+    (address <= 255).then_some(address)
+}
+
+fn run(options: &RunOptions) -> ExitCode {
+    let source = match std::fs::read_to_string(&options.path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("error: cannot read {}: {}", options.path, e);
+            return ExitCode::from(1);
+        }
+    };
+    let assembled = assembler::assemble_for(&source, options.load_at, options.instruction_set)
+        .and_then(|mut program| {
+            if options.machine_code {
+                program.encode(&Encoding::default())?;
+            }
+            Ok(program)
+        });
+    let program = match assembled {
+        Ok(program) => program,
+        Err(diagnostics) => {
+            print_diagnostics(&options.path, &diagnostics);
+            return ExitCode::from(2);
+        }
+    };
+    print_diagnostics(&options.path, &program.warnings);
+    let mut machine = Machine::default();
+    machine.load_program(&program);
+    machine.pc = program.entry_address();
+
+    let (outcome, steps) =
+        machine.run_with_input(&mut options.input.chars(), options.max_steps, |_, _| {
+            ControlFlow::Continue(())
+        });
+    let info = match outcome {
+        StepOutcome::Continue => unreachable!("nothing pauses the run"),
+        StepOutcome::AwaitingInput => None,
+        StepOutcome::Stopped(info) => Some(info),
+    };
+
+    print!("{}", machine.output);
+    if !machine.output.is_empty() && !machine.output.ends_with('\n') {
+        println!();
+    }
+    println!();
+    print_registers(&machine, steps);
+    match info {
+        Some(info @ ExecutionInfo::ExecutionTerminated { .. }) => {
+            println!("{}", info);
+            ExitCode::SUCCESS
+        }
+        Some(info) => {
+            eprintln!("error: {}", info);
+            ExitCode::from(3)
+        }
+        None => {
+            eprintln!(
+                "error: IN at address {:02X} requested input, but --input has run out",
+                machine.pc.wrapping_sub(1)
+            );
+            ExitCode::from(3)
+        }
+    }
+}
+
+fn lint_file(options: &LintOptions) -> ExitCode {
+    let source = match std::fs::read_to_string(&options.path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("error: cannot read {}: {}", options.path, e);
+            return ExitCode::from(1);
+        }
+    };
+    let mut program =
+        match assembler::assemble_for(&source, options.load_at, options.instruction_set) {
+            Ok(program) => program,
+            Err(diagnostics) => {
+                print_diagnostics(&options.path, &diagnostics);
+                return ExitCode::from(2);
+            }
+        };
+    program.warnings.extend(lint(&program));
+    program
+        .warnings
+        .sort_by_key(|d| (d.span().line, d.span().start));
+    print_diagnostics(&options.path, &program.warnings);
+    if program.warnings.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(4)
+    }
+}
+
+fn fmt(options: &FmtOptions) -> ExitCode {
+    let source = match std::fs::read_to_string(&options.path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("error: cannot read {}: {}", options.path, e);
+            return ExitCode::from(1);
+        }
+    };
+    let formatted = format_source(&source);
+    if options.check {
+        if formatted == source {
+            return ExitCode::SUCCESS;
+        }
+        eprintln!("{} is not formatted", options.path);
+        return ExitCode::from(4);
+    }
+    if !options.write {
+        print!("{}", formatted);
+        return ExitCode::SUCCESS;
+    }
+    match std::fs::write(&options.path, formatted) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: cannot write {}: {}", options.path, e);
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn disasm(options: &DisasmOptions) -> ExitCode {
+    let state = std::fs::read_to_string(&options.path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_str::<ExportedState>(&json).map_err(|e| e.to_string()));
+    match state {
+        Ok(state) => {
+            let format = if options.hex {
+                NumberFormat::Hexadecimal
+            } else {
+                NumberFormat::Decimal
+            };
+            print!(
+                "{}",
+                disassemble(
+                    state.memory.as_flattened(),
+                    options.load_at,
+                    state.encoding.as_ref(),
+                    format
+                )
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: cannot read {}: {}", options.path, e);
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn print_diagnostics(path: &str, diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        let span = diagnostic.span();
+        eprintln!("{}:{}:{}: {}", path, span.line, span.start + 1, diagnostic);
+    }
+}
+
+fn print_registers(machine: &Machine, steps: u64) {
+    let mdr = match machine.mdr {
+        MemoryData::Instruction(opcode, operand) => format!("{} {}", opcode, operand),
+        MemoryData::Value(v) => format!("{:04X}", v),
+    };
+    println!("PC   {:02X}", machine.pc);
+    println!("CIR  {} {}", machine.cir.0, machine.cir.1);
+    println!("IX   {:04X}", machine.ix);
+    println!("MDR  {}", mdr);
+    println!("MAR  {:02X}", machine.mar);
+    println!("ACC  {:04X}", machine.acc);
+    println!(
+        "Flags: carry={} zero={} overflow={} sign={}",
+        machine.carry as u8, machine.zero as u8, machine.overflow as u8, machine.sign as u8
+    );
+    println!("Instructions executed: {}", steps);
+}
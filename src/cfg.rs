@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+
+use crate::{assembler::Program, MemoryData, Opcode, Operand};
+
+/// A program split into basic blocks: runs of instructions that are always executed in order.
+pub struct ControlFlowGraph {
+    /// Blocks in address order.
+    pub blocks: Vec<BasicBlock>,
+}
+
+pub struct BasicBlock {
+    /// The label at the start of the block, if it has one.
+    pub label: Option<String>,
+    /// Address, opcode and operand of each instruction.
+    pub instructions: Vec<(u16, Opcode, Operand)>,
+    /// Where execution can go after the last instruction. Empty if it stops there.
+    pub edges: Vec<Edge>,
+}
+
+impl BasicBlock {
+    pub fn start(&self) -> u16 {
+        self.instructions[0].0
+    }
+
+    /// Returns whether the block holds the instruction at `address`.
+    pub fn contains(&self, address: u16) -> bool {
+        self.instructions.iter().any(|&(a, _, _)| a == address)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction.
+    Next,
+    /// An unconditional `JMP`.
+    Jump,
+    /// A `JPE` or `JPN` that jumps.
+    Taken,
+    /// A `JPE` or `JPN` that does not jump.
+    NotTaken,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Edge {
+    pub kind: EdgeKind,
+    pub address: u16,
+    /// Index of the block starting at `address`, or `None` if there is no code there, e.g. a
+    /// jump into data or out of the program.
+    pub target: Option<usize>,
+}
+
+impl ControlFlowGraph {
+    /// Builds the graph of the code reachable from the entry address of a program or one of its
+    /// code labels.
+    ///
+    /// Blocks start at labels and jump targets, and end at `JMP`, `JPE`, `JPN` and `END`.
+    /// Instructions encoded as machine code are decoded, as the emulator would when executing
+    /// them.
+    pub fn new(program: &Program) -> Self {
+        let instruction = |index: usize| match *program.words.get(index)? {
+            MemoryData::Instruction(opcode, operand) => Some((opcode, operand)),
+            MemoryData::Value(word) => program.encoding.as_ref()?.decode(word),
+        };
+        let offset_of = |address: u16| {
+            address
+                .checked_sub(program.load_address)
+                .map(usize::from)
+                .filter(|&offset| offset < program.words.len())
+        };
+
+        let mut leaders: BTreeSet<usize> = program
+            .definitions
+            .iter()
+            .filter(|(_, definition)| definition.is_code)
+            .filter_map(|(label, _)| offset_of(program.address_of(label)?))
+            .collect();
+        leaders.insert(usize::from(program.entry_address() - program.load_address));
+
+        // Find every instruction that can be executed from a leader
+        let mut code = BTreeSet::new();
+        let mut stack: Vec<usize> = leaders.iter().copied().collect();
+        while let Some(i) = stack.pop() {
+            let Some((opcode, operand)) = instruction(i) else {
+                continue;
+            };
+            if !code.insert(i) {
+                continue;
+            }
+            let target = match (opcode, operand) {
+                (Opcode::Jmp | Opcode::Jpe | Opcode::Jpn, Operand::Address(a)) => offset_of(a),
+                _ => None,
+            };
+            if let Some(target) = target {
+                leaders.insert(target);
+                stack.push(target);
+            }
+            match opcode {
+                Opcode::End | Opcode::Jmp => (),
+                Opcode::Jpe | Opcode::Jpn => {
+                    leaders.insert(i + 1);
+                    stack.push(i + 1);
+                }
+                _ => stack.push(i + 1),
+            }
+        }
+
+        let address_of = |index: usize| program.load_address + index as u16;
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        let mut previous = None;
+        for &i in &code {
+            let (opcode, operand) = instruction(i).expect("code is decoded above");
+            let continues = previous.is_some_and(|p: usize| {
+                p + 1 == i && !leaders.contains(&i) && !ends_block(instruction(p))
+            });
+            if !continues {
+                blocks.push(BasicBlock {
+                    label: program
+                        .symbols
+                        .iter()
+                        .find(|&(_, &a)| a == address_of(i))
+                        .map(|(label, _)| label.clone()),
+                    instructions: Vec::new(),
+                    edges: Vec::new(),
+                });
+            }
+            let block = blocks.last_mut().expect("a block was pushed above");
+            block.instructions.push((address_of(i), opcode, operand));
+            previous = Some(i);
+        }
+
+        for block in &mut blocks {
+            let &(address, opcode, operand) =
+                block.instructions.last().expect("blocks are not empty");
+            let next = address.wrapping_add(1);
+            block.edges = match (opcode, operand) {
+                (Opcode::End, _) => vec![],
+                (Opcode::Jmp, Operand::Address(a)) => vec![(EdgeKind::Jump, a)],
+                (Opcode::Jpe | Opcode::Jpn, Operand::Address(a)) => {
+                    vec![(EdgeKind::Taken, a), (EdgeKind::NotTaken, next)]
+                }
+                _ => vec![(EdgeKind::Next, next)],
+            }
+            .into_iter()
+            .map(|(kind, address)| Edge {
+                kind,
+                address,
+                target: None,
+            })
+            .collect();
+        }
+        let starts: Vec<u16> = blocks.iter().map(BasicBlock::start).collect();
+        for block in &mut blocks {
+            for edge in &mut block.edges {
+                edge.target = starts.iter().position(|&s| s == edge.address);
+            }
+        }
+        ControlFlowGraph { blocks }
+    }
+
+    /// Returns the index of the block holding the instruction at `address`.
+    pub fn block_of(&self, address: u16) -> Option<usize> {
+        self.blocks.iter().position(|block| block.contains(address))
+    }
+}
+
+fn ends_block(instruction: Option<(Opcode, Operand)>) -> bool {
+    matches!(
+        instruction,
+        Some((Opcode::End | Opcode::Jmp | Opcode::Jpe | Opcode::Jpn, _)) | None
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::assemble, encoding::Encoding};
+
+    /// The start of a block, and the kind and target block of each of its edges.
+    type Summary = (u16, Vec<(EdgeKind, Option<usize>)>);
+
+    fn starts_and_edges(graph: &ControlFlowGraph) -> Vec<Summary> {
+        graph
+            .blocks
+            .iter()
+            .map(|block| {
+                let edges = block.edges.iter().map(|e| (e.kind, e.target)).collect();
+                (block.start(), edges)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn splits_blocks_at_labels_and_jumps() {
+        let program = assemble("LDM #3\nloop: DEC ACC\nJPN loop\nEND", 0).unwrap();
+        let graph = ControlFlowGraph::new(&program);
+        assert_eq!(
+            starts_and_edges(&graph),
+            [
+                (0, vec![(EdgeKind::Next, Some(1))]),
+                (
+                    1,
+                    vec![(EdgeKind::Taken, Some(1)), (EdgeKind::NotTaken, Some(2))]
+                ),
+                (3, vec![]),
+            ]
+        );
+        assert_eq!(graph.blocks[1].label.as_deref(), Some("loop"));
+        assert_eq!(graph.block_of(2), Some(1));
+    }
+
+    #[test]
+    fn jumps_into_data_have_no_target() {
+        let program = assemble("JMP data\nEND\ndata: #1", 0).unwrap();
+        let graph = ControlFlowGraph::new(&program);
+        assert_eq!(
+            starts_and_edges(&graph),
+            [(0, vec![(EdgeKind::Jump, None)])]
+        );
+        assert_eq!(graph.blocks[0].edges[0].address, 2);
+    }
+
+    #[test]
+    fn encoded_programs_are_decoded() {
+        let source = "start: LDM #1\nJPE start\nEND";
+        let mut program = assemble(source, 8).unwrap();
+        program.encode(&Encoding::default()).unwrap();
+        assert_eq!(
+            starts_and_edges(&ControlFlowGraph::new(&program)),
+            starts_and_edges(&ControlFlowGraph::new(&assemble(source, 8).unwrap()))
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    encoding::Encoding, number_format::NumberFormat, trace::format_immediate, MemoryData, Opcode,
+    Operand,
+};
+
+/// Turns memory contents back into source code that assembles to the same memory when loaded at
+/// `load_address`.
+///
+/// Cells from `load_address` up to the last non-zero cell (or the last address an instruction
+/// refers to, if that is further) are emitted, one line each. Jump targets get `label_XX` labels
+/// and other addresses used by instructions get `data_XX` labels, where `XX` is the address in
+/// hexadecimal. Values are written as `#n`, `&hh` or `Bbbbb`, depending on `format`.
+///
+/// If `encoding` is given, values that are the machine code of an instruction are written as
+/// that instruction, so the disassembly assembles and encodes to the same memory.
+pub fn disassemble(
+    memory: &[MemoryData],
+    load_address: u16,
+    encoding: Option<&Encoding>,
+    format: NumberFormat,
+) -> String {
+    let start = load_address as usize;
+    let words = memory.get(start..).unwrap_or_default();
+    let memory: Vec<MemoryData> = words
+        .iter()
+        .map(|&data| match (data, encoding) {
+            (MemoryData::Value(word), Some(encoding)) => encoding
+                .decode(word)
+                .filter(|&(opcode, operand)| encoding.encode(opcode, operand) == Some(word))
+                .map_or(data, |(opcode, operand)| {
+                    MemoryData::Instruction(opcode, operand)
+                }),
+            _ => data,
+        })
+        .collect();
+
+    let mut labels = BTreeMap::new();
+    for data in &memory {
+        if let MemoryData::Instruction(opcode, Operand::Address(a)) = data {
+            let Some(offset) = a.checked_sub(load_address).map(usize::from) else {
+                continue;
+            };
+            if offset >= memory.len() {
+                continue;
+            }
+            if matches!(opcode, Opcode::Jmp | Opcode::Jpe | Opcode::Jpn) {
+                labels.insert(offset, format!("label_{:02X}", a));
+            } else {
+                labels
+                    .entry(offset)
+                    .or_insert_with(|| format!("data_{:02X}", a));
+            }
+        }
+    }
+    // Zero words are left out at the end even if they decode as `END`
+    let last_used = words
+        .iter()
+        .rposition(|data| *data != MemoryData::Value(0))
+        .into_iter()
+        .chain(labels.keys().last().copied())
+        .max();
+    let Some(last_used) = last_used else {
+        return String::new();
+    };
+
+    let mut source = String::new();
+    for (offset, data) in memory[..=last_used].iter().enumerate() {
+        if let Some(label) = labels.get(&offset) {
+            if offset > 0 {
+                source.push('\n');
+            }
+            source += &format!("{}:\n", label);
+        }
+        let line = match *data {
+            MemoryData::Value(v) => format_immediate(v, format),
+            MemoryData::Instruction(opcode, operand) => {
+                let operand = match operand {
+                    Operand::Empty => String::new(),
+                    Operand::Register(r) => r.to_string(),
+                    Operand::Immediate(v) => format_immediate(v, format),
+                    Operand::Address(a) => a
+                        .checked_sub(load_address)
+                        .and_then(|offset| labels.get(&(offset as usize)))
+                        .cloned()
+                        .unwrap_or_else(|| a.to_string()),
+                };
+                format!("{} {}", opcode, operand).trim_end().to_owned()
+            }
+        };
+        source += &format!("    {}\n", line);
+    }
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::assemble, Machine};
+
+    const SOURCE: &str = "start: LDD count
+      OUT
+      JMP start
+count: #65
+      &1F";
+
+    fn memory(encoding: Option<&Encoding>) -> Machine {
+        let mut program = assemble(SOURCE, 0).unwrap();
+        if let Some(encoding) = encoding {
+            program.encode(encoding).unwrap();
+        }
+        let mut machine = Machine::default();
+        machine.load_program(&program);
+        machine
+    }
+
+    #[test]
+    fn disassembles_labels_and_values() {
+        let machine = memory(None);
+        assert_eq!(
+            disassemble(
+                machine.memory.as_flattened(),
+                0,
+                None,
+                NumberFormat::Decimal
+            ),
+            "label_00:\n    LDD data_03\n    OUT\n    JMP label_00\n\ndata_03:\n    #65\n    #31\n"
+        );
+    }
+
+    #[test]
+    fn encoded_memory_disassembles_like_symbolic_memory() {
+        let encoding = Encoding::default();
+        let encoded = memory(Some(&encoding));
+        let disassembly = disassemble(
+            encoded.memory.as_flattened(),
+            0,
+            encoded.encoding.as_ref(),
+            NumberFormat::Hexadecimal,
+        );
+        let symbolic = memory(None);
+        assert_eq!(
+            disassembly,
+            disassemble(
+                symbolic.memory.as_flattened(),
+                0,
+                None,
+                NumberFormat::Hexadecimal
+            )
+        );
+
+        let mut program = assemble(&disassembly, 0).unwrap();
+        program.encode(&encoding).unwrap();
+        assert_eq!(
+            program.words,
+            encoded.memory.as_flattened()[..program.words.len()]
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Opcode, Operand, Register};
+
+/// The kind of operand an instruction takes, which decides how it is encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OperandKind {
+    None,
+    Immediate,
+    Address,
+    /// Register operands are part of the opcode, so `INC IX` and `INC ACC` are different forms.
+    Register(Register),
+}
+
+impl OperandKind {
+    pub fn of(operand: Operand) -> Self {
+        match operand {
+            Operand::Empty => OperandKind::None,
+            Operand::Immediate(_) => OperandKind::Immediate,
+            Operand::Address(_) => OperandKind::Address,
+            Operand::Register(r) => OperandKind::Register(r),
+        }
+    }
+}
+
+/// Every valid combination of opcode and operand kind.
+pub const INSTRUCTION_FORMS: [(Opcode, OperandKind); 33] = [
+    (Opcode::End, OperandKind::None),
+    (Opcode::Ldm, OperandKind::Immediate),
+    (Opcode::Ldd, OperandKind::Address),
+    (Opcode::Ldi, OperandKind::Address),
+    (Opcode::Ldx, OperandKind::Address),
+    (Opcode::Ldr, OperandKind::Immediate),
+    (Opcode::Mov, OperandKind::Register(Register::Ix)),
+    (Opcode::Mov, OperandKind::Register(Register::Acc)),
+    (Opcode::Sto, OperandKind::Address),
+    (Opcode::Add, OperandKind::Immediate),
+    (Opcode::Add, OperandKind::Address),
+    (Opcode::Sub, OperandKind::Immediate),
+    (Opcode::Sub, OperandKind::Address),
+    (Opcode::Inc, OperandKind::Register(Register::Ix)),
+    (Opcode::Inc, OperandKind::Register(Register::Acc)),
+    (Opcode::Dec, OperandKind::Register(Register::Ix)),
+    (Opcode::Dec, OperandKind::Register(Register::Acc)),
+    (Opcode::Jmp, OperandKind::Address),
+    (Opcode::Cmp, OperandKind::Immediate),
+    (Opcode::Cmp, OperandKind::Address),
+    (Opcode::Cmi, OperandKind::Address),
+    (Opcode::Jpe, OperandKind::Address),
+    (Opcode::Jpn, OperandKind::Address),
+    (Opcode::In, OperandKind::None),
+    (Opcode::Out, OperandKind::None),
+    (Opcode::And, OperandKind::Immediate),
+    (Opcode::And, OperandKind::Address),
+    (Opcode::Xor, OperandKind::Immediate),
+    (Opcode::Xor, OperandKind::Address),
+    (Opcode::Or, OperandKind::Immediate),
+    (Opcode::Or, OperandKind::Address),
+    (Opcode::Lsl, OperandKind::Immediate),
+    (Opcode::Lsr, OperandKind::Immediate),
+];
+
+/// How an instruction form is written in source code, e.g. `LDM #n`.
+pub fn form_syntax(opcode: Opcode, kind: OperandKind) -> String {
+    match kind {
+        OperandKind::None => opcode.to_string(),
+        OperandKind::Immediate => format!("{} #n", opcode),
+        OperandKind::Address => format!("{} <address>", opcode),
+        OperandKind::Register(r) => format!("{} {}", opcode, r),
+    }
+}
+
+/// A bit layout for storing instructions as 16-bit words.
+///
+/// The opcode is stored in the `opcode_bits` high bits of a word and the operand in the remaining
+/// low bits. Register operands are encoded in the opcode, and their operand field is zero.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Encoding {
+    pub opcode_bits: u8,
+    /// The opcode of each instruction form, indexed like [`INSTRUCTION_FORMS`].
+    pub opcodes: Vec<u16>,
+}
+
+impl Default for Encoding {
+    /// An 8-bit opcode followed by an 8-bit operand, with `END` encoded as zero so that empty
+    /// memory stops execution.
+    fn default() -> Self {
+        Self {
+            opcode_bits: 8,
+            opcodes: (0..INSTRUCTION_FORMS.len() as u16).collect(),
+        }
+    }
+}
+
+impl Encoding {
+    pub const MIN_OPCODE_BITS: u8 = 6;
+    pub const MAX_OPCODE_BITS: u8 = 12;
+
+    pub fn operand_bits(&self) -> u8 {
+        16u8.saturating_sub(self.opcode_bits)
+    }
+
+    /// The largest operand that fits in the operand field.
+    pub fn max_operand(&self) -> u16 {
+        u16::MAX.checked_shr(self.opcode_bits.into()).unwrap_or(0)
+    }
+
+    /// Encodes an instruction. Returns `None` if it is not a valid form or its operand is too
+    /// large.
+    pub fn encode(&self, opcode: Opcode, operand: Operand) -> Option<u16> {
+        let kind = OperandKind::of(operand);
+        let form = INSTRUCTION_FORMS
+            .iter()
+            .position(|&(o, k)| o == opcode && k == kind)?;
+        let value = match operand {
+            Operand::Immediate(v) | Operand::Address(v) => v,
+            Operand::Register(_) | Operand::Empty => 0,
+        };
+        if value > self.max_operand() {
+            return None;
+        }
+        let code = self.opcodes.get(form)?;
+        Some(code.checked_shl(self.operand_bits().into()).unwrap_or(0) | value)
+    }
+
+    /// Decodes a word. Returns `None` if its opcode field is not assigned to any instruction form.
+    pub fn decode(&self, word: u16) -> Option<(Opcode, Operand)> {
+        let code = word.checked_shr(self.operand_bits().into()).unwrap_or(0);
+        let value = word & self.max_operand();
+        let form = self.opcodes.iter().position(|&c| c == code)?;
+        let (opcode, kind) = *INSTRUCTION_FORMS.get(form)?;
+        let operand = match kind {
+            OperandKind::None => Operand::Empty,
+            OperandKind::Immediate => Operand::Immediate(value),
+            OperandKind::Address => Operand::Address(value),
+            OperandKind::Register(r) => Operand::Register(r),
+        };
+        Some((opcode, operand))
+    }
+
+    /// Checks that the layout can be used, returning why it cannot otherwise.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(Self::MIN_OPCODE_BITS..=Self::MAX_OPCODE_BITS).contains(&self.opcode_bits) {
+            return Err(format!(
+                "the opcode must be {} to {} bits wide",
+                Self::MIN_OPCODE_BITS,
+                Self::MAX_OPCODE_BITS
+            ));
+        }
+        if self.opcodes.len() != INSTRUCTION_FORMS.len() {
+            return Err("every instruction form needs an opcode".to_owned());
+        }
+        let max_opcode = u16::MAX
+            .checked_shr(self.operand_bits().into())
+            .unwrap_or(0);
+        for (i, &code) in self.opcodes.iter().enumerate() {
+            let (opcode, kind) = INSTRUCTION_FORMS[i];
+            if code > max_opcode {
+                return Err(format!(
+                    "the opcode of {} does not fit in {} bits",
+                    form_syntax(opcode, kind),
+                    self.opcode_bits
+                ));
+            }
+            if let Some(j) = self.opcodes[..i].iter().position(|&c| c == code) {
+                let (other, other_kind) = INSTRUCTION_FORMS[j];
+                return Err(format!(
+                    "{} and {} have the same opcode",
+                    form_syntax(other, other_kind),
+                    form_syntax(opcode, kind)
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instructions(operand: u16) -> impl Iterator<Item = (Opcode, Operand)> {
+        INSTRUCTION_FORMS.iter().map(move |&(opcode, kind)| {
+            let operand = match kind {
+                OperandKind::None => Operand::Empty,
+                OperandKind::Immediate => Operand::Immediate(operand),
+                OperandKind::Address => Operand::Address(operand),
+                OperandKind::Register(r) => Operand::Register(r),
+            };
+            (opcode, operand)
+        })
+    }
+
+    #[test]
+    fn every_form_decodes_to_what_was_encoded() {
+        let reversed = Encoding {
+            opcode_bits: 6,
+            opcodes: (0..INSTRUCTION_FORMS.len() as u16).rev().collect(),
+        };
+        for encoding in [Encoding::default(), reversed] {
+            assert_eq!(encoding.validate(), Ok(()));
+            for (opcode, operand) in instructions(0).chain(instructions(encoding.max_operand())) {
+                let word = encoding.encode(opcode, operand).unwrap();
+                assert_eq!(encoding.decode(word), Some((opcode, operand)));
+            }
+        }
+    }
+
+    #[test]
+    fn end_is_zero_by_default() {
+        assert_eq!(
+            Encoding::default().encode(Opcode::End, Operand::Empty),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn operands_must_fit_the_operand_field() {
+        let encoding = Encoding::default();
+        assert_eq!(encoding.max_operand(), 255);
+        assert_eq!(encoding.encode(Opcode::Ldm, Operand::Immediate(256)), None);
+        assert_eq!(encoding.encode(Opcode::Ldm, Operand::Address(1)), None);
+    }
+
+    #[test]
+    fn rejects_unusable_layouts() {
+        let mut encoding = Encoding {
+            opcode_bits: 4,
+            ..Encoding::default()
+        };
+        assert!(encoding.validate().is_err());
+        encoding.opcode_bits = 8;
+        encoding.opcodes[1] = encoding.opcodes[0];
+        assert!(encoding.validate().unwrap_err().contains("the same opcode"));
+        encoding.opcodes.pop();
+        assert!(encoding.validate().is_err());
+    }
+}
@@ -0,0 +1,344 @@
+use std::{collections::HashMap, iter::Peekable, ops::Range, str::CharIndices};
+
+/// The names an expression can refer to.
+pub(crate) struct Symbols<'a> {
+    /// Labels, with their offset from the start of the program.
+    pub labels: &'a HashMap<&'a str, usize>,
+    pub constants: &'a HashMap<&'a str, u16>,
+    /// The number of words in the statement each label names, for `len(label)`.
+    pub lengths: &'a HashMap<&'a str, usize>,
+}
+
+/// The result of evaluating an expression.
+#[derive(Clone, Copy)]
+pub(crate) struct Value {
+    pub value: i64,
+    /// How many labels were added (or, if negative, subtracted). Labels evaluate to offsets from
+    /// the start of the program, so `value` is only an absolute address once this many load
+    /// addresses are added to it.
+    pub labels: i64,
+}
+
+impl Value {
+    /// The value when the program is loaded at `load_address`.
+    pub fn absolute(&self, load_address: u16) -> i64 {
+        self.value + self.labels * i64::from(load_address)
+    }
+}
+
+pub(crate) enum ExpressionError {
+    Malformed,
+    UndefinedSymbol(String),
+}
+
+/// Returns whether an operand starts with a number that is an immediate on its own, i.e. a
+/// hexadecimal (`&FF`) or binary (`B1010`) number. Decimal numbers are addresses unless they
+/// follow `#`.
+pub(crate) fn starts_with_immediate(text: &str) -> bool {
+    text.starts_with('&') || binary_digits(identifier_at(text)).is_some()
+}
+
+/// Evaluates an expression such as `string+3`, `'A'`, `-1`, `&FF-count` or `len(string)`.
+///
+/// Terms are decimal, `&` hexadecimal and `B` binary numbers, character literals, labels,
+/// constants and `len(label)`, optionally in parentheses and combined with `+` and `-`.
+pub(crate) fn evaluate(text: &str, symbols: &Symbols<'_>) -> Result<Value, ExpressionError> {
+    let mut parser = Parser {
+        text,
+        chars: text.char_indices().peekable(),
+        symbols,
+    };
+    let value = parser.expression()?;
+    match parser.chars.next() {
+        Some(_) => Err(ExpressionError::Malformed),
+        None => Ok(value),
+    }
+}
+
+/// Finds the names of labels and constants used in an expression, with their byte offsets.
+///
+/// Names are found as [`evaluate`] would read them, so `len(label)` yields `label`, but numbers
+/// and character literals are skipped.
+pub(crate) fn symbol_names(text: &str) -> Vec<(usize, &str)> {
+    lex(text)
+        .into_iter()
+        .filter(|(_, lexeme)| *lexeme == Lexeme::Symbol)
+        .map(|(range, _)| (range.start, &text[range]))
+        .collect()
+}
+
+/// A piece of an expression, as found by [`lex`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Lexeme {
+    Decimal,
+    Hexadecimal,
+    Binary,
+    Character,
+    /// The name of a label or constant.
+    Symbol,
+    /// `len`, when followed by `(`.
+    Function,
+    /// Anything else, such as `+` or `(`, one character at a time.
+    Other,
+}
+
+/// Returns the length in bytes of the rest of a character literal after its opening quote: the
+/// character (or escape) and the closing quote, if there is one.
+pub(crate) fn character_literal_len(text: &str) -> usize {
+    let mut chars = text.chars();
+    let skipped = match chars.next() {
+        Some('\\') => 1 + chars.next().map_or(0, char::len_utf8),
+        Some(c) => c.len_utf8(),
+        None => 0,
+    };
+    skipped + usize::from(text[skipped..].starts_with('\''))
+}
+
+/// Splits an expression into the pieces [`evaluate`] reads, with their byte ranges. Whitespace
+/// is skipped, but the text does not have to be a valid expression.
+pub(crate) fn lex(text: &str) -> Vec<(Range<usize>, Lexeme)> {
+    let mut lexemes = Vec::new();
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let (len, lexeme) = if c == '\'' {
+            (
+                1 + character_literal_len(&text[i + 1..]),
+                Some(Lexeme::Character),
+            )
+        } else if c == '&' {
+            (
+                1 + identifier_at(&text[i + 1..]).len(),
+                Some(Lexeme::Hexadecimal),
+            )
+        } else if c.is_alphanumeric() || c == '_' || c == '.' {
+            let word = identifier_at(&text[i..]);
+            let lexeme = if word == "len" && text[i + word.len()..].starts_with('(') {
+                Lexeme::Function
+            } else if binary_digits(word).is_some() {
+                Lexeme::Binary
+            } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+                Lexeme::Decimal
+            } else {
+                Lexeme::Symbol
+            };
+            (word.len(), Some(lexeme))
+        } else if c.is_whitespace() {
+            (c.len_utf8(), None)
+        } else {
+            (c.len_utf8(), Some(Lexeme::Other))
+        };
+        if let Some(lexeme) = lexeme {
+            lexemes.push((i..i + len, lexeme));
+        }
+        i += len;
+    }
+    lexemes
+}
+
+struct Parser<'a, 's> {
+    text: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    symbols: &'s Symbols<'s>,
+}
+
+impl<'a> Parser<'a, '_> {
+    fn expression(&mut self) -> Result<Value, ExpressionError> {
+        let mut value = self.term()?;
+        while let Some(&(_, c @ ('+' | '-'))) = self.chars.peek() {
+            self.chars.next();
+            let term = self.term()?;
+            value = match c {
+                '+' => Value {
+                    value: value.value + term.value,
+                    labels: value.labels + term.labels,
+                },
+                _ => Value {
+                    value: value.value - term.value,
+                    labels: value.labels - term.labels,
+                },
+            };
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<Value, ExpressionError> {
+        let Some(&(_, c)) = self.chars.peek() else {
+            return Err(ExpressionError::Malformed);
+        };
+        match c {
+            '-' => {
+                self.chars.next();
+                let term = self.term()?;
+                Ok(Value {
+                    value: -term.value,
+                    labels: -term.labels,
+                })
+            }
+            '(' => {
+                self.chars.next();
+                let value = self.expression()?;
+                self.expect(')')?;
+                Ok(value)
+            }
+            '\'' => {
+                self.chars.next();
+                let c = match self.chars.next() {
+                    Some((_, '\\')) => match self.chars.next() {
+                        Some((_, 'n')) => '\n',
+                        Some((_, 't')) => '\t',
+                        Some((_, '0')) => '\0',
+                        Some((_, c @ ('\'' | '\\'))) => c,
+                        _ => return Err(ExpressionError::Malformed),
+                    },
+                    Some((_, c)) => c,
+                    None => return Err(ExpressionError::Malformed),
+                };
+                self.expect('\'')?;
+                Ok(number(u32::from(c).into()))
+            }
+            '&' => {
+                self.chars.next();
+                let digits = self.identifier();
+                u32::from_str_radix(digits, 16)
+                    .map(|v| number(v.into()))
+                    .map_err(|_| ExpressionError::Malformed)
+            }
+            _ => {
+                let word = self.identifier();
+                if word.is_empty() {
+                    return Err(ExpressionError::Malformed);
+                }
+                if let Some(digits) = binary_digits(word) {
+                    u32::from_str_radix(digits, 2)
+                        .map(|v| number(v.into()))
+                        .map_err(|_| ExpressionError::Malformed)
+                } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+                    word.parse::<u32>()
+                        .map(|v| number(v.into()))
+                        .map_err(|_| ExpressionError::Malformed)
+                } else if word == "len" && self.chars.peek().is_some_and(|&(_, c)| c == '(') {
+                    self.chars.next();
+                    let label = self.identifier();
+                    self.expect(')')?;
+                    match self.symbols.lengths.get(label) {
+                        Some(&len) => Ok(number(len as i64)),
+                        None => Err(ExpressionError::UndefinedSymbol(label.to_string())),
+                    }
+                } else if let Some(&value) = self.symbols.constants.get(word) {
+                    Ok(number(value.into()))
+                } else if let Some(&offset) = self.symbols.labels.get(word) {
+                    Ok(Value {
+                        value: offset as i64,
+                        labels: 1,
+                    })
+                } else {
+                    Err(ExpressionError::UndefinedSymbol(word.to_string()))
+                }
+            }
+        }
+    }
+
+    /// Consumes a run of letters, digits, `_` and `.`, which may be empty.
+    fn identifier(&mut self) -> &'a str {
+        let start = self.chars.peek().map_or(self.text.len(), |&(i, _)| i);
+        let word = identifier_at(&self.text[start..]);
+        for _ in word.chars() {
+            self.chars.next();
+        }
+        word
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ExpressionError> {
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            _ => Err(ExpressionError::Malformed),
+        }
+    }
+}
+
+fn number(value: i64) -> Value {
+    Value { value, labels: 0 }
+}
+
+/// The identifier at the start of `text`.
+fn identifier_at(text: &str) -> &str {
+    let end = text
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(text.len());
+    &text[..end]
+}
+
+/// The digits of `word` if it is a binary number such as `B1010`.
+fn binary_digits(word: &str) -> Option<&str> {
+    word.strip_prefix('B')
+        .filter(|digits| !digits.is_empty() && digits.chars().all(|c| c == '0' || c == '1'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate_with_symbols(text: &str) -> Result<(i64, i64), ExpressionError> {
+        let labels = HashMap::from([("start", 2)]);
+        let constants = HashMap::from([("size", 3)]);
+        let lengths = HashMap::from([("start", 4)]);
+        let symbols = Symbols {
+            labels: &labels,
+            constants: &constants,
+            lengths: &lengths,
+        };
+        evaluate(text, &symbols).map(|value| (value.value, value.labels))
+    }
+
+    fn value_of(text: &str) -> i64 {
+        match evaluate_with_symbols(text) {
+            Ok((value, 0)) => value,
+            Ok(_) => panic!("{:?} refers to a label", text),
+            Err(_) => panic!("{:?} did not evaluate", text),
+        }
+    }
+
+    #[test]
+    fn evaluates_numbers_and_characters() {
+        assert_eq!(value_of("12"), 12);
+        assert_eq!(value_of("&fF"), 255);
+        assert_eq!(value_of("B101"), 5);
+        assert_eq!(value_of("'A'"), 65);
+        assert_eq!(value_of("'\\n'"), 10);
+        assert_eq!(value_of("'\\''"), 39);
+        assert_eq!(value_of("-1"), -1);
+        assert_eq!(value_of("10-(1+2)-3"), 4);
+    }
+
+    #[test]
+    fn evaluates_constants_lengths_and_labels() {
+        assert_eq!(value_of("size+'0'"), 51);
+        assert_eq!(value_of("len(start)"), 4);
+        assert!(matches!(evaluate_with_symbols("start+size"), Ok((5, 1))));
+        assert!(matches!(evaluate_with_symbols("start-start"), Ok((0, 0))));
+    }
+
+    #[test]
+    fn rejects_malformed_expressions_and_unknown_names() {
+        for text in ["", "1+", "(1", "'ab'", "&", "1 2"] {
+            assert!(
+                matches!(evaluate_with_symbols(text), Err(ExpressionError::Malformed)),
+                "{:?}",
+                text
+            );
+        }
+        assert!(matches!(
+            evaluate_with_symbols("len(missing)"),
+            Err(ExpressionError::UndefinedSymbol(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn only_hexadecimal_and_binary_numbers_are_immediates() {
+        assert!(starts_with_immediate("&FF"));
+        assert!(starts_with_immediate("B101+1"));
+        assert!(!starts_with_immediate("B12"));
+        assert!(!starts_with_immediate("Bob"));
+        assert!(!starts_with_immediate("12"));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{
+    assembler::tokenize,
+    expression::{lex, Lexeme},
+    instruction_set::opcode_of,
+};
+
+/// Columns are multiples of this many characters.
+const TAB_WIDTH: usize = 4;
+
+/// A line of source code split into the parts that are aligned.
+struct Line<'a> {
+    /// A label with its colon, or the name of a constant defined by `EQU`.
+    label: Option<&'a str>,
+    /// The mnemonic, directive or data, followed by any operands.
+    code: Vec<String>,
+    comment: Option<&'a str>,
+    /// Whether the line starts with whitespace, for lines that only hold a comment.
+    indented: bool,
+}
+
+/// Formats source code: labels start lines, mnemonics, operands and `//` comments are aligned
+/// into columns, mnemonics and directives are written in upper case, hexadecimal digits in upper
+/// case, and `#` is dropped from `#&hh` and `#Bbbbb` operands.
+///
+/// Only whitespace, case and redundant prefixes change, so a program that assembles still
+/// assembles to the same words, and formatting the result again changes nothing.
+pub fn format_source(source: &str) -> String {
+    let lines: Vec<Line<'_>> = source.split('\n').map(parse_line).collect();
+
+    let indent = lines
+        .iter()
+        .filter(|line| !line.code.is_empty())
+        .filter_map(|line| line.label)
+        .map(|label| round_up(label.chars().count() + 1))
+        .max()
+        .unwrap_or(0)
+        .max(TAB_WIDTH);
+    let mnemonic_width = lines
+        .iter()
+        .filter(|line| line.code.len() > 1)
+        .map(|line| line.code[0].chars().count() + 1)
+        .max()
+        .unwrap_or(0);
+    let code: Vec<String> = lines
+        .iter()
+        .map(|line| {
+            let mut text = line.label.unwrap_or_default().to_owned();
+            if let Some((first, operands)) = line.code.split_first() {
+                text = format!("{:indent$}{}", text, first);
+                if !operands.is_empty() {
+                    text = format!(
+                        "{:width$}{}",
+                        text,
+                        operands.join(" "),
+                        width = indent + mnemonic_width
+                    );
+                }
+            } else if line.label.is_none() && line.indented {
+                text = " ".repeat(indent);
+            }
+            text
+        })
+        .collect();
+    let comment_column = lines
+        .iter()
+        .zip(&code)
+        .filter(|(line, text)| line.comment.is_some() && !text.trim().is_empty())
+        .map(|(_, text)| round_up(text.chars().count() + 1))
+        .max()
+        .unwrap_or(0);
+
+    let formatted: Vec<String> = lines
+        .iter()
+        .zip(code)
+        .map(|(line, text)| match line.comment {
+            Some(comment) if text.trim().is_empty() => text + comment,
+            Some(comment) => format!("{:comment_column$}{}", text, comment),
+            None => text,
+        })
+        .collect();
+    formatted.join("\n")
+}
+
+fn parse_line(line: &str) -> Line<'_> {
+    let tokens = tokenize(line, 1);
+    let code_end = tokens
+        .last()
+        .map_or(0, |token| token.offset + token.text.len());
+    let comment = line[code_end..]
+        .find("//")
+        .map(|start| line[code_end + start..].trim_end());
+    let indented = line.starts_with(char::is_whitespace);
+
+    let mut parts = tokens.as_slice();
+    let is_constant = parts
+        .get(1)
+        .is_some_and(|t| t.text.eq_ignore_ascii_case("EQU"));
+    let label = parts
+        .first()
+        .filter(|t| t.text.ends_with(':') || is_constant)
+        .map(|t| t.text);
+    if label.is_some() {
+        parts = &parts[1..];
+    }
+    let code = match parts.split_first() {
+        Some((first, operands)) => {
+            let upper = first.text.to_uppercase();
+            let is_instruction = opcode_of(&upper).is_some();
+            let is_keyword = is_instruction || matches!(upper.as_str(), "EQU" | "ORG" | "DS");
+            // Directive operands are left with their `#`
+            let first = if is_keyword {
+                upper
+            } else {
+                format_operand(first.text, true)
+            };
+            std::iter::once(first)
+                .chain(
+                    operands
+                        .iter()
+                        .map(|t| format_operand(t.text, is_instruction)),
+                )
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    Line {
+        label,
+        code,
+        comment,
+        indented,
+    }
+}
+
+/// Writes hexadecimal digits in upper case and, if `drop_hash`, drops the `#` before a lone `&`
+/// or `B` number, which is an immediate either way.
+fn format_operand(text: &str, drop_hash: bool) -> String {
+    if text.starts_with('"') {
+        return text.to_owned();
+    }
+    let mut formatted = String::new();
+    let mut end = 0;
+    for (range, lexeme) in lex(text) {
+        if lexeme == Lexeme::Hexadecimal
+            && text[range.start + 1..range.end]
+                .chars()
+                .all(|c| c.is_ascii_hexdigit())
+        {
+            formatted += &text[end..range.start];
+            formatted += &text[range.clone()].to_uppercase();
+            end = range.end;
+        }
+    }
+    formatted += &text[end..];
+
+    if let Some(number) = formatted.strip_prefix('#').filter(|_| drop_hash) {
+        if let [(range, Lexeme::Hexadecimal | Lexeme::Binary)] = lex(number).as_slice() {
+            if *range == (0..number.len()) {
+                return number.to_owned();
+            }
+        }
+    }
+    formatted
+}
+
+fn round_up(width: usize) -> usize {
+    width.div_ceil(TAB_WIDTH) * TAB_WIDTH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::assemble, MemoryData};
+
+    const SOURCES: [&str; 5] = [
+        "start: LDM #&ff // load\n  ADD  one\nOUT\n    END\none: #1\n",
+        "x EQU 5\n      LDM #x\n  loop:  DEC ACC\n CMP #B0\nJPN loop\n END",
+        "ORG 4\nLDM #'A'\n OUT\nEND\ntext: \"a b\"  // string\nDS 2",
+        "LDM 5 // not an immediate\nEND",
+        "x: LDD missing\n END",
+    ];
+
+    fn words(source: &str) -> Option<Vec<MemoryData>> {
+        assemble(source, 0).ok().map(|program| program.words)
+    }
+
+    #[test]
+    fn aligns_labels_mnemonics_and_comments() {
+        assert_eq!(
+            format_source("start: LDM #&ff // load\n  ADD  one\none: #1"),
+            "start:  LDM &FF // load\n        ADD one\none:    #1"
+        );
+    }
+
+    #[test]
+    fn formatting_twice_changes_nothing() {
+        for source in SOURCES {
+            let formatted = format_source(source);
+            assert_eq!(format_source(&formatted), formatted, "{source:?}");
+        }
+    }
+
+    #[test]
+    fn formatted_source_assembles_to_the_same_words() {
+        for source in SOURCES {
+            assert_eq!(words(&format_source(source)), words(source), "{source:?}");
+        }
+    }
+
+    #[test]
+    fn mnemonics_and_directives_are_upper_case() {
+        let formatted = format_source("ldm #1\nx equ 5\n  Out");
+        assert_eq!(formatted, "    LDM #1\nx   EQU 5\n    OUT");
+        assert_eq!(format_source(&formatted), formatted);
+    }
+}
@@ -0,0 +1,187 @@
+use std::ops::Range;
+
+use crate::{
+    assembler::tokenize,
+    expression::{lex, Lexeme},
+    instruction_set::opcode_of,
+};
+
+/// What a highlighted piece of source code is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Highlight {
+    /// A label or constant where it is defined.
+    Label,
+    Mnemonic,
+    /// `ORG`, `EQU`, `DS` or `len`.
+    Directive,
+    Register,
+    /// A `#` operand, and the decimal numbers in it.
+    Immediate,
+    /// A `&` number.
+    Hexadecimal,
+    /// A `B` number.
+    Binary,
+    /// A decimal number outside a `#` operand, usually an address.
+    Number,
+    Character,
+    String,
+    /// A label or constant where it is used.
+    Symbol,
+    Comment,
+}
+
+/// Finds the parts of a line of source code to colour, as sorted byte ranges in the line.
+/// Whitespace and operators are left out.
+pub fn highlight_line(line: &str) -> Vec<(Range<usize>, Highlight)> {
+    let tokens = tokenize(line, 1);
+    let mut highlights = Vec::new();
+    for (k, token) in tokens.iter().enumerate() {
+        let range = token.offset..token.offset + token.text.len();
+        let is_name =
+            k == 0 && (token.text.ends_with(':') || tokens.get(1).is_some_and(|t| t.text == "EQU"));
+        let highlight = if is_name {
+            Highlight::Label
+        } else if opcode_of(token.text).is_some() {
+            Highlight::Mnemonic
+        } else if matches!(token.text, "EQU" | "ORG" | "DS") {
+            Highlight::Directive
+        } else if matches!(token.text, "ACC" | "IX") {
+            Highlight::Register
+        } else if token.text.starts_with('"') {
+            Highlight::String
+        } else {
+            highlight_operand(token.text, token.offset, &mut highlights);
+            continue;
+        };
+        highlights.push((range, highlight));
+    }
+    let code_end = tokens
+        .last()
+        .map_or(0, |token| token.offset + token.text.len());
+    if let Some(comment) = line[code_end..].find("//") {
+        highlights.push((code_end + comment..line.len(), Highlight::Comment));
+    }
+    highlights
+}
+
+/// Highlights an operand, such as `#'A'`, `@count` or `string+1`, starting at byte `offset`.
+fn highlight_operand(text: &str, offset: usize, highlights: &mut Vec<(Range<usize>, Highlight)>) {
+    let prefix = text.len() - text.trim_start_matches(['@', '~']).len();
+    let expression = &text[prefix..];
+    let immediate = expression.starts_with('#');
+    let start = offset + prefix;
+    if immediate {
+        highlights.push((start..start + 1, Highlight::Immediate));
+    }
+    let expression_start = start + usize::from(immediate);
+    for (range, lexeme) in lex(&text[prefix + usize::from(immediate)..]) {
+        let highlight = match lexeme {
+            Lexeme::Decimal if immediate => Highlight::Immediate,
+            Lexeme::Decimal => Highlight::Number,
+            Lexeme::Hexadecimal => Highlight::Hexadecimal,
+            Lexeme::Binary => Highlight::Binary,
+            Lexeme::Character => Highlight::Character,
+            Lexeme::Symbol => Highlight::Symbol,
+            Lexeme::Function => Highlight::Directive,
+            Lexeme::Other => continue,
+        };
+        highlights.push((
+            expression_start + range.start..expression_start + range.end,
+            highlight,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn highlights(line: &str) -> Vec<(&str, Highlight)> {
+        highlight_line(line)
+            .into_iter()
+            .map(|(range, highlight)| (&line[range], highlight))
+            .collect()
+    }
+
+    #[test]
+    fn highlights_definitions_mnemonics_and_comments() {
+        assert_eq!(
+            highlights("start: LDD count // load"),
+            [
+                ("start:", Highlight::Label),
+                ("LDD", Highlight::Mnemonic),
+                ("count", Highlight::Symbol),
+                ("// load", Highlight::Comment),
+            ]
+        );
+        assert_eq!(
+            highlights("size EQU len(text)"),
+            [
+                ("size", Highlight::Label),
+                ("EQU", Highlight::Directive),
+                ("len", Highlight::Directive),
+                ("text", Highlight::Symbol),
+            ]
+        );
+        assert_eq!(
+            highlights("text: DS \"hi\""),
+            [
+                ("text:", Highlight::Label),
+                ("DS", Highlight::Directive),
+                ("\"hi\"", Highlight::String),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlights_operands() {
+        assert_eq!(
+            highlights("LDM #'A'"),
+            [
+                ("LDM", Highlight::Mnemonic),
+                ("#", Highlight::Immediate),
+                ("'A'", Highlight::Character),
+            ]
+        );
+        assert_eq!(
+            highlights("ADD #10"),
+            [
+                ("ADD", Highlight::Mnemonic),
+                ("#", Highlight::Immediate),
+                ("10", Highlight::Immediate),
+            ]
+        );
+        assert_eq!(
+            highlights("CMP &1F"),
+            [
+                ("CMP", Highlight::Mnemonic),
+                ("&1F", Highlight::Hexadecimal),
+            ]
+        );
+        assert_eq!(
+            highlights("AND B1010"),
+            [("AND", Highlight::Mnemonic), ("B1010", Highlight::Binary)]
+        );
+        assert_eq!(
+            highlights("MOV IX"),
+            [("MOV", Highlight::Mnemonic), ("IX", Highlight::Register)]
+        );
+        assert_eq!(
+            highlights("JMP @data+1"),
+            [
+                ("JMP", Highlight::Mnemonic),
+                ("data", Highlight::Symbol),
+                ("1", Highlight::Number),
+            ]
+        );
+    }
+
+    #[test]
+    fn comment_only_lines_are_comments() {
+        assert_eq!(
+            highlights("  // nothing here"),
+            [("// nothing here", Highlight::Comment)]
+        );
+        assert!(highlights("").is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::VecDeque;
+
+use crate::{Machine, MemoryData, Opcode, Operand, StepOutcome};
+
+/// Maximum number of steps kept. The oldest steps are forgotten first.
+const HISTORY_LIMIT: usize = 100_000;
+
+/// Everything about a [`Machine`] apart from its memory and console output.
+#[derive(Clone, Copy)]
+struct Registers {
+    pc: u16,
+    cir: (Opcode, Operand),
+    ix: u16,
+    mdr: MemoryData,
+    mar: u16,
+    acc: u16,
+    carry: bool,
+    zero: bool,
+    overflow: bool,
+    sign: bool,
+}
+
+impl Registers {
+    fn of(machine: &Machine) -> Self {
+        Self {
+            pc: machine.pc,
+            cir: machine.cir,
+            ix: machine.ix,
+            mdr: machine.mdr,
+            mar: machine.mar,
+            acc: machine.acc,
+            carry: machine.carry,
+            zero: machine.zero,
+            overflow: machine.overflow,
+            sign: machine.sign,
+        }
+    }
+
+    fn restore(&self, machine: &mut Machine) {
+        machine.pc = self.pc;
+        machine.cir = self.cir;
+        machine.ix = self.ix;
+        machine.mdr = self.mdr;
+        machine.mar = self.mar;
+        machine.acc = self.acc;
+        machine.carry = self.carry;
+        machine.zero = self.zero;
+        machine.overflow = self.overflow;
+        machine.sign = self.sign;
+    }
+}
+
+/// The changes made by executing one instruction.
+struct StepRecord {
+    before: Registers,
+    after: Registers,
+    /// `(address, old value, new value)` of every memory cell that changed.
+    memory_writes: Vec<(usize, MemoryData, MemoryData)>,
+    /// Length of the console output before the step, in bytes.
+    output_len: usize,
+    /// Characters printed by the step.
+    output: String,
+}
+
+/// A record of executed instructions that can be undone and redone exactly.
+///
+/// Steps are recorded by executing them through [`History::step`]. After stepping back, the
+/// undone steps can be replayed with [`History::step_forward`] until a new step is executed.
+///
+/// Positions count every step since the history was cleared, including the oldest steps that
+/// have been forgotten, so they can be used as step numbers.
+#[derive(Default)]
+pub struct History {
+    records: VecDeque<StepRecord>,
+    /// Number of steps forgotten because of [`HISTORY_LIMIT`], which came before `records`.
+    forgotten: usize,
+    /// Number of steps currently applied to the machine.
+    position: usize,
+}
+
+impl History {
+    /// Executes one instruction on `machine` and records what it changed.
+    pub fn step(&mut self, machine: &mut Machine) -> StepOutcome {
+        let before = Registers::of(machine);
+        let memory = machine.memory;
+        let output_len = machine.output.len();
+        let outcome = machine.step();
+
+        let memory_writes = memory
+            .as_flattened()
+            .iter()
+            .zip(machine.memory.as_flattened())
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(address, (&old, &new))| (address, old, new))
+            .collect();
+        self.records.truncate(self.position - self.forgotten);
+        self.records.push_back(StepRecord {
+            before,
+            after: Registers::of(machine),
+            memory_writes,
+            output_len,
+            output: machine.output[output_len..].to_owned(),
+        });
+        if self.records.len() > HISTORY_LIMIT {
+            self.records.pop_front();
+            self.forgotten += 1;
+        }
+        self.position = self.len();
+        outcome
+    }
+
+    /// Completes a pending `IN` instruction, so that replaying the step restores the character.
+    pub fn input(&mut self, machine: &mut Machine, c: char) {
+        machine.input(c);
+        let index = (self.position - self.forgotten).checked_sub(1);
+        if let Some(record) = index.map(|i| &mut self.records[i]) {
+            record.after = Registers::of(machine);
+        }
+    }
+
+    /// Undoes the last applied step. Returns `false` if there is nothing to undo.
+    pub fn step_back(&mut self, machine: &mut Machine) -> bool {
+        let index = (self.position - self.forgotten).checked_sub(1);
+        let Some(record) = index.map(|i| &self.records[i]) else {
+            return false;
+        };
+        record.before.restore(machine);
+        let memory = machine.memory.as_flattened_mut();
+        for &(address, old, _) in &record.memory_writes {
+            memory[address] = old;
+        }
+        machine.output.truncate(record.output_len);
+        self.position -= 1;
+        true
+    }
+
+    /// Replays the next undone step. Returns `false` if there is nothing to redo.
+    pub fn step_forward(&mut self, machine: &mut Machine) -> bool {
+        let Some(record) = self.records.get(self.position - self.forgotten) else {
+            return false;
+        };
+        record.after.restore(machine);
+        let memory = machine.memory.as_flattened_mut();
+        for &(address, _, new) in &record.memory_writes {
+            memory[address] = new;
+        }
+        machine.output.truncate(record.output_len);
+        machine.output.push_str(&record.output);
+        self.position += 1;
+        true
+    }
+
+    /// Steps back or forward until `position` steps are applied.
+    pub fn seek(&mut self, machine: &mut Machine, position: usize) {
+        while self.position > position && self.step_back(machine) {}
+        while self.position < position && self.step_forward(machine) {}
+    }
+
+    /// Number of steps currently applied to the machine.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The earliest position that can be sought to. The steps before it have been forgotten.
+    pub fn first(&self) -> usize {
+        self.forgotten
+    }
+
+    /// Number of steps executed, including undone and forgotten ones.
+    pub fn len(&self) -> usize {
+        self.forgotten + self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Address of the instruction executed by step `index` (0-based), unless it is forgotten.
+    pub fn address_of(&self, index: usize) -> Option<u16> {
+        let record = self.records.get(index.checked_sub(self.forgotten)?)?;
+        Some(record.before.pc)
+    }
+
+    /// Forgets every recorded step, e.g. after memory is replaced outside of execution.
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.forgotten = 0;
+        self.position = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn machine(source: &str) -> Machine {
+        let mut machine = Machine::default();
+        machine.load_program(&assemble(source, 0).unwrap());
+        machine
+    }
+
+    #[test]
+    fn steps_are_undone_and_redone_exactly() {
+        let mut machine = machine("LDM #65\nSTO 10\nOUT\nEND");
+        let mut history = History::default();
+        for _ in 0..3 {
+            history.step(&mut machine);
+        }
+        assert_eq!(machine.output, "A");
+        let after = machine.clone();
+
+        history.seek(&mut machine, 0);
+        assert_eq!(history.position(), 0);
+        assert_eq!((machine.pc, machine.acc), (0, 0));
+        assert_eq!(machine.memory.as_flattened()[10], MemoryData::Value(0));
+        assert_eq!(machine.output, "");
+
+        history.seek(&mut machine, 3);
+        assert_eq!((machine.pc, machine.acc), (after.pc, after.acc));
+        assert_eq!(machine.memory, after.memory);
+        assert_eq!(machine.output, "A");
+        assert_eq!(history.address_of(1), Some(1));
+    }
+
+    #[test]
+    fn stepping_after_going_back_discards_later_steps() {
+        let mut machine = machine("INC ACC\nINC ACC\nINC ACC\nEND");
+        let mut history = History::default();
+        history.step(&mut machine);
+        history.step(&mut machine);
+        history.step_back(&mut machine);
+        history.step(&mut machine);
+        assert_eq!(history.len(), 2);
+        assert!(!history.step_forward(&mut machine));
+    }
+
+    #[test]
+    fn positions_keep_counting_after_old_steps_are_forgotten() {
+        let mut machine = machine("loop: JMP loop");
+        let mut history = History::default();
+        for _ in 0..HISTORY_LIMIT + 5 {
+            history.step(&mut machine);
+        }
+        assert_eq!(history.first(), 5);
+        assert_eq!(history.position(), HISTORY_LIMIT + 5);
+        assert_eq!(history.len(), HISTORY_LIMIT + 5);
+        assert_eq!(history.address_of(4), None);
+        assert_eq!(history.address_of(5), Some(0));
+
+        history.seek(&mut machine, 0);
+        assert_eq!(history.position(), 5);
+        assert!(!history.step_back(&mut machine));
+    }
+}
@@ -0,0 +1,293 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    encoding::{OperandKind, INSTRUCTION_FORMS},
+    Opcode, Operand, Register,
+};
+
+/// The mnemonic of every opcode, as written in source code.
+pub const MNEMONICS: [(&str, Opcode); 24] = [
+    ("LDM", Opcode::Ldm),
+    ("LDD", Opcode::Ldd),
+    ("LDI", Opcode::Ldi),
+    ("LDX", Opcode::Ldx),
+    ("LDR", Opcode::Ldr),
+    ("MOV", Opcode::Mov),
+    ("STO", Opcode::Sto),
+    ("ADD", Opcode::Add),
+    ("SUB", Opcode::Sub),
+    ("INC", Opcode::Inc),
+    ("DEC", Opcode::Dec),
+    ("JMP", Opcode::Jmp),
+    ("CMP", Opcode::Cmp),
+    ("CMI", Opcode::Cmi),
+    ("JPE", Opcode::Jpe),
+    ("JPN", Opcode::Jpn),
+    ("IN", Opcode::In),
+    ("OUT", Opcode::Out),
+    ("END", Opcode::End),
+    ("AND", Opcode::And),
+    ("XOR", Opcode::Xor),
+    ("OR", Opcode::Or),
+    ("LSL", Opcode::Lsl),
+    ("LSR", Opcode::Lsr),
+];
+
+/// The forms of the 9608 syllabus, which has no `MOV`, `SUB` or `CMI`, and only adds from memory.
+const FORMS_9608: [(Opcode, OperandKind); 27] = [
+    (Opcode::End, OperandKind::None),
+    (Opcode::Ldm, OperandKind::Immediate),
+    (Opcode::Ldd, OperandKind::Address),
+    (Opcode::Ldi, OperandKind::Address),
+    (Opcode::Ldx, OperandKind::Address),
+    (Opcode::Ldr, OperandKind::Immediate),
+    (Opcode::Sto, OperandKind::Address),
+    (Opcode::Add, OperandKind::Address),
+    (Opcode::Inc, OperandKind::Register(Register::Ix)),
+    (Opcode::Inc, OperandKind::Register(Register::Acc)),
+    (Opcode::Dec, OperandKind::Register(Register::Ix)),
+    (Opcode::Dec, OperandKind::Register(Register::Acc)),
+    (Opcode::Jmp, OperandKind::Address),
+    (Opcode::Cmp, OperandKind::Immediate),
+    (Opcode::Cmp, OperandKind::Address),
+    (Opcode::Jpe, OperandKind::Address),
+    (Opcode::Jpn, OperandKind::Address),
+    (Opcode::In, OperandKind::None),
+    (Opcode::Out, OperandKind::None),
+    (Opcode::And, OperandKind::Immediate),
+    (Opcode::And, OperandKind::Address),
+    (Opcode::Xor, OperandKind::Immediate),
+    (Opcode::Xor, OperandKind::Address),
+    (Opcode::Or, OperandKind::Immediate),
+    (Opcode::Or, OperandKind::Address),
+    (Opcode::Lsl, OperandKind::Immediate),
+    (Opcode::Lsr, OperandKind::Immediate),
+];
+
+/// The forms listed in the 9618 syllabus, which only moves the ACC to the IX.
+const FORMS_EXAM_ONLY: [(Opcode, OperandKind); 32] = [
+    (Opcode::End, OperandKind::None),
+    (Opcode::Ldm, OperandKind::Immediate),
+    (Opcode::Ldd, OperandKind::Address),
+    (Opcode::Ldi, OperandKind::Address),
+    (Opcode::Ldx, OperandKind::Address),
+    (Opcode::Ldr, OperandKind::Immediate),
+    (Opcode::Mov, OperandKind::Register(Register::Ix)),
+    (Opcode::Sto, OperandKind::Address),
+    (Opcode::Add, OperandKind::Immediate),
+    (Opcode::Add, OperandKind::Address),
+    (Opcode::Sub, OperandKind::Immediate),
+    (Opcode::Sub, OperandKind::Address),
+    (Opcode::Inc, OperandKind::Register(Register::Ix)),
+    (Opcode::Inc, OperandKind::Register(Register::Acc)),
+    (Opcode::Dec, OperandKind::Register(Register::Ix)),
+    (Opcode::Dec, OperandKind::Register(Register::Acc)),
+    (Opcode::Jmp, OperandKind::Address),
+    (Opcode::Cmp, OperandKind::Immediate),
+    (Opcode::Cmp, OperandKind::Address),
+    (Opcode::Cmi, OperandKind::Address),
+    (Opcode::Jpe, OperandKind::Address),
+    (Opcode::Jpn, OperandKind::Address),
+    (Opcode::In, OperandKind::None),
+    (Opcode::Out, OperandKind::None),
+    (Opcode::And, OperandKind::Immediate),
+    (Opcode::And, OperandKind::Address),
+    (Opcode::Xor, OperandKind::Immediate),
+    (Opcode::Xor, OperandKind::Address),
+    (Opcode::Or, OperandKind::Immediate),
+    (Opcode::Or, OperandKind::Address),
+    (Opcode::Lsl, OperandKind::Immediate),
+    (Opcode::Lsr, OperandKind::Immediate),
+];
+
+/// A named profile of the instruction forms the assembler and the emulator accept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InstructionSet {
+    /// The current syllabus, plus `MOV ACC`.
+    #[default]
+    Cambridge9618,
+    /// The older syllabus.
+    Cambridge9608,
+    /// Only the forms listed in the 9618 syllabus.
+    ExamOnly,
+}
+
+impl InstructionSet {
+    pub const ALL: [InstructionSet; 3] = [
+        InstructionSet::Cambridge9618,
+        InstructionSet::Cambridge9608,
+        InstructionSet::ExamOnly,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InstructionSet::Cambridge9618 => "9618",
+            InstructionSet::Cambridge9608 => "9608",
+            InstructionSet::ExamOnly => "exam-only",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|set| set.name() == name)
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            InstructionSet::Cambridge9618 => "The 9618 instruction set, as well as MOV ACC.",
+            InstructionSet::Cambridge9608 => {
+                "The older 9608 instruction set, without MOV, SUB, CMI and ADD #n."
+            }
+            InstructionSet::ExamOnly => {
+                "Only the instruction forms listed in the 9618 syllabus, so MOV ACC is rejected."
+            }
+        }
+    }
+
+    /// Every combination of opcode and operand kind in this set.
+    pub fn forms(&self) -> &'static [(Opcode, OperandKind)] {
+        match self {
+            InstructionSet::Cambridge9618 => &INSTRUCTION_FORMS,
+            InstructionSet::Cambridge9608 => &FORMS_9608,
+            InstructionSet::ExamOnly => &FORMS_EXAM_ONLY,
+        }
+    }
+
+    /// Returns whether `opcode` is in this set with any operand.
+    pub fn has_opcode(&self, opcode: Opcode) -> bool {
+        self.forms().iter().any(|&(o, _)| o == opcode)
+    }
+
+    /// Returns the operand kinds `opcode` accepts in this set.
+    pub fn operand_kinds(&self, opcode: Opcode) -> impl Iterator<Item = OperandKind> {
+        self.forms()
+            .iter()
+            .filter(move |&&(o, _)| o == opcode)
+            .map(|&(_, kind)| kind)
+    }
+
+    pub fn allows(&self, opcode: Opcode, operand: Operand) -> bool {
+        let kind = OperandKind::of(operand);
+        self.operand_kinds(opcode).any(|k| k == kind)
+    }
+
+    /// Describes the operands `opcode` accepts, e.g. `Address/Number` or `IX`.
+    pub fn expected_operand(&self, opcode: Opcode) -> String {
+        let kinds: Vec<OperandKind> = self.operand_kinds(opcode).collect();
+        let registers: Vec<String> = kinds
+            .iter()
+            .filter_map(|kind| match kind {
+                OperandKind::Register(r) => Some(r.to_string()),
+                _ => None,
+            })
+            .collect();
+        let mut expected = Vec::new();
+        if kinds.contains(&OperandKind::Address) {
+            expected.push("Address".to_owned());
+        }
+        if kinds.contains(&OperandKind::Immediate) {
+            expected.push("Number".to_owned());
+        }
+        match registers.len() {
+            0 => (),
+            1 => expected.extend(registers),
+            _ => expected.push("Register".to_owned()),
+        }
+        expected.join("/")
+    }
+}
+
+/// Looks up the opcode written as `mnemonic`.
+pub fn opcode_of(mnemonic: &str) -> Option<Opcode> {
+    MNEMONICS
+        .iter()
+        .find(|&&(m, _)| m == mnemonic)
+        .map(|&(_, opcode)| opcode)
+}
+
+/// What an instruction form does, in register transfer notation, e.g. `ACC ← ACC + [address]`.
+pub fn form_rtl(opcode: Opcode, kind: OperandKind) -> &'static str {
+    match (opcode, kind) {
+        (Opcode::End, _) => "Return control to the operating system",
+        (Opcode::Ldm, _) => "ACC ← n",
+        (Opcode::Ldd, _) => "ACC ← [address]",
+        (Opcode::Ldi, _) => "ACC ← [[address]]",
+        (Opcode::Ldx, _) => "ACC ← [address + IX]",
+        (Opcode::Ldr, _) => "IX ← n",
+        (Opcode::Mov, OperandKind::Register(Register::Acc)) => "ACC ← ACC",
+        (Opcode::Mov, _) => "IX ← ACC",
+        (Opcode::Sto, _) => "[address] ← ACC",
+        (Opcode::Add, OperandKind::Immediate) => "ACC ← ACC + n",
+        (Opcode::Add, _) => "ACC ← ACC + [address]",
+        (Opcode::Sub, OperandKind::Immediate) => "ACC ← ACC - n",
+        (Opcode::Sub, _) => "ACC ← ACC - [address]",
+        (Opcode::Inc, OperandKind::Register(Register::Ix)) => "IX ← IX + 1",
+        (Opcode::Inc, _) => "ACC ← ACC + 1",
+        (Opcode::Dec, OperandKind::Register(Register::Ix)) => "IX ← IX - 1",
+        (Opcode::Dec, _) => "ACC ← ACC - 1",
+        (Opcode::Jmp, _) => "PC ← address",
+        (Opcode::Cmp, OperandKind::Immediate) => "Compare ACC with n",
+        (Opcode::Cmp, _) => "Compare ACC with [address]",
+        (Opcode::Cmi, _) => "Compare ACC with [[address]]",
+        (Opcode::Jpe, _) => "If the comparison was equal, PC ← address",
+        (Opcode::Jpn, _) => "If the comparison was not equal, PC ← address",
+        (Opcode::In, _) => "ACC ← ASCII code of a character typed in",
+        (Opcode::Out, _) => "Output the character whose ASCII code is in ACC",
+        (Opcode::And, OperandKind::Immediate) => "ACC ← ACC AND n",
+        (Opcode::And, _) => "ACC ← ACC AND [address]",
+        (Opcode::Xor, OperandKind::Immediate) => "ACC ← ACC XOR n",
+        (Opcode::Xor, _) => "ACC ← ACC XOR [address]",
+        (Opcode::Or, OperandKind::Immediate) => "ACC ← ACC OR n",
+        (Opcode::Or, _) => "ACC ← ACC OR [address]",
+        (Opcode::Lsl, _) => "ACC ← ACC shifted left by n bits, filling with zeros",
+        (Opcode::Lsr, _) => "ACC ← ACC shifted right by n bits, filling with zeros",
+        (Opcode::Data(_), _) => "Not an instruction",
+    }
+}
+
+/// A line of source code using `opcode`, with a comment on what it does.
+pub fn example(opcode: Opcode) -> &'static str {
+    match opcode {
+        Opcode::End => "END          // stop the program",
+        Opcode::Ldm => "LDM #5       // ACC = 5",
+        Opcode::Ldd => "LDD count    // ACC = the value at count",
+        Opcode::Ldi => "LDI pointer  // ACC = the value at the address stored at pointer",
+        Opcode::Ldx => "LDX string   // ACC = the value at string + IX",
+        Opcode::Ldr => "LDR #0       // IX = 0",
+        Opcode::Mov => "MOV IX       // IX = ACC",
+        Opcode::Sto => "STO total    // total = ACC",
+        Opcode::Add => "ADD #1       // ACC = ACC + 1",
+        Opcode::Sub => "SUB total    // ACC = ACC - the value at total",
+        Opcode::Inc => "INC IX       // IX = IX + 1",
+        Opcode::Dec => "DEC ACC      // ACC = ACC - 1",
+        Opcode::Jmp => "JMP loop     // continue at loop",
+        Opcode::Cmp => "CMP #0       // compare ACC with 0",
+        Opcode::Cmi => "CMI pointer  // compare ACC with the value pointer points to",
+        Opcode::Jpe => "JPE done     // continue at done if the comparison was equal",
+        Opcode::Jpn => "JPN loop     // continue at loop if the comparison was not equal",
+        Opcode::In => "IN           // ACC = the next character typed",
+        Opcode::Out => "OUT          // print the character in ACC",
+        Opcode::And => "AND #B1111   // keep the low 4 bits of ACC",
+        Opcode::Xor => "XOR #&FF     // invert the low 8 bits of ACC",
+        Opcode::Or => "OR #B0001    // set the lowest bit of ACC",
+        Opcode::Lsl => "LSL #1       // ACC = ACC * 2",
+        Opcode::Lsr => "LSR #1       // ACC = ACC DIV 2",
+        Opcode::Data(_) => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_9608_set_shifts_but_only_adds_from_memory() {
+        let set = InstructionSet::Cambridge9608;
+        assert!(set.allows(Opcode::Lsl, Operand::Immediate(1)));
+        assert!(set.allows(Opcode::Lsr, Operand::Immediate(1)));
+        assert!(set.allows(Opcode::Add, Operand::Address(0)));
+        assert!(!set.allows(Opcode::Add, Operand::Immediate(1)));
+        for opcode in [Opcode::Mov, Opcode::Sub, Opcode::Cmi] {
+            assert!(!set.has_opcode(opcode));
+        }
+    }
+}
@@ -1,24 +1,41 @@
 mod app;
-mod assembler;
+pub mod assembler;
+pub mod cfg;
 mod colors;
+pub mod disassembler;
+pub mod encoding;
+mod expression;
+pub mod format;
+pub mod highlight;
+pub mod history;
 pub mod icons;
 mod init;
+pub mod instruction_set;
+pub mod lint;
+pub mod machine;
+pub mod number_format;
+pub mod protocol;
+pub mod test_case;
+pub mod trace;
+pub mod worksheet;
 use std::{
     collections::HashMap,
     fmt::{Debug, Display},
 };
 
 pub use app::CaieAsmApp;
+use instruction_set::InstructionSet;
+pub use machine::{Machine, StepOutcome};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MemoryData {
     Instruction(Opcode, Operand),
     Value(u16),
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Opcode {
     Ldm,
     Ldd,
@@ -50,43 +67,17 @@ pub enum Opcode {
 impl TryFrom<&str> for Opcode {
     type Error = ();
     fn try_from(s: &str) -> Result<Self, Self::Error> {
-        match s {
-            "LDM" => Ok(Opcode::Ldm),
-            "LDD" => Ok(Opcode::Ldd),
-            "LDI" => Ok(Opcode::Ldi),
-            "LDX" => Ok(Opcode::Ldx),
-            "LDR" => Ok(Opcode::Ldr),
-            "MOV" => Ok(Opcode::Mov),
-            "STO" => Ok(Opcode::Sto),
-            "ADD" => Ok(Opcode::Add),
-            "SUB" => Ok(Opcode::Sub),
-            "INC" => Ok(Opcode::Inc),
-            "DEC" => Ok(Opcode::Dec),
-            "JMP" => Ok(Opcode::Jmp),
-            "CMP" => Ok(Opcode::Cmp),
-            "CMI" => Ok(Opcode::Cmi),
-            "JPE" => Ok(Opcode::Jpe),
-            "JPN" => Ok(Opcode::Jpn),
-            "IN" => Ok(Opcode::In),
-            "OUT" => Ok(Opcode::Out),
-            "END" => Ok(Opcode::End),
-            "AND" => Ok(Opcode::And),
-            "XOR" => Ok(Opcode::Xor),
-            "OR" => Ok(Opcode::Or),
-            "LSL" => Ok(Opcode::Lsl),
-            "LSR" => Ok(Opcode::Lsr),
-            _ => {
-                if let Ok(Operand::Immediate(v)) = Operand::str_to_operand(s, &HashMap::new()) {
-                    Ok(Opcode::Data(v))
-                } else {
-                    Err(())
-                }
-            }
+        if let Some(opcode) = instruction_set::opcode_of(s) {
+            Ok(opcode)
+        } else if let Ok(Operand::Immediate(v)) = Operand::str_to_operand(s, &HashMap::new()) {
+            Ok(Opcode::Data(v))
+        } else {
+            Err(())
         }
     }
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Operand {
     Register(Register),
     Address(u16),
@@ -128,7 +119,7 @@ impl Operand {
     }
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Register {
     Ix,
     Acc,
@@ -139,10 +130,11 @@ pub enum ExecutionState {
     Executing,
     ExecutingAwaitingInput,
     SteppingAwaitingInput,
+    Paused,
     Stopped,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ExecutionInfo {
     ExecutionTerminated {
         ins_address: u16,
@@ -162,9 +154,16 @@ pub enum ExecutionInfo {
         ins_address: u16,
         requested_address: u16,
     },
+    InvalidInstruction {
+        ins_address: u16,
+    },
+    UnsupportedInstruction {
+        ins_address: u16,
+        instruction_set: InstructionSet,
+    },
 }
 
-#[derive(Error, Debug, Serialize, Deserialize)]
+#[derive(Error, Clone, Debug, Serialize, Deserialize)]
 pub enum AssemblerError {
     #[error("too many operands on line {line_index}: found {operands_found} operands")]
     TooManyOperands {
@@ -192,8 +191,51 @@ pub enum AssemblerError {
         operand_given: Operand,
         operand_type_expected: String,
     },
+    #[error("unsupported instruction on line {line_index}: {opcode} is not in the {} instruction set", instruction_set.name())]
+    UnsupportedOpcode {
+        line_index: usize,
+        opcode: Opcode,
+        instruction_set: InstructionSet,
+    },
     #[error("missing operand on line {line_index}: {opcode} expects an operand")]
     MissingOperand { line_index: usize, opcode: Opcode },
+    #[error("operand too large on line {line_index}: {operand} does not fit in the {operand_bits}-bit operand field of the machine code")]
+    OperandTooLarge {
+        line_index: usize,
+        operand: Operand,
+        operand_bits: u8,
+    },
+    #[error("invalid machine code layout: {reason}")]
+    InvalidEncoding { reason: String },
+    #[error("undefined symbol on line {line_index}: {symbol} is not a label or a constant")]
+    UndefinedSymbol {
+        line_index: usize,
+        symbol: String,
+        operand: String,
+    },
+    #[error("operand out of range on line {line_index}: {operand} is {value}, but it must be from {min} to {max}")]
+    OperandOutOfRange {
+        line_index: usize,
+        operand: String,
+        value: i64,
+        min: i64,
+        max: i64,
+    },
+    #[error("missing operand on line {line_index}: {directive} expects a number")]
+    MissingDirectiveOperand {
+        line_index: usize,
+        directive: String,
+    },
+    #[error("missing constant name on line {line_index}: EQU must follow the name of the constant it defines")]
+    MissingConstantName { line_index: usize },
+    #[error("ORG before the current location on line {line_index}: the program has already reached address {current}, so it cannot continue at {address}")]
+    OrgBeforeLocation {
+        line_index: usize,
+        address: u16,
+        current: u16,
+    },
+    #[error("malformed string on line {line_index}: {string}")]
+    MalformedString { line_index: usize, string: String },
     #[error("program too long: program size is {program_size}, but only {memory_available} unit of memory space is available")]
     ProgramTooLong {
         program_size: usize,
@@ -201,6 +243,85 @@ pub enum AssemblerError {
     },
 }
 
+#[derive(Error, Clone, Debug, Serialize, Deserialize)]
+pub enum AssemblerWarning {
+    #[error("duplicate label on line {line_index}: {label} was already defined on line {first_line}, this definition replaces it")]
+    DuplicateLabel {
+        line_index: usize,
+        label: String,
+        first_line: usize,
+    },
+    #[error("missing END on line {line_index}: this is the last instruction, and the program has no END")]
+    MissingEnd { line_index: usize },
+    #[error("unreachable code on line {line_index}: execution never gets to this instruction from the start of the program")]
+    UnreachableCode { line_index: usize },
+    #[error("jump into data on line {line_index}: {opcode} jumps to address {address:02X}, which holds the value on line {target_line}, not an instruction")]
+    JumpIntoData {
+        line_index: usize,
+        opcode: Opcode,
+        address: u16,
+        target_line: usize,
+    },
+    #[error("load from an instruction on line {line_index}: {opcode} reads address {address:02X}, which holds the instruction on line {target_line}, so execution would abort")]
+    LoadFromInstruction {
+        line_index: usize,
+        opcode: Opcode,
+        address: u16,
+        target_line: usize,
+    },
+    #[error("unused label on line {line_index}: {label} is never used")]
+    UnusedLabel { line_index: usize, label: String },
+    #[error(
+        "loop without exit on line {line_index}: execution can never get from this jump to END"
+    )]
+    LoopWithoutExit { line_index: usize },
+}
+
+/// A range of characters on one line of the source code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    /// 1-based line number, as used by `line_index` in errors.
+    pub line: usize,
+    /// 0-based column of the first character.
+    pub start: usize,
+    /// 0-based column just after the last character.
+    pub end: usize,
+}
+
+/// An error or warning reported by the assembler, together with where in the source it applies.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Diagnostic {
+    Error {
+        span: Span,
+        error: AssemblerError,
+    },
+    Warning {
+        span: Span,
+        warning: AssemblerWarning,
+    },
+}
+
+impl Diagnostic {
+    pub fn span(&self) -> Span {
+        match self {
+            Diagnostic::Error { span, .. } | Diagnostic::Warning { span, .. } => *span,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Diagnostic::Error { .. })
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnostic::Error { error, .. } => write!(f, "error: {}", error),
+            Diagnostic::Warning { warning, .. } => write!(f, "warning: {}", warning),
+        }
+    }
+}
+
 impl Debug for Opcode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -270,3 +391,51 @@ impl Display for Operand {
         Debug::fmt(self, f)
     }
 }
+
+impl Display for ExecutionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionInfo::ExecutionTerminated { ins_address } => {
+                write!(f, "execution terminated at address {:02X}", ins_address)
+            }
+            ExecutionInfo::ExecutionAbortedValueMet { ins_address, value } => write!(
+                f,
+                "execution aborted at address {:02X}: value {} is not an instruction",
+                ins_address, value
+            ),
+            ExecutionInfo::TooManySteps { steps } => {
+                write!(f, "{} instructions executed without reaching END", steps)
+            }
+            ExecutionInfo::AddressNotInMemory {
+                ins_address,
+                requested_address,
+            } => write!(
+                f,
+                "execution aborted at address {:02X}: address {:02X} is not in the memory",
+                ins_address, requested_address
+            ),
+            ExecutionInfo::InvalidLoad {
+                ins_address,
+                requested_address,
+            } => write!(
+                f,
+                "execution aborted at address {:02X}: address {:02X} holds an instruction, not a value",
+                ins_address, requested_address
+            ),
+            ExecutionInfo::InvalidInstruction { ins_address } => write!(
+                f,
+                "execution aborted at address {:02X}: the instruction has an operand its opcode does not accept",
+                ins_address
+            ),
+            ExecutionInfo::UnsupportedInstruction {
+                ins_address,
+                instruction_set,
+            } => write!(
+                f,
+                "execution aborted at address {:02X}: the instruction is not in the {} instruction set",
+                ins_address,
+                instruction_set.name()
+            ),
+        }
+    }
+}
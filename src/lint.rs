@@ -0,0 +1,253 @@
+use crate::{assembler::Program, AssemblerWarning, Diagnostic, MemoryData, Opcode, Operand};
+
+/// Looks for likely mistakes in a program that the assembler accepts: a missing `END`,
+/// unreachable code, jumps into data, loads from instructions, unused labels and loops that can
+/// never reach `END`.
+///
+/// Execution is assumed to start at [`Program::entry_address`]. The program must not be encoded
+/// as machine code yet. Duplicate labels are already reported by the assembler, in
+/// [`Program::warnings`].
+pub fn lint(program: &Program) -> Vec<Diagnostic> {
+    let words = &program.words;
+    let mut warnings = Vec::new();
+    let mut warn = |index: usize, warning| {
+        warnings.push(Diagnostic::Warning {
+            span: program.spans[index],
+            warning,
+        })
+    };
+    let offset_of = |address: u16| {
+        address
+            .checked_sub(program.load_address)
+            .map(usize::from)
+            .filter(|&offset| offset < words.len())
+    };
+    let line = |index: usize| program.spans[index].line;
+
+    let successors: Vec<Successors> = (0..words.len())
+        .map(|i| successors(program, i, offset_of))
+        .collect();
+
+    let mut reachable = vec![false; words.len()];
+    let mut stack = vec![usize::from(program.entry_address() - program.load_address)];
+    while let Some(i) = stack.pop() {
+        if i < words.len() && !reachable[i] {
+            reachable[i] = true;
+            stack.extend(&successors[i].next);
+        }
+    }
+
+    // Whether END (or anything else that stops execution) can be reached from each word
+    let mut can_stop: Vec<bool> = successors.iter().map(|s| s.stops).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..words.len() {
+            if !can_stop[i] && successors[i].next.iter().any(|&next| can_stop[next]) {
+                can_stop[i] = true;
+                changed = true;
+            }
+        }
+    }
+
+    let mut last_instruction = None;
+    let mut has_end = false;
+    for (i, word) in words.iter().enumerate() {
+        let MemoryData::Instruction(opcode, operand) = *word else {
+            continue;
+        };
+        last_instruction = Some(i);
+        has_end |= opcode == Opcode::End;
+
+        let previous_unreachable =
+            i > 0 && !reachable[i - 1] && matches!(words[i - 1], MemoryData::Instruction(_, _));
+        if !reachable[i] && !previous_unreachable {
+            warn(
+                i,
+                AssemblerWarning::UnreachableCode {
+                    line_index: line(i),
+                },
+            );
+        }
+
+        let Operand::Address(address) = operand else {
+            continue;
+        };
+        let target = offset_of(address);
+        match opcode {
+            Opcode::Jmp | Opcode::Jpe | Opcode::Jpn => {
+                if let Some(t) = target.filter(|&t| matches!(words[t], MemoryData::Value(_))) {
+                    warn(
+                        i,
+                        AssemblerWarning::JumpIntoData {
+                            line_index: line(i),
+                            opcode,
+                            address,
+                            target_line: line(t),
+                        },
+                    );
+                }
+                if target.is_some_and(|t| t <= i) && reachable[i] && !can_stop[i] {
+                    warn(
+                        i,
+                        AssemblerWarning::LoopWithoutExit {
+                            line_index: line(i),
+                        },
+                    );
+                }
+            }
+            Opcode::Ldd
+            | Opcode::Ldi
+            | Opcode::Add
+            | Opcode::Sub
+            | Opcode::Cmp
+            | Opcode::Cmi
+            | Opcode::And
+            | Opcode::Xor
+            | Opcode::Or => {
+                if let Some(t) =
+                    target.filter(|&t| matches!(words[t], MemoryData::Instruction(_, _)))
+                {
+                    warn(
+                        i,
+                        AssemblerWarning::LoadFromInstruction {
+                            line_index: line(i),
+                            opcode,
+                            address,
+                            target_line: line(t),
+                        },
+                    );
+                }
+            }
+            _ => (),
+        }
+    }
+    if let (Some(i), false) = (last_instruction, has_end) {
+        warn(
+            i,
+            AssemblerWarning::MissingEnd {
+                line_index: line(i),
+            },
+        );
+    }
+
+    for (label, definition) in &program.definitions {
+        if !program.references.iter().any(|r| r.name == *label) {
+            warnings.push(Diagnostic::Warning {
+                span: definition.span,
+                warning: AssemblerWarning::UnusedLabel {
+                    line_index: definition.span.line,
+                    label: label.clone(),
+                },
+            });
+        }
+    }
+
+    warnings.sort_by_key(|d| (d.span().line, d.span().start));
+    warnings
+}
+
+/// Where execution can go after the word at some index of a program.
+struct Successors {
+    /// Indices of the words that may be executed next.
+    next: Vec<usize>,
+    /// Whether execution may stop, or leave the program, instead.
+    stops: bool,
+}
+
+fn successors(program: &Program, i: usize, offset_of: impl Fn(u16) -> Option<usize>) -> Successors {
+    let following = Some(i + 1).filter(|&next| next < program.words.len());
+    let (next, stops) = match program.words[i] {
+        // Values stop execution, either as END (zero) or by aborting
+        MemoryData::Value(_) | MemoryData::Instruction(Opcode::End, _) => (vec![], true),
+        MemoryData::Instruction(Opcode::Jmp, Operand::Address(a)) => match offset_of(a) {
+            Some(target) => (vec![target], false),
+            None => (vec![], true),
+        },
+        MemoryData::Instruction(Opcode::Jpe | Opcode::Jpn, Operand::Address(a)) => {
+            let next: Vec<usize> = following.into_iter().chain(offset_of(a)).collect();
+            let stops = next.len() < 2;
+            (next, stops)
+        }
+        MemoryData::Instruction(_, _) => (following.into_iter().collect(), following.is_none()),
+    };
+    Successors { next, stops }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn warnings(source: &str) -> Vec<AssemblerWarning> {
+        lint(&assemble(source, 0).unwrap())
+            .into_iter()
+            .map(|diagnostic| match diagnostic {
+                Diagnostic::Warning { warning, .. } => warning,
+                Diagnostic::Error { error, .. } => panic!("{}", error),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_correct_program_has_no_warnings() {
+        assert!(
+            warnings("start: LDD count\n OUT\n DEC ACC\n CMP #0\n JPN start\n END\ncount: #3")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn reports_a_missing_end() {
+        assert!(matches!(
+            warnings("LDM #1\nOUT")[..],
+            [AssemblerWarning::MissingEnd { line_index: 2 }]
+        ));
+    }
+
+    #[test]
+    fn reports_unreachable_code_and_unused_labels() {
+        assert!(matches!(
+            &warnings("JMP done\nOUT\nunused: OUT\ndone: END")[..],
+            [
+                AssemblerWarning::UnreachableCode { line_index: 2 },
+                AssemblerWarning::UnusedLabel { line_index: 3, label },
+            ] if label == "unused"
+        ));
+    }
+
+    #[test]
+    fn reports_jumps_into_data_and_loads_from_instructions() {
+        let warnings = warnings("LDD start\nstart: JMP data\nEND\ndata: #1");
+        assert!(warnings.iter().any(|warning| matches!(
+            warning,
+            AssemblerWarning::LoadFromInstruction {
+                line_index: 1,
+                address: 1,
+                target_line: 2,
+                ..
+            }
+        )));
+        assert!(warnings.iter().any(|warning| matches!(
+            warning,
+            AssemblerWarning::JumpIntoData {
+                line_index: 2,
+                address: 3,
+                target_line: 4,
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn reports_loops_that_never_reach_end() {
+        assert!(warnings("loop: OUT\nJMP loop\nEND")
+            .iter()
+            .any(|warning| matches!(warning, AssemblerWarning::LoopWithoutExit { line_index: 2 })));
+    }
+
+    #[test]
+    fn code_after_a_leading_org_is_reachable() {
+        assert!(warnings("ORG 16\nLDM #1\nEND").is_empty());
+    }
+}
@@ -0,0 +1,530 @@
+use std::ops::ControlFlow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assembler::Program,
+    encoding::{Encoding, OperandKind, INSTRUCTION_FORMS},
+    instruction_set::InstructionSet,
+    ExecutionInfo, MemoryData, Opcode, Operand, Register,
+};
+
+/// What happened after [`Machine::step`] executed (or tried to execute) one instruction.
+#[derive(Debug)]
+pub enum StepOutcome {
+    /// The instruction was executed, and the machine is ready for the next one.
+    Continue,
+    /// An `IN` instruction was executed. The character should be supplied with [`Machine::input`].
+    AwaitingInput,
+    /// Execution has stopped, either normally (`END`) or because of a fault.
+    Stopped(ExecutionInfo),
+}
+
+/// A headless CAIE processor: registers, flags and 256 cells of memory.
+///
+/// The machine does no UI work. Faults are returned as values in [`StepOutcome::Stopped`].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Machine {
+    pub memory: [[MemoryData; 16]; 16],
+    pub pc: u16,
+    pub cir: (Opcode, Operand),
+    pub ix: u16,
+    pub mdr: MemoryData,
+    pub mar: u16,
+    pub acc: u16,
+    pub carry: bool,
+    pub zero: bool,
+    pub overflow: bool,
+    pub sign: bool,
+    pub output: String,
+    /// If set, `Value` cells are decoded as instructions when they are fetched.
+    #[serde(default)]
+    pub encoding: Option<Encoding>,
+    /// Instructions outside this set stop execution when they are fetched.
+    #[serde(default)]
+    pub instruction_set: InstructionSet,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self {
+            memory: [[MemoryData::Value(0); 16]; 16],
+            pc: 0,
+            cir: (Opcode::End, Operand::Empty),
+            ix: 0,
+            mdr: MemoryData::Value(0),
+            mar: 0,
+            acc: 0,
+            carry: false,
+            zero: false,
+            overflow: false,
+            sign: false,
+            output: String::new(),
+            encoding: None,
+            instruction_set: InstructionSet::default(),
+        }
+    }
+}
+
+impl Machine {
+    /// Writes an assembled program into memory at its load address, and adopts its encoding and
+    /// instruction set. Cells in the gaps left by `ORG` keep their contents. The PC is not
+    /// changed, see [`Program::entry_address`].
+    pub fn load_program(&mut self, program: &Program) {
+        self.encoding = program.encoding.clone();
+        self.instruction_set = program.instruction_set;
+        let cells = self.memory.as_flattened_mut().iter_mut();
+        for (i, (cell, &word)) in cells
+            .skip(program.load_address as usize)
+            .zip(&program.words)
+            .enumerate()
+        {
+            if !program.is_gap(i) {
+                *cell = word;
+            }
+        }
+    }
+
+    /// Returns the instruction stored at `address`, decoding it if it is stored as machine code.
+    /// Returns `None` for values that are not instructions and for addresses outside memory.
+    pub fn instruction_at(&self, address: u16) -> Option<(Opcode, Operand)> {
+        match *self.memory.as_flattened().get(address as usize)? {
+            MemoryData::Instruction(opcode, operand) => Some((opcode, operand)),
+            MemoryData::Value(word) => self.encoding.as_ref()?.decode(word),
+        }
+    }
+
+    /// Fetches, decodes and executes the instruction at `pc`.
+    pub fn step(&mut self) -> StepOutcome {
+        match self.execute() {
+            Ok(outcome) => outcome,
+            Err(info) => StepOutcome::Stopped(info),
+        }
+    }
+
+    /// Steps until the machine stops or asks for input, giving up after `limit` instructions.
+    pub fn run(&mut self, limit: u64) -> StepOutcome {
+        let (outcome, _) = self.run_with_input(&mut std::iter::empty(), limit, |_, _| {
+            ControlFlow::Continue(())
+        });
+        outcome
+    }
+
+    /// Steps until the machine stops, giving `IN` instructions their characters from `input`, and
+    /// gives up after `limit` instructions.
+    ///
+    /// `on_step` is called with the address of every instruction executed, once an `IN` has its
+    /// character, and can pause by returning [`ControlFlow::Break`]. Returns the number of
+    /// instructions executed, and [`StepOutcome::Continue`] if `on_step` paused,
+    /// [`StepOutcome::AwaitingInput`] if `input` ran out, or why the machine stopped.
+    pub fn run_with_input(
+        &mut self,
+        input: &mut impl Iterator<Item = char>,
+        limit: u64,
+        mut on_step: impl FnMut(&Machine, u16) -> ControlFlow<()>,
+    ) -> (StepOutcome, u64) {
+        for steps in 1..=limit {
+            let address = self.pc;
+            let fetched = self.instruction_at(address).is_some();
+            let mut outcome = self.step();
+            if matches!(outcome, StepOutcome::AwaitingInput) {
+                if let Some(c) = input.next() {
+                    self.input(c);
+                    outcome = StepOutcome::Continue;
+                }
+            }
+            let flow = if fetched {
+                on_step(self, address)
+            } else {
+                ControlFlow::Continue(())
+            };
+            match outcome {
+                StepOutcome::Continue if flow.is_continue() => (),
+                outcome => return (outcome, steps),
+            }
+        }
+        (
+            StepOutcome::Stopped(ExecutionInfo::TooManySteps { steps: limit }),
+            limit,
+        )
+    }
+
+    /// Completes a pending `IN` instruction by loading the character into the ACC.
+    pub fn input(&mut self, c: char) {
+        self.acc = c as u16;
+    }
+
+    fn execute(&mut self) -> Result<StepOutcome, ExecutionInfo> {
+        let ins_address = self.pc;
+        if ins_address > 255 {
+            return Err(ExecutionInfo::AddressNotInMemory {
+                ins_address,
+                requested_address: ins_address,
+            });
+        }
+        self.mar = self.pc;
+        self.mdr = self.memory.as_flattened()[self.pc as usize];
+        let decoded = match (self.mdr, &self.encoding) {
+            (MemoryData::Value(word), Some(encoding)) => encoding.decode(word),
+            _ => None,
+        };
+        let (opcode, operand) = match (self.mdr, decoded) {
+            (MemoryData::Instruction(opcode, operand), _) => (opcode, operand),
+            (MemoryData::Value(_), Some(instruction)) => instruction,
+            (MemoryData::Value(0), None) => {
+                return Ok(StepOutcome::Stopped(ExecutionInfo::ExecutionTerminated {
+                    ins_address,
+                }))
+            }
+            (MemoryData::Value(value), None) => {
+                return Err(ExecutionInfo::ExecutionAbortedValueMet { ins_address, value })
+            }
+        };
+        self.cir = (opcode, operand);
+        // Forms that are not valid in any set are reported as invalid instructions below
+        let form = (opcode, OperandKind::of(operand));
+        if INSTRUCTION_FORMS.contains(&form) && !self.instruction_set.allows(opcode, operand) {
+            return Err(ExecutionInfo::UnsupportedInstruction {
+                ins_address,
+                instruction_set: self.instruction_set,
+            });
+        }
+        self.pc += 1;
+        match (opcode, operand) {
+            (Opcode::Ldm, Operand::Immediate(v)) => self.acc = v,
+            (Opcode::Ldd, Operand::Address(a)) => self.acc = self.load(a, ins_address)?,
+            (Opcode::Ldi, Operand::Address(a)) => {
+                let pointer = self.load(a, ins_address)?;
+                self.acc = self.load(pointer, ins_address)?;
+            }
+            (Opcode::Ldx, Operand::Address(a)) => {
+                self.acc = self.load(a.saturating_add(self.ix), ins_address)?
+            }
+            (Opcode::Ldr, Operand::Immediate(v)) => self.ix = v,
+            (Opcode::Mov, Operand::Register(r)) => match r {
+                Register::Ix => self.ix = self.acc,
+                Register::Acc => (),
+            },
+            (Opcode::Sto, Operand::Address(a)) => {
+                self.mar = a;
+                self.mdr = MemoryData::Value(self.acc);
+                if a > 255 {
+                    return Err(ExecutionInfo::AddressNotInMemory {
+                        ins_address,
+                        requested_address: a,
+                    });
+                }
+                self.memory.as_flattened_mut()[a as usize] = MemoryData::Value(self.acc);
+            }
+            (Opcode::Add, _) => {
+                let v = self.operand_value(operand, ins_address)?;
+                let (result, overflow) = self.acc.overflowing_add(v);
+                self.acc = result;
+                self.carry = overflow;
+                self.overflow = overflow;
+                self.set_zero_and_sign(result);
+            }
+            (Opcode::Sub, _) => {
+                let v = self.operand_value(operand, ins_address)?;
+                let (result, overflow) = self.acc.overflowing_sub(v);
+                self.acc = result;
+                self.carry = overflow;
+                self.overflow = overflow;
+                self.set_zero_and_sign(result);
+            }
+            // Add 1 to / subtract 1 from the destination register, while preserving CF
+            (Opcode::Inc, Operand::Register(r)) => {
+                let register = self.register_mut(r);
+                let (result, overflow) = register.overflowing_add(1);
+                *register = result;
+                self.overflow = overflow;
+                self.set_zero_and_sign(result);
+            }
+            (Opcode::Dec, Operand::Register(r)) => {
+                let register = self.register_mut(r);
+                let (result, overflow) = register.overflowing_sub(1);
+                *register = result;
+                self.overflow = overflow;
+                self.set_zero_and_sign(result);
+            }
+            (Opcode::Jmp, Operand::Address(a)) => self.jump(a, ins_address)?,
+            (Opcode::Cmp, _) => {
+                let v = self.operand_value(operand, ins_address)?;
+                self.compare(v);
+            }
+            (Opcode::Cmi, Operand::Address(a)) => {
+                let pointer = self.load(a, ins_address)?;
+                let v = self.load(pointer, ins_address)?;
+                self.compare(v);
+            }
+            (Opcode::Jpe, Operand::Address(a)) => {
+                if self.zero {
+                    self.jump(a, ins_address)?;
+                }
+            }
+            (Opcode::Jpn, Operand::Address(a)) => {
+                if !self.zero {
+                    self.jump(a, ins_address)?;
+                }
+            }
+            (Opcode::In, _) => return Ok(StepOutcome::AwaitingInput),
+            (Opcode::Out, _) => {
+                if let Some(c) = std::char::from_u32(self.acc as u32) {
+                    self.output.push(c);
+                } else {
+                    self.output.push('�');
+                }
+            }
+            (Opcode::End, _) => {
+                return Ok(StepOutcome::Stopped(ExecutionInfo::ExecutionTerminated {
+                    ins_address,
+                }))
+            }
+            (Opcode::And, _) => {
+                self.acc &= self.operand_value(operand, ins_address)?;
+                self.set_zero_and_sign(self.acc);
+            }
+            (Opcode::Xor, _) => {
+                self.acc ^= self.operand_value(operand, ins_address)?;
+                self.set_zero_and_sign(self.acc);
+            }
+            (Opcode::Or, _) => {
+                self.acc |= self.operand_value(operand, ins_address)?;
+                self.set_zero_and_sign(self.acc);
+            }
+            (Opcode::Lsl, _) => {
+                let v = self.operand_value(operand, ins_address)?;
+                self.acc = self.acc.checked_shl(v as u32).unwrap_or(0);
+                self.set_zero_and_sign(self.acc);
+            }
+            (Opcode::Lsr, _) => {
+                let v = self.operand_value(operand, ins_address)?;
+                self.acc = self.acc.checked_shr(v as u32).unwrap_or(0);
+                self.set_zero_and_sign(self.acc);
+            }
+            _ => return Err(ExecutionInfo::InvalidInstruction { ins_address }),
+        }
+        Ok(StepOutcome::Continue)
+    }
+
+    /// Reads a value from memory through the MAR and MDR.
+    fn load(&mut self, address: u16, ins_address: u16) -> Result<u16, ExecutionInfo> {
+        self.mar = address;
+        if address > 255 {
+            return Err(ExecutionInfo::AddressNotInMemory {
+                ins_address,
+                requested_address: address,
+            });
+        }
+        self.mdr = self.memory.as_flattened()[address as usize];
+        match self.mdr {
+            MemoryData::Value(v) => Ok(v),
+            MemoryData::Instruction(_, _) => Err(ExecutionInfo::InvalidLoad {
+                ins_address,
+                requested_address: address,
+            }),
+        }
+    }
+
+    /// Resolves an operand that may be either a number or the address of a number.
+    fn operand_value(&mut self, operand: Operand, ins_address: u16) -> Result<u16, ExecutionInfo> {
+        match operand {
+            Operand::Immediate(v) => Ok(v),
+            Operand::Address(a) => self.load(a, ins_address),
+            _ => Err(ExecutionInfo::InvalidInstruction { ins_address }),
+        }
+    }
+
+    fn jump(&mut self, address: u16, ins_address: u16) -> Result<(), ExecutionInfo> {
+        if address > 255 {
+            return Err(ExecutionInfo::AddressNotInMemory {
+                ins_address,
+                requested_address: address,
+            });
+        }
+        self.pc = address;
+        Ok(())
+    }
+
+    fn compare(&mut self, v: u16) {
+        let (result, overflow) = self.acc.overflowing_sub(v);
+        self.carry = overflow;
+        self.overflow = overflow;
+        self.set_zero_and_sign(result);
+    }
+
+    fn register_mut(&mut self, register: Register) -> &mut u16 {
+        match register {
+            Register::Ix => &mut self.ix,
+            Register::Acc => &mut self.acc,
+        }
+    }
+
+    fn set_zero_and_sign(&mut self, result: u16) {
+        self.zero = result == 0;
+        self.sign = result & 0x8000 != 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    /// Assembles `source` at address 0 and loads it, ready to run from its entry address.
+    fn machine_for(source: &str) -> Machine {
+        let program = assemble(source, 0).unwrap();
+        let mut machine = Machine::default();
+        machine.load_program(&program);
+        machine.pc = program.entry_address();
+        machine
+    }
+
+    #[test]
+    fn steps_one_instruction_at_a_time() {
+        let mut machine = machine_for("LDM #3\nADD #4\nEND");
+        assert!(matches!(machine.step(), StepOutcome::Continue));
+        assert_eq!((machine.acc, machine.pc), (3, 1));
+        assert!(matches!(machine.step(), StepOutcome::Continue));
+        assert_eq!((machine.acc, machine.pc), (7, 2));
+        assert!(matches!(
+            machine.step(),
+            StepOutcome::Stopped(ExecutionInfo::ExecutionTerminated { ins_address: 2 })
+        ));
+    }
+
+    #[test]
+    fn runs_a_loop_to_end() {
+        let mut machine = machine_for("LDM #3\nloop: DEC ACC\nCMP #0\nJPN loop\nEND");
+        assert!(matches!(
+            machine.run(100),
+            StepOutcome::Stopped(ExecutionInfo::ExecutionTerminated { ins_address: 4 })
+        ));
+        assert_eq!(machine.acc, 0);
+        assert!(machine.zero);
+    }
+
+    #[test]
+    fn run_gives_up_after_the_limit() {
+        let mut machine = machine_for("loop: JMP loop");
+        assert!(matches!(
+            machine.run(10),
+            StepOutcome::Stopped(ExecutionInfo::TooManySteps { steps: 10 })
+        ));
+    }
+
+    #[test]
+    fn in_waits_for_input() {
+        let mut machine = machine_for("IN\nOUT\nEND");
+        assert!(matches!(machine.run(100), StepOutcome::AwaitingInput));
+        assert_eq!(machine.pc, 1);
+        machine.input('x');
+        assert!(matches!(machine.run(100), StepOutcome::Stopped(_)));
+        assert_eq!(machine.output, "x");
+    }
+
+    #[test]
+    fn runs_with_input_until_paused() {
+        let mut machine = machine_for("IN\nOUT\nIN\nOUT\nEND");
+        let mut input = "ab".chars();
+        let mut addresses = Vec::new();
+        let (outcome, steps) = machine.run_with_input(&mut input, 100, |machine, address| {
+            addresses.push(address);
+            match machine.output.len() {
+                2 => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        });
+        assert!(matches!(outcome, StepOutcome::Continue));
+        assert_eq!((steps, addresses), (4, vec![0, 1, 2, 3]));
+        assert_eq!(machine.output, "ab");
+
+        let mut machine = machine_for("IN\nOUT\nIN\nOUT\nEND");
+        let (outcome, steps) =
+            machine.run_with_input(&mut "a".chars(), 100, |_, _| ControlFlow::Continue(()));
+        assert!(matches!(outcome, StepOutcome::AwaitingInput));
+        assert_eq!((steps, machine.pc), (3, 3));
+    }
+
+    #[test]
+    fn addition_wraps_and_sets_the_carry() {
+        let mut machine = machine_for("LDM #-1\nADD #1\nEND");
+        machine.run(100);
+        assert_eq!(machine.acc, 0);
+        assert!(machine.carry && machine.zero && !machine.sign);
+    }
+
+    #[test]
+    fn faults_stop_execution() {
+        let mut machine = machine_for("LDD 0\nEND");
+        assert!(matches!(
+            machine.run(100),
+            StepOutcome::Stopped(ExecutionInfo::InvalidLoad {
+                ins_address: 0,
+                requested_address: 0
+            })
+        ));
+
+        let mut machine = machine_for("JMP data\ndata: #7");
+        assert!(matches!(
+            machine.run(100),
+            StepOutcome::Stopped(ExecutionInfo::ExecutionAbortedValueMet {
+                ins_address: 1,
+                value: 7
+            })
+        ));
+
+        let mut machine = Machine::default();
+        machine.memory[0][0] = MemoryData::Instruction(Opcode::Ldd, Operand::Address(300));
+        assert!(matches!(
+            machine.step(),
+            StepOutcome::Stopped(ExecutionInfo::AddressNotInMemory {
+                ins_address: 0,
+                requested_address: 300
+            })
+        ));
+    }
+
+    #[test]
+    fn instructions_outside_the_set_are_unsupported() {
+        let mut machine = machine_for("LDM #1\nSUB #1\nEND");
+        machine.instruction_set = InstructionSet::Cambridge9608;
+        assert!(matches!(
+            machine.run(100),
+            StepOutcome::Stopped(ExecutionInfo::UnsupportedInstruction {
+                ins_address: 1,
+                instruction_set: InstructionSet::Cambridge9608
+            })
+        ));
+    }
+
+    #[test]
+    fn encoded_instructions_are_decoded_when_fetched() {
+        let encoding = Encoding::default();
+        let mut machine = machine_for("LDM #65\nOUT\nEND");
+        for address in 0..3 {
+            let (opcode, operand) = machine.instruction_at(address).unwrap();
+            machine.memory.as_flattened_mut()[address as usize] =
+                MemoryData::Value(encoding.encode(opcode, operand).unwrap());
+        }
+        machine.encoding = Some(encoding);
+        assert!(matches!(machine.run(100), StepOutcome::Stopped(_)));
+        assert_eq!(machine.output, "A");
+    }
+
+    #[test]
+    fn org_gaps_keep_memory_and_execution_starts_after_them() {
+        let program = assemble("ORG 16\nLDM #65\nOUT\nEND", 0).unwrap();
+        let mut machine = Machine::default();
+        machine.memory[0][3] = MemoryData::Value(7);
+        machine.load_program(&program);
+        machine.pc = program.entry_address();
+        assert_eq!(machine.memory[0][3], MemoryData::Value(7));
+
+        assert!(matches!(
+            machine.run(100),
+            StepOutcome::Stopped(ExecutionInfo::ExecutionTerminated { ins_address: 18 })
+        ));
+        assert_eq!(machine.output, "A");
+    }
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+
+/// How numbers are shown and typed in the app.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NumberFormat {
+    #[default]
+    Hexadecimal,
+    Decimal,
+    /// Decimal, reading 16-bit values as two's complement.
+    SignedDecimal,
+    Binary,
+}
+
+impl NumberFormat {
+    pub const ALL: [NumberFormat; 4] = [
+        NumberFormat::Hexadecimal,
+        NumberFormat::Decimal,
+        NumberFormat::SignedDecimal,
+        NumberFormat::Binary,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NumberFormat::Hexadecimal => "hex",
+            NumberFormat::Decimal => "dec",
+            NumberFormat::SignedDecimal => "signed",
+            NumberFormat::Binary => "bin",
+        }
+    }
+
+    /// Formats a 16-bit value, e.g. `002A`, `42`, `-1` or `B0000 0000 0010 1010`.
+    pub fn format(&self, value: u16) -> String {
+        match self {
+            NumberFormat::Hexadecimal => format!("{:04X}", value),
+            NumberFormat::Decimal => value.to_string(),
+            NumberFormat::SignedDecimal => (value as i16).to_string(),
+            NumberFormat::Binary => format_binary(value, 16),
+        }
+    }
+
+    /// Formats a memory address, which is always positive and fits in 8 bits.
+    pub fn format_address(&self, address: u16) -> String {
+        match self {
+            NumberFormat::Hexadecimal => format!("{:02X}", address),
+            NumberFormat::Decimal | NumberFormat::SignedDecimal => address.to_string(),
+            NumberFormat::Binary => format_binary(address, 8),
+        }
+    }
+
+    /// Parses a number typed in this format.
+    ///
+    /// Spaces and underscores are ignored, and a number in another base can be typed with the
+    /// prefix used in source code: `&` for hexadecimal, `B` for binary (except in hexadecimal,
+    /// where `B` is a digit) or `#` for decimal. Negative numbers are stored as two's complement.
+    pub fn parse(&self, s: &str) -> Option<u16> {
+        let s: String = s
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '_')
+            .collect();
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(s) => (true, s),
+            None => (false, s.as_str()),
+        };
+        let (radix, digits) = if let Some(hex) = s.strip_prefix('&') {
+            (16, hex)
+        } else if let Some(binary) = s
+            .strip_prefix(['B', 'b'])
+            .filter(|_| *self != NumberFormat::Hexadecimal)
+        {
+            (2, binary)
+        } else if let Some(decimal) = s.strip_prefix('#') {
+            (10, decimal)
+        } else {
+            match self {
+                NumberFormat::Hexadecimal => (16, s),
+                NumberFormat::Decimal | NumberFormat::SignedDecimal => (10, s),
+                NumberFormat::Binary => (2, s),
+            }
+        };
+        let value = u16::from_str_radix(digits, radix).ok()?;
+        if negative {
+            (value <= 0x8000).then(|| value.wrapping_neg())
+        } else {
+            Some(value)
+        }
+    }
+}
+
+/// Formats the low `bits` bits of `value` in binary, grouped into nibbles.
+fn format_binary(value: u16, bits: usize) -> String {
+    let digits = format!("{:0width$b}", value, width = bits);
+    let nibbles: Vec<&str> = (0..digits.len())
+        .step_by(4)
+        .map(|i| &digits[i..(i + 4).min(digits.len())])
+        .collect();
+    format!("B{}", nibbles.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_binary_in_nibbles() {
+        assert_eq!(NumberFormat::Binary.format(42), "B0000 0000 0010 1010");
+        assert_eq!(NumberFormat::Binary.format_address(5), "B0000 0101");
+    }
+
+    #[test]
+    fn formats_values_from_8000_as_negative() {
+        let signed = NumberFormat::SignedDecimal;
+        assert_eq!(signed.format(0x7FFF), "32767");
+        assert_eq!(signed.format(0x8000), "-32768");
+        assert_eq!(signed.format(0xFFFF), "-1");
+        assert_eq!(NumberFormat::Decimal.format(0xFFFF), "65535");
+        assert_eq!(NumberFormat::Hexadecimal.format(42), "002A");
+    }
+
+    #[test]
+    fn parses_source_code_prefixes() {
+        for format in NumberFormat::ALL {
+            assert_eq!(format.parse("&2a"), Some(42), "{:?}", format);
+            assert_eq!(format.parse("#42"), Some(42), "{:?}", format);
+        }
+        assert_eq!(NumberFormat::Decimal.parse("B10 1010"), Some(42));
+        assert_eq!(NumberFormat::Binary.parse("0010_1010"), Some(42));
+        // `B` is a hexadecimal digit
+        assert_eq!(NumberFormat::Hexadecimal.parse("B1"), Some(0xB1));
+    }
+
+    #[test]
+    fn parses_negative_numbers_as_twos_complement() {
+        assert_eq!(NumberFormat::SignedDecimal.parse("-1"), Some(0xFFFF));
+        assert_eq!(NumberFormat::Decimal.parse("-32768"), Some(0x8000));
+        assert_eq!(NumberFormat::Hexadecimal.parse("-&10"), Some(0xFFF0));
+    }
+
+    #[test]
+    fn rejects_numbers_out_of_range() {
+        assert_eq!(NumberFormat::Decimal.parse("65536"), None);
+        assert_eq!(NumberFormat::SignedDecimal.parse("-32769"), None);
+        assert_eq!(NumberFormat::Hexadecimal.parse("1FFFF"), None);
+        assert_eq!(NumberFormat::Binary.parse("2"), None);
+        assert_eq!(NumberFormat::Decimal.parse(""), None);
+    }
+}
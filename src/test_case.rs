@@ -0,0 +1,210 @@
+use std::ops::ControlFlow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assembler::Program, number_format::NumberFormat, trace::format_instruction, ExecutionInfo,
+    Machine, MemoryData, StepOutcome,
+};
+
+/// What a program is expected to do when given some input.
+///
+/// Expectations that are `None` (or, for memory, not listed) are not checked.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    /// Characters fed to `IN` instructions, one per instruction.
+    pub input: String,
+    pub expected_output: Option<String>,
+    pub expected_acc: Option<u16>,
+    pub expected_ix: Option<u16>,
+    /// `(address, value)` pairs that memory must contain when the program ends.
+    pub expected_memory: Vec<(u16, u16)>,
+    pub max_steps: u64,
+}
+
+impl Default for TestCase {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            input: String::new(),
+            expected_output: None,
+            expected_acc: None,
+            expected_ix: None,
+            expected_memory: Vec::new(),
+            max_steps: 1000,
+        }
+    }
+}
+
+/// A difference between what a test case expects and what the program did.
+pub struct Mismatch {
+    /// What was compared, e.g. `ACC` or `Address 1E`.
+    pub what: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+pub struct TestResult {
+    pub mismatches: Vec<Mismatch>,
+    /// Number of instructions executed.
+    pub steps: u64,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl TestCase {
+    /// Runs `program` on an empty machine and compares the final state with the expectations.
+    pub fn run(&self, program: &Program) -> TestResult {
+        let mut machine = Machine::default();
+        machine.load_program(program);
+        machine.pc = program.entry_address();
+
+        let (outcome, steps) =
+            machine.run_with_input(&mut self.input.chars(), self.max_steps, |_, _| {
+                ControlFlow::Continue(())
+            });
+        let stop = match outcome {
+            StepOutcome::Continue => unreachable!("nothing pauses the run"),
+            StepOutcome::AwaitingInput => {
+                "IN requested more input than the test case gives".to_owned()
+            }
+            StepOutcome::Stopped(ExecutionInfo::ExecutionTerminated { .. }) => String::new(),
+            StepOutcome::Stopped(info) => info.to_string(),
+        };
+
+        let mut mismatches = Vec::new();
+        if !stop.is_empty() {
+            mismatches.push(Mismatch {
+                what: "Execution".to_owned(),
+                expected: "reaches END".to_owned(),
+                actual: stop,
+            });
+        }
+        if let Some(expected) = &self.expected_output {
+            if *expected != machine.output {
+                mismatches.push(Mismatch {
+                    what: "Output".to_owned(),
+                    expected: format!("{:?}", expected),
+                    actual: format!("{:?}", machine.output),
+                });
+            }
+        }
+        for (what, expected, actual) in [
+            ("ACC", self.expected_acc, machine.acc),
+            ("IX", self.expected_ix, machine.ix),
+        ] {
+            if let Some(expected) = expected.filter(|&expected| expected != actual) {
+                mismatches.push(Mismatch {
+                    what: what.to_owned(),
+                    expected: expected.to_string(),
+                    actual: actual.to_string(),
+                });
+            }
+        }
+        for &(address, expected) in &self.expected_memory {
+            let actual = match machine.memory.as_flattened().get(address as usize) {
+                Some(MemoryData::Value(v)) if *v == expected => continue,
+                Some(MemoryData::Value(v)) => v.to_string(),
+                Some(MemoryData::Instruction(opcode, operand)) => {
+                    format!(
+                        "instruction {}",
+                        format_instruction(*opcode, *operand, NumberFormat::Decimal)
+                    )
+                }
+                None => "not in memory".to_owned(),
+            };
+            mismatches.push(Mismatch {
+                what: format!("Address {:02X}", address),
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+        TestResult { mismatches, steps }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    const ECHO: &str = "IN\nOUT\nSTO last\nEND\nlast: #0";
+
+    fn summary(result: &TestResult) -> Vec<(&str, &str, &str)> {
+        result
+            .mismatches
+            .iter()
+            .map(|m| (m.what.as_str(), m.expected.as_str(), m.actual.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn passes_when_every_expectation_holds() {
+        let case = TestCase {
+            input: "A".to_owned(),
+            expected_output: Some("A".to_owned()),
+            expected_acc: Some(65),
+            expected_memory: vec![(4, 65)],
+            ..TestCase::default()
+        };
+        let result = case.run(&assemble(ECHO, 0).unwrap());
+        assert!(result.passed());
+        assert_eq!(result.steps, 4);
+    }
+
+    #[test]
+    fn lists_each_difference() {
+        let case = TestCase {
+            input: "B".to_owned(),
+            expected_output: Some("A".to_owned()),
+            expected_acc: Some(65),
+            expected_ix: Some(0),
+            expected_memory: vec![(4, 65), (0, 1)],
+            ..TestCase::default()
+        };
+        let result = case.run(&assemble(ECHO, 0).unwrap());
+        assert!(!result.passed());
+        assert_eq!(
+            summary(&result),
+            [
+                ("Output", "\"A\"", "\"B\""),
+                ("ACC", "65", "66"),
+                ("Address 04", "65", "66"),
+                ("Address 00", "1", "instruction IN"),
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_when_input_runs_out() {
+        let result = TestCase::default().run(&assemble(ECHO, 0).unwrap());
+        assert_eq!(
+            summary(&result),
+            [(
+                "Execution",
+                "reaches END",
+                "IN requested more input than the test case gives"
+            )]
+        );
+    }
+
+    #[test]
+    fn gives_up_after_max_steps() {
+        let case = TestCase {
+            max_steps: 5,
+            ..TestCase::default()
+        };
+        let result = case.run(&assemble("loop: JMP loop", 0).unwrap());
+        assert_eq!(result.steps, 5);
+        assert_eq!(result.mismatches.len(), 1);
+        assert_eq!(
+            result.mismatches[0].actual,
+            ExecutionInfo::TooManySteps { steps: 5 }.to_string()
+        );
+    }
+}
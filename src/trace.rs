@@ -0,0 +1,384 @@
+use std::ops::ControlFlow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assembler::Program, number_format::NumberFormat, Machine, MemoryData, Opcode, Operand, Register,
+};
+
+/// A column of a trace table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TraceColumn {
+    /// Address of the executed instruction.
+    Address,
+    /// The executed instruction, e.g. `LDD 30`.
+    Instruction,
+    Acc,
+    Ix,
+    /// The value stored in a memory address.
+    Memory(u16),
+    /// Characters printed by `OUT`.
+    Output,
+}
+
+impl TraceColumn {
+    pub fn header(&self, format: NumberFormat) -> String {
+        match self {
+            TraceColumn::Address => "Instruction address".to_owned(),
+            TraceColumn::Instruction => "Instruction".to_owned(),
+            TraceColumn::Acc => "ACC".to_owned(),
+            TraceColumn::Ix => "IX".to_owned(),
+            TraceColumn::Memory(address) => format.format_address(*address),
+            TraceColumn::Output => "OUTPUT".to_owned(),
+        }
+    }
+
+    /// The columns of a typical CAIE trace table, before memory addresses are added.
+    pub fn defaults() -> Vec<TraceColumn> {
+        vec![
+            TraceColumn::Address,
+            TraceColumn::Acc,
+            TraceColumn::Ix,
+            TraceColumn::Output,
+        ]
+    }
+}
+
+/// One executed instruction and the state it left behind.
+#[derive(Clone, Debug)]
+pub struct TraceRow {
+    /// Number of steps executed before this one, counting steps that recorded no row.
+    pub step: usize,
+    pub address: u16,
+    pub instruction: (Opcode, Operand),
+    pub acc: u16,
+    pub ix: u16,
+    pub output: String,
+}
+
+impl TraceRow {
+    fn writes_acc(&self) -> bool {
+        matches!(
+            self.instruction,
+            (
+                Opcode::Ldm | Opcode::Ldd | Opcode::Ldi | Opcode::Ldx | Opcode::In,
+                _
+            ) | (
+                Opcode::Add | Opcode::Sub | Opcode::And | Opcode::Or | Opcode::Xor,
+                _
+            ) | (Opcode::Lsl | Opcode::Lsr, _)
+                | (Opcode::Inc | Opcode::Dec, Operand::Register(Register::Acc))
+        )
+    }
+
+    fn writes_ix(&self) -> bool {
+        matches!(
+            self.instruction,
+            (Opcode::Ldr, _)
+                | (Opcode::Mov, Operand::Register(Register::Ix))
+                | (Opcode::Inc | Opcode::Dec, Operand::Register(Register::Ix))
+        )
+    }
+
+    fn written_address(&self) -> Option<u16> {
+        match self.instruction {
+            (Opcode::Sto, Operand::Address(a)) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+/// A trace table: the initial memory followed by one row per executed instruction.
+///
+/// Following exam convention, a register or memory cell only has a value in the rows where an
+/// instruction changes it. Assigning the value a cell last showed leaves it empty.
+#[derive(Clone, Default)]
+pub struct Trace {
+    initial_memory: Vec<MemoryData>,
+    output_len: usize,
+    pub rows: Vec<TraceRow>,
+}
+
+impl Trace {
+    /// Starts a trace from the current state of `machine`.
+    pub fn new(machine: &Machine) -> Self {
+        Self {
+            initial_memory: machine.memory.as_flattened().to_vec(),
+            output_len: machine.output.len(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Loads `program` into an empty machine and traces it until it stops, runs out of `input`
+    /// or has executed `max_steps` instructions.
+    pub fn run(program: &Program, input: &str, max_steps: usize) -> Self {
+        let mut machine = Machine::default();
+        machine.load_program(program);
+        machine.pc = program.entry_address();
+        let mut trace = Trace::new(&machine);
+        // Only the last step can fail to fetch an instruction, so every step before it has a row
+        machine.run_with_input(&mut input.chars(), max_steps as u64, |machine, address| {
+            trace.record(trace.len(), address, machine);
+            ControlFlow::Continue(())
+        });
+        trace
+    }
+
+    /// Adds a row for the instruction at `address`, which `machine` has just executed.
+    pub fn record(&mut self, step: usize, address: u16, machine: &Machine) {
+        self.rows.push(TraceRow {
+            step,
+            address,
+            instruction: machine.cir,
+            acc: machine.acc,
+            ix: machine.ix,
+            output: machine
+                .output
+                .get(self.output_len..)
+                .unwrap_or("")
+                .to_owned(),
+        });
+        self.output_len = machine.output.len();
+    }
+
+    /// Updates the last row after an `IN` instruction has been given its character.
+    pub fn input(&mut self, machine: &Machine) {
+        if let Some(row) = self.rows.last_mut() {
+            row.acc = machine.acc;
+        }
+    }
+
+    /// Keeps only the rows of the first `steps` steps, e.g. after stepping back to `machine`.
+    pub fn truncate(&mut self, steps: usize, machine: &Machine) {
+        self.rows.retain(|row| row.step < steps);
+        self.output_len = machine.output.len();
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Renders the table as text cells, starting with a row of initial memory values.
+    ///
+    /// Empty cells are empty strings.
+    pub fn cells(&self, columns: &[TraceColumn], format: NumberFormat) -> Vec<Vec<String>> {
+        let initial = |column: &TraceColumn| match column {
+            TraceColumn::Memory(address) => self.initial_memory.get(*address as usize).copied(),
+            _ => None,
+        };
+        let mut cells = vec![columns
+            .iter()
+            .map(|column| {
+                initial(column).map_or_else(String::new, |data| format_memory(&data, format))
+            })
+            .collect()];
+        // The value each register or memory column last showed
+        let mut shown: Vec<Option<u16>> = columns
+            .iter()
+            .map(|column| match initial(column) {
+                Some(MemoryData::Value(v)) => Some(v),
+                _ => None,
+            })
+            .collect();
+        for row in &self.rows {
+            let cells_of_row = columns.iter().zip(&mut shown).map(|(column, shown)| {
+                let written = match column {
+                    TraceColumn::Address => return format.format_address(row.address),
+                    TraceColumn::Instruction => {
+                        return format_instruction(row.instruction.0, row.instruction.1, format)
+                    }
+                    TraceColumn::Output => return row.output.clone(),
+                    TraceColumn::Acc => row.writes_acc().then_some(row.acc),
+                    TraceColumn::Ix => row.writes_ix().then_some(row.ix),
+                    TraceColumn::Memory(address) => {
+                        (row.written_address() == Some(*address)).then_some(row.acc)
+                    }
+                };
+                match written {
+                    Some(value) if *shown != Some(value) => {
+                        *shown = Some(value);
+                        format.format(value)
+                    }
+                    _ => String::new(),
+                }
+            });
+            cells.push(cells_of_row.collect());
+        }
+        cells
+    }
+
+    /// Renders the table as comma-separated values, with a header line.
+    pub fn to_csv(&self, columns: &[TraceColumn], format: NumberFormat) -> String {
+        let escape = |cell: &str| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.to_owned()
+            }
+        };
+        let header = columns.iter().map(|column| column.header(format));
+        let mut csv = header
+            .map(|cell| escape(&cell))
+            .collect::<Vec<_>>()
+            .join(",");
+        csv.push('\n');
+        for row in self.cells(columns, format) {
+            csv += &row
+                .iter()
+                .map(|cell| escape(cell))
+                .collect::<Vec<_>>()
+                .join(",");
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// Renders the table as a Markdown table.
+    pub fn to_markdown(&self, columns: &[TraceColumn], format: NumberFormat) -> String {
+        let escape = |cell: &str| {
+            cell.replace('\\', "\\\\")
+                .replace('|', "\\|")
+                .replace('\n', "\\n")
+        };
+        let line = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+        let mut markdown = line(columns.iter().map(|c| escape(&c.header(format))).collect());
+        markdown += &line(columns.iter().map(|_| "---".to_owned()).collect());
+        for row in self.cells(columns, format) {
+            markdown += &line(row.iter().map(|cell| escape(cell)).collect());
+        }
+        markdown
+    }
+}
+
+fn format_memory(data: &MemoryData, format: NumberFormat) -> String {
+    match data {
+        MemoryData::Value(v) => format.format(*v),
+        MemoryData::Instruction(opcode, operand) => format_instruction(*opcode, *operand, format),
+    }
+}
+
+/// Formats an instruction the way it is written in source code, e.g. `LDM #5`.
+///
+/// Immediate values are written with the prefix of `format`'s base. Addresses are written in
+/// `format`, which may not be valid source code.
+pub fn format_instruction(opcode: Opcode, operand: Operand, format: NumberFormat) -> String {
+    match operand {
+        Operand::Empty => opcode.to_string(),
+        Operand::Register(r) => format!("{} {}", opcode, r),
+        Operand::Address(a) => format!("{} {}", opcode, format.format_address(a)),
+        Operand::Immediate(v) => format!("{} {}", opcode, format_immediate(v, format)),
+    }
+}
+
+/// Formats an immediate value as it is written in source code, e.g. `#5`, `&1F` or `B101`.
+pub(crate) fn format_immediate(value: u16, format: NumberFormat) -> String {
+    match format {
+        NumberFormat::Hexadecimal => format!("&{:X}", value),
+        NumberFormat::Binary => format!("B{:b}", value),
+        NumberFormat::Decimal | NumberFormat::SignedDecimal => format!("#{}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::assemble, encoding::Encoding};
+
+    const HELLO: &str = "loop: LDX string
+      OUT
+      INC IX
+      LDD count
+      DEC ACC
+      STO count
+      CMP #0
+      JPN loop
+      END
+count: #2
+string: &48
+      &69";
+
+    #[test]
+    fn encoded_programs_are_traced_like_symbolic_ones() {
+        let mut program = assemble(HELLO, 0).unwrap();
+        let symbolic = Trace::run(&program, "", 1000);
+        program.encode(&Encoding::default()).unwrap();
+        let encoded = Trace::run(&program, "", 1000);
+
+        assert_eq!(symbolic.len(), 17);
+        let rows = |trace: &Trace| -> Vec<_> {
+            trace
+                .rows
+                .iter()
+                .map(|row| (row.step, row.address, row.instruction, row.acc, row.ix))
+                .collect()
+        };
+        assert_eq!(rows(&encoded), rows(&symbolic));
+        assert_eq!(encoded.rows.last().unwrap().instruction.0, Opcode::End);
+    }
+
+    #[test]
+    fn values_that_stop_execution_get_no_row() {
+        let program = assemble("LDM #1\n#5", 0).unwrap();
+        let trace = Trace::run(&program, "", 1000);
+        assert_eq!(trace.len(), 1);
+    }
+
+    #[test]
+    fn values_are_only_shown_when_they_change() {
+        let program =
+            assemble("LDM #5\nLDM #5\nSTO x\nLDM #6\nSTO x\nSTO x\nEND\nx: #5", 0).unwrap();
+        let trace = Trace::run(&program, "", 1000);
+        let columns = [TraceColumn::Acc, TraceColumn::Memory(7)];
+        assert_eq!(
+            trace.cells(&columns, NumberFormat::Decimal),
+            [
+                ["", "5"],
+                ["5", ""],
+                ["", ""],
+                ["", ""],
+                ["6", ""],
+                ["", "6"],
+                ["", ""],
+                ["", ""],
+            ]
+        );
+    }
+
+    #[test]
+    fn exports_csv() {
+        let trace = Trace::run(&assemble("LDM #44\nOUT\nEND", 0).unwrap(), "", 1000);
+        let columns = [
+            TraceColumn::Address,
+            TraceColumn::Instruction,
+            TraceColumn::Acc,
+            TraceColumn::Output,
+        ];
+        assert_eq!(
+            trace.to_csv(&columns, NumberFormat::Decimal),
+            "Instruction address,Instruction,ACC,OUTPUT\n\
+             ,,,\n\
+             0,LDM #44,44,\n\
+             1,OUT,,\",\"\n\
+             2,END,,\n"
+        );
+    }
+
+    #[test]
+    fn exports_markdown() {
+        let trace = Trace::run(&assemble("LDM #124\nOUT\nEND", 0).unwrap(), "", 1000);
+        let columns = [TraceColumn::Address, TraceColumn::Acc, TraceColumn::Output];
+        assert_eq!(
+            trace.to_markdown(&columns, NumberFormat::Hexadecimal),
+            "| Instruction address | ACC | OUTPUT |\n\
+             | --- | --- | --- |\n\
+             |  |  |  |\n\
+             | 00 | 007C |  |\n\
+             | 01 |  | \\| |\n\
+             | 02 |  |  |\n"
+        );
+    }
+}
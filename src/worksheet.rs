@@ -0,0 +1,300 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    number_format::NumberFormat,
+    trace::{format_instruction, Trace, TraceColumn, TraceRow},
+    Opcode, Operand,
+};
+
+/// A trace table filled in by hand, to be checked against the real execution.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Worksheet {
+    pub columns: Vec<TraceColumn>,
+    /// Characters fed to `IN` instructions when working out the expected table.
+    pub input: String,
+    /// The cells written by the student, one row per executed instruction, indexed like `columns`.
+    pub answers: Vec<Vec<String>>,
+}
+
+/// The result of checking a [`Worksheet`].
+pub struct WorksheetMarks {
+    /// `Some(true)` for a correct cell, `Some(false)` for a wrong one, and `None` for a cell that
+    /// is correctly left empty. Indexed like [`Worksheet::answers`].
+    pub cells: Vec<Vec<Option<bool>>>,
+    /// Number of instructions actually executed.
+    pub expected_rows: usize,
+    /// An explanation of the first mistake, if there is one.
+    pub first_mistake: Option<String>,
+}
+
+impl Worksheet {
+    pub fn new(columns: Vec<TraceColumn>, input: String) -> Self {
+        Self {
+            columns,
+            input,
+            answers: Vec::new(),
+        }
+    }
+
+    pub fn add_row(&mut self) {
+        self.answers.push(vec![String::new(); self.columns.len()]);
+    }
+
+    /// Marks every cell against `expected`, a trace of the program the worksheet is about.
+    pub fn check(&self, expected: &Trace, format: NumberFormat) -> WorksheetMarks {
+        // The first row of cells holds the initial memory values, which are given
+        let expected_cells = &expected.cells(&self.columns, format)[1..];
+        let cells: Vec<Vec<Option<bool>>> = self
+            .answers
+            .iter()
+            .enumerate()
+            .map(|(row, answers)| {
+                answers
+                    .iter()
+                    .enumerate()
+                    .map(|(column, answer)| {
+                        // Rows past the end of the trace are expected to be empty, but cells
+                        // past the last column are never right
+                        let expected = match expected_cells.get(row) {
+                            Some(cells) => match cells.get(column) {
+                                Some(cell) => cell.as_str(),
+                                None => return Some(false),
+                            },
+                            None => "",
+                        };
+                        if expected.trim().is_empty() && answer.trim().is_empty() {
+                            None
+                        } else {
+                            Some(cells_match(answer, expected, format))
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let first_mistake = cells
+            .iter()
+            .enumerate()
+            .find_map(|(row, marks)| {
+                let column = marks.iter().position(|&mark| mark == Some(false))?;
+                Some(self.explain(expected, row, column, format))
+            })
+            .or_else(|| {
+                (self.answers.len() < expected.len()).then(|| {
+                    let row = &expected.rows[self.answers.len()];
+                    format!(
+                        "The table is not finished: the program executes {} instructions, but only {} rows are filled in. Row {} is {}.",
+                        expected.len(),
+                        self.answers.len(),
+                        self.answers.len() + 1,
+                        describe_row(row, format)
+                    )
+                })
+            });
+        WorksheetMarks {
+            cells,
+            expected_rows: expected.len(),
+            first_mistake,
+        }
+    }
+
+    /// Explains why the cell at `row` and `column` of the answers is wrong.
+    fn explain(&self, expected: &Trace, row: usize, column: usize, format: NumberFormat) -> String {
+        let given = self.answers[row][column].trim();
+        let Some(header) = self.columns.get(column).map(|c| c.header(format)) else {
+            return format!("Row {} has more cells than the table has columns.", row + 1);
+        };
+        let Some(expected_row) = expected.rows.get(row) else {
+            return format!(
+                "Row {} should not be there: the program stops after {} instructions.",
+                row + 1,
+                expected.len()
+            );
+        };
+        let expected_cell = expected.cells(&self.columns, format)[row + 1][column].clone();
+        let mut explanation = format!("Row {}, {}: ", row + 1, header);
+        explanation += &if expected_cell.is_empty() {
+            format!("you wrote {}, but the cell should be empty. ", given)
+        } else if given.is_empty() {
+            format!("the cell should be {}. ", expected_cell)
+        } else {
+            format!("you wrote {}, but it should be {}. ", given, expected_cell)
+        };
+
+        if self.columns[column] == TraceColumn::Address {
+            explanation += &match row.checked_sub(1).and_then(|i| expected.rows.get(i)) {
+                Some(previous) => format!(
+                    "The previous instruction is {}: {}",
+                    format_instruction(previous.instruction.0, previous.instruction.1, format),
+                    describe(previous, format)
+                ),
+                None => "Execution starts at the address the program is loaded to.".to_owned(),
+            };
+        } else {
+            explanation += &format!(
+                "This row executes {}. {}",
+                describe_row(expected_row, format),
+                describe(expected_row, format)
+            );
+            if expected_cell.is_empty() {
+                explanation +=
+                    &format!(" It does not change {}, so the cell is left empty.", header);
+            }
+        }
+        explanation
+    }
+}
+
+/// Compares a student's cell with the expected one, ignoring case, spacing and leading zeros.
+fn cells_match(given: &str, expected: &str, format: NumberFormat) -> bool {
+    let (given, expected) = (given.trim(), expected.trim());
+    match (format.parse(given), format.parse(expected)) {
+        (Some(given), Some(expected)) => given == expected,
+        _ => {
+            let normalize = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+            normalize(given).eq_ignore_ascii_case(&normalize(expected))
+        }
+    }
+}
+
+fn describe_row(row: &TraceRow, format: NumberFormat) -> String {
+    format!(
+        "{} at address {}",
+        format_instruction(row.instruction.0, row.instruction.1, format),
+        format.format_address(row.address)
+    )
+}
+
+/// Describes what an executed instruction does, in words.
+fn describe(row: &TraceRow, format: NumberFormat) -> String {
+    let n = |a: u16| format.format_address(a);
+    match row.instruction {
+        (Opcode::Ldm, Operand::Immediate(v)) => {
+            format!("LDM loads the number {} into the ACC.", format.format(v))
+        }
+        (Opcode::Ldd, Operand::Address(a)) => {
+            format!("LDD loads the value stored at address {} into the ACC.", n(a))
+        }
+        (Opcode::Ldi, Operand::Address(a)) => format!(
+            "LDI reads an address from address {}, then loads the value stored there into the ACC.",
+            n(a)
+        ),
+        (Opcode::Ldx, Operand::Address(a)) => format!(
+            "LDX loads the value stored at address {} + IX = {} into the ACC.",
+            n(a),
+            n(a.saturating_add(row.ix))
+        ),
+        (Opcode::Ldr, Operand::Immediate(v)) => {
+            format!("LDR loads the number {} into the IX.", format.format(v))
+        }
+        (Opcode::Mov, _) => "MOV copies the contents of the ACC into the IX.".to_owned(),
+        (Opcode::Sto, Operand::Address(a)) => {
+            format!("STO stores the contents of the ACC at address {}.", n(a))
+        }
+        (Opcode::Add, operand) => {
+            format!("ADD adds {} to the ACC.", describe_operand(operand, format))
+        }
+        (Opcode::Sub, operand) => {
+            format!("SUB subtracts {} from the ACC.", describe_operand(operand, format))
+        }
+        (Opcode::Inc, register) => format!("INC adds 1 to the {}.", register),
+        (Opcode::Dec, register) => format!("DEC subtracts 1 from the {}.", register),
+        (Opcode::Jmp, Operand::Address(a)) => format!("JMP jumps to address {}.", n(a)),
+        (Opcode::Cmp, operand) => format!(
+            "CMP compares the ACC with {}. It only sets the flags and does not change the ACC.",
+            describe_operand(operand, format)
+        ),
+        (Opcode::Cmi, Operand::Address(a)) => format!(
+            "CMI compares the ACC with the value at the address stored in address {}. It does not change the ACC.",
+            n(a)
+        ),
+        (Opcode::Jpe, Operand::Address(a)) => format!(
+            "JPE jumps to address {} if the last comparison was equal, otherwise execution continues with the next instruction.",
+            n(a)
+        ),
+        (Opcode::Jpn, Operand::Address(a)) => format!(
+            "JPN jumps to address {} if the last comparison was not equal, otherwise execution continues with the next instruction.",
+            n(a)
+        ),
+        (Opcode::In, _) => "IN reads a character from the input and stores its ASCII value in the ACC.".to_owned(),
+        (Opcode::Out, _) => "OUT outputs the character whose ASCII value is in the ACC.".to_owned(),
+        (Opcode::End, _) => "END stops the program.".to_owned(),
+        (Opcode::And | Opcode::Or | Opcode::Xor, operand) => format!(
+            "{} combines the ACC bit by bit with {}, and stores the result in the ACC.",
+            row.instruction.0,
+            describe_operand(operand, format)
+        ),
+        (Opcode::Lsl, operand) => format!(
+            "LSL shifts the bits in the ACC left by {} places.",
+            describe_operand(operand, format)
+        ),
+        (Opcode::Lsr, operand) => format!(
+            "LSR shifts the bits in the ACC right by {} places.",
+            describe_operand(operand, format)
+        ),
+        _ => format!(
+            "{} is not a valid instruction.",
+            format_instruction(row.instruction.0, row.instruction.1, format)
+        ),
+    }
+}
+
+fn describe_operand(operand: Operand, format: NumberFormat) -> String {
+    match operand {
+        Operand::Immediate(v) => format!("the number {}", format.format(v)),
+        Operand::Address(a) => format!("the value stored at address {}", format.format_address(a)),
+        Operand::Register(r) => format!("the {}", r),
+        Operand::Empty => "nothing".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn worksheet(answers: &[&[&str]]) -> Worksheet {
+        Worksheet {
+            columns: vec![TraceColumn::Address, TraceColumn::Acc],
+            input: String::new(),
+            answers: answers
+                .iter()
+                .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+                .collect(),
+        }
+    }
+
+    fn trace() -> Trace {
+        Trace::run(&assemble("LDM #5\nEND", 0).unwrap(), "", 100)
+    }
+
+    #[test]
+    fn marks_each_cell() {
+        let marks = worksheet(&[&["0", "5"], &["1", "7"]]).check(&trace(), NumberFormat::Decimal);
+        assert_eq!(
+            marks.cells,
+            [vec![Some(true), Some(true)], vec![Some(true), Some(false)]]
+        );
+        assert_eq!(marks.expected_rows, 2);
+        assert!(marks.first_mistake.unwrap().starts_with("Row 2, ACC:"));
+    }
+
+    #[test]
+    fn cells_past_the_last_column_are_wrong() {
+        let marks = worksheet(&[&["0", "5", "extra"]]).check(&trace(), NumberFormat::Decimal);
+        assert_eq!(marks.cells, [vec![Some(true), Some(true), Some(false)]]);
+        assert_eq!(
+            marks.first_mistake.unwrap(),
+            "Row 1 has more cells than the table has columns."
+        );
+    }
+
+    #[test]
+    fn immediates_are_described_as_values() {
+        let row = &trace().rows[0];
+        assert_eq!(
+            describe(row, NumberFormat::Binary),
+            "LDM loads the number B0000 0000 0000 0101 into the ACC."
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! Runs the command-line runner on source files and checks its output and exit status.
+
+use std::{path::PathBuf, process::Command};
+
+struct Output {
+    code: i32,
+    stdout: String,
+    stderr: String,
+}
+
+/// Writes `source` to a file named `name` and runs `caie-asm` with `args`, where `{}` stands for
+/// the file's path.
+fn caie_asm(name: &str, source: &str, args: &[&str]) -> Output {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, source).unwrap();
+    let args = args.iter().map(|&arg| {
+        if arg == "{}" {
+            path.to_str().unwrap()
+        } else {
+            arg
+        }
+    });
+    let output = Command::new(env!("CARGO_BIN_EXE_caie-asm"))
+        .args(args)
+        .output()
+        .unwrap();
+    Output {
+        code: output.status.code().unwrap(),
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        stderr: String::from_utf8(output.stderr).unwrap(),
+    }
+}
+
+#[test]
+fn runs_a_program_to_end() {
+    let output = caie_asm("hello.asm", "LDM #65\nOUT\nEND\n", &["run", "{}"]);
+    assert_eq!(output.code, 0);
+    assert!(
+        output.stdout.starts_with("A\n\nPC   03\n"),
+        "{}",
+        output.stdout
+    );
+    assert!(output.stdout.contains("ACC  0041\n"));
+    assert!(output.stdout.contains("Instructions executed: 3\n"));
+}
+
+#[test]
+fn feeds_input_to_in() {
+    let source = "IN\nOUT\nEND\n";
+    let output = caie_asm("echo.asm", source, &["run", "{}", "--input", "x"]);
+    assert_eq!(output.code, 0);
+    assert!(output.stdout.starts_with("x\n"));
+
+    let output = caie_asm("echo-without-input.asm", source, &["run", "{}"]);
+    assert_eq!(output.code, 3);
+    assert!(
+        output.stderr.contains("--input has run out"),
+        "{}",
+        output.stderr
+    );
+}
+
+#[test]
+fn usage_and_io_errors_exit_with_1() {
+    let output = caie_asm("usage.asm", "END\n", &[]);
+    assert_eq!(output.code, 1);
+    assert!(output.stderr.contains("Usage: caie-asm run"));
+
+    assert_eq!(
+        caie_asm("option.asm", "END\n", &["run", "{}", "--fast"]).code,
+        1
+    );
+
+    let output = caie_asm("missing.asm", "END\n", &["run", "missing/file.asm"]);
+    assert_eq!(output.code, 1);
+    assert!(output.stderr.contains("cannot read missing/file.asm"));
+}
+
+#[test]
+fn assembler_errors_exit_with_2() {
+    let output = caie_asm("error.asm", "END\nFOO 3\n", &["run", "{}"]);
+    assert_eq!(output.code, 2);
+    assert!(
+        output
+            .stderr
+            .contains("error.asm:2:1: error: unknown opcode on line 2: FOO"),
+        "{}",
+        output.stderr
+    );
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn faults_and_step_limits_exit_with_3() {
+    let output = caie_asm("fault.asm", "JMP data\ndata: #7\n", &["run", "{}"]);
+    assert_eq!(output.code, 3);
+    assert!(output.stdout.contains("Instructions executed: 2\n"));
+
+    let source = "loop: JMP loop\n";
+    let output = caie_asm("loop.asm", source, &["run", "{}", "--max-steps", "5"]);
+    assert_eq!(output.code, 3);
+    assert!(output.stdout.contains("Instructions executed: 5\n"));
+}
+
+#[test]
+fn lint_warnings_exit_with_4() {
+    let output = caie_asm("no-end.asm", "LDM #1\nOUT\n", &["lint", "{}"]);
+    assert_eq!(output.code, 4);
+    assert!(
+        output
+            .stderr
+            .contains("no-end.asm:2:1: warning: missing END"),
+        "{}",
+        output.stderr
+    );
+
+    assert_eq!(
+        caie_asm("clean.asm", "LDM #1\nOUT\nEND\n", &["lint", "{}"]).code,
+        0
+    );
+}
+
+#[test]
+fn unformatted_sources_fail_fmt_check_with_4() {
+    let output = caie_asm("messy.asm", "ldm #1\n  END\n", &["fmt", "{}"]);
+    assert_eq!(output.code, 0);
+    assert_eq!(output.stdout, "    LDM #1\n    END\n");
+
+    let output = caie_asm(
+        "messy-check.asm",
+        "ldm #1\n  END\n",
+        &["fmt", "{}", "--check"],
+    );
+    assert_eq!(output.code, 4);
+    assert!(output.stderr.contains("is not formatted"));
+
+    let tidy = "    LDM #1\n    END\n";
+    assert_eq!(
+        caie_asm("tidy.asm", tidy, &["fmt", "{}", "--check"]).code,
+        0
+    );
+}
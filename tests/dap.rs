@@ -0,0 +1,156 @@
+//! Drives the debug adapter over stdio, as an editor would.
+
+use std::{
+    io::BufReader,
+    path::PathBuf,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use eframe_template::protocol::{read_message, write_message};
+use serde_json::{json, Value};
+
+struct Client {
+    adapter: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    seq: u64,
+    /// Text of the `stdout` output events received so far.
+    stdout: String,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut adapter = Command::new(env!("CARGO_BIN_EXE_caie-asm-dap"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let input = adapter.stdin.take().unwrap();
+        let output = BufReader::new(adapter.stdout.take().unwrap());
+        Self {
+            adapter,
+            input,
+            output,
+            seq: 0,
+            stdout: String::new(),
+        }
+    }
+
+    fn receive(&mut self) -> Value {
+        let message = read_message(&mut self.output).unwrap().unwrap();
+        if message["event"] == "output" && message["body"]["category"] == "stdout" {
+            self.stdout += message["body"]["output"].as_str().unwrap();
+        }
+        message
+    }
+
+    /// Sends a request and returns its response. Events sent before the response are skipped.
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        self.seq += 1;
+        let request = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        });
+        write_message(&mut self.input, &request).unwrap();
+        loop {
+            let message = self.receive();
+            if message["type"] == "response" {
+                assert_eq!(message["request_seq"], self.seq);
+                assert_eq!(message["success"], true, "{}", message);
+                return message["body"].clone();
+            }
+        }
+    }
+
+    /// Skips messages until the event `name`, and returns its body.
+    fn event(&mut self, name: &str) -> Value {
+        loop {
+            let message = self.receive();
+            if message["event"] == name {
+                return message["body"].clone();
+            }
+        }
+    }
+
+    fn line(&mut self) -> (Value, Value) {
+        let trace = self.request("stackTrace", json!({ "threadId": 1 }));
+        let frame = &trace["stackFrames"][0];
+        (frame["line"].clone(), frame["column"].clone())
+    }
+
+    fn disconnect(mut self) {
+        self.request("disconnect", json!({}));
+        assert!(self.adapter.wait().unwrap().success());
+    }
+}
+
+fn program(name: &str, source: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, source).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn stops_at_a_breakpoint_on_the_first_instruction() {
+    let path = program("first.asm", "LDM #65\nOUT\nEND\n");
+    let mut client = Client::start();
+    client.request("initialize", json!({ "adapterID": "caie-asm" }));
+    client.request("launch", json!({ "program": path }));
+    client.event("initialized");
+    let breakpoints = client.request(
+        "setBreakpoints",
+        json!({ "source": { "path": path }, "breakpoints": [{ "line": 1 }] }),
+    );
+    assert_eq!(breakpoints["breakpoints"][0]["line"], 1);
+    assert_eq!(breakpoints["breakpoints"][0]["verified"], true);
+
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event("stopped")["reason"], "breakpoint");
+    assert_eq!(client.line(), (json!(1), json!(1)));
+
+    client.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(client.event("exited")["exitCode"], 0);
+    client.event("terminated");
+    assert_eq!(client.stdout, "A");
+    client.disconnect();
+}
+
+#[test]
+fn reads_input_from_the_debug_console_with_zero_based_lines() {
+    let path = program("echo.asm", "IN\nOUT\nEND\n");
+    let mut client = Client::start();
+    client.request(
+        "initialize",
+        json!({ "adapterID": "caie-asm", "linesStartAt1": false, "columnsStartAt1": false }),
+    );
+    client.request("launch", json!({ "program": path, "stopOnEntry": true }));
+    client.event("initialized");
+    let breakpoints = client.request(
+        "setBreakpoints",
+        json!({ "source": { "path": path }, "breakpoints": [{ "line": 1 }] }),
+    );
+    assert_eq!(breakpoints["breakpoints"][0]["line"], 1);
+
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event("stopped")["reason"], "entry");
+    assert_eq!(client.line(), (json!(0), json!(0)));
+
+    client.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["description"], "Waiting for input");
+    let result = client.request("evaluate", json!({ "expression": "x", "context": "repl" }));
+    assert!(result["result"].as_str().unwrap().contains("'x'"));
+    let registers = client.request("variables", json!({ "variablesReference": 1 }));
+    assert_eq!(registers["variables"][1]["name"], "ACC");
+    assert_eq!(registers["variables"][1]["value"], "0078");
+
+    // The breakpoint on OUT is hit once, and not again when continuing from it
+    client.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["reason"], "breakpoint");
+    assert_eq!(client.line(), (json!(1), json!(0)));
+    client.request("continue", json!({ "threadId": 1 }));
+    client.event("terminated");
+    assert_eq!(client.stdout, "x");
+    client.disconnect();
+}
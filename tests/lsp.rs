@@ -0,0 +1,121 @@
+//! Drives the language server over stdio, as an editor would.
+
+use std::{
+    io::BufReader,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use eframe_template::{
+    format::format_source,
+    protocol::{read_message, write_message},
+};
+use serde_json::{json, Value};
+
+const URI: &str = "file:///program.asm";
+const SOURCE: &str = "start: LDD count\n OUT\n JMP start\n LDM 5\ncount: #5\n";
+
+struct Client {
+    server: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    id: u64,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_caie-asm-lsp"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let input = server.stdin.take().unwrap();
+        let output = BufReader::new(server.stdout.take().unwrap());
+        Self {
+            server,
+            input,
+            output,
+            id: 0,
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        write_message(&mut self.input, &message).unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        read_message(&mut self.output).unwrap().unwrap()
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.id += 1;
+        let message =
+            json!({ "jsonrpc": "2.0", "id": self.id, "method": method, "params": params });
+        write_message(&mut self.input, &message).unwrap();
+        let response = self.receive();
+        assert_eq!(response["id"], self.id);
+        response["result"].clone()
+    }
+}
+
+fn position(line: u64, character: u64) -> Value {
+    json!({
+        "textDocument": { "uri": URI },
+        "position": { "line": line, "character": character },
+    })
+}
+
+#[test]
+fn serves_an_editor_session() {
+    let mut client = Client::start();
+    let result = client.request("initialize", json!({ "capabilities": {} }));
+    assert_eq!(result["capabilities"]["hoverProvider"], true);
+    client.notify("initialized", json!({}));
+
+    client.notify(
+        "textDocument/didOpen",
+        json!({
+            "textDocument": { "uri": URI, "languageId": "caie-asm", "version": 1, "text": SOURCE },
+        }),
+    );
+    let published = client.receive();
+    assert_eq!(published["method"], "textDocument/publishDiagnostics");
+    assert_eq!(published["params"]["uri"], URI);
+    let diagnostics = published["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(
+        diagnostics[0]["range"]["start"],
+        json!({ "line": 3, "character": 5 })
+    );
+
+    let hover = client.request("textDocument/hover", position(0, 8));
+    let documentation = hover["contents"]["value"].as_str().unwrap();
+    assert!(documentation.contains("LDD"), "{}", documentation);
+
+    let definition = client.request("textDocument/definition", position(0, 12));
+    assert_eq!(definition["uri"], URI);
+    assert_eq!(
+        definition["range"]["start"],
+        json!({ "line": 4, "character": 0 })
+    );
+
+    let edits = client.request(
+        "textDocument/formatting",
+        json!({ "textDocument": { "uri": URI }, "options": { "tabSize": 4, "insertSpaces": true } }),
+    );
+    assert_eq!(edits.as_array().unwrap().len(), 1);
+    assert_eq!(edits[0]["newText"], format_source(SOURCE));
+
+    assert_eq!(client.request("shutdown", Value::Null), Value::Null);
+    client.notify("exit", Value::Null);
+    assert!(client.server.wait().unwrap().success());
+}
+
+#[test]
+fn exit_without_shutdown_fails() {
+    let mut client = Client::start();
+    client.request("initialize", json!({ "capabilities": {} }));
+    client.notify("exit", Value::Null);
+    assert!(!client.server.wait().unwrap().success());
+}